@@ -0,0 +1,2 @@
+DROP INDEX "accounts_fingerprint";
+ALTER TABLE "accounts" DROP COLUMN "fingerprint";
@@ -0,0 +1,3 @@
+-- Filled in once the secrets are readable, see `Account::load`
+ALTER TABLE "accounts" ADD COLUMN "fingerprint" TEXT NULL;
+CREATE INDEX "accounts_fingerprint" ON "accounts" ("fingerprint");
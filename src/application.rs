@@ -1,6 +1,11 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use adw::prelude::*;
+use anyhow::Context;
 use futures_util::StreamExt;
 use gettextrs::gettext;
 use gtk::{
@@ -11,15 +16,26 @@ use gtk::{
 use search_provider::ResultMeta;
 
 use crate::{
+    backup::auto,
     config,
     models::{
-        keyring, start as start_search_provider, Account, OTPUri, Provider, ProvidersModel,
-        SearchProviderAction, FAVICONS_PATH, RUNTIME, SECRET_SERVICE, SETTINGS,
+        clipboard, database, i18n, keyring, start as start_search_provider, start_codes_service,
+        start_session_monitor, Account, CodesAccount, CodesAction, CodesCaller, OTPUri, Provider,
+        ProvidersModel, SearchProviderAction, SessionEvent, SystemClock, Ticker, FAVICONS_PATH,
+        RUNTIME, SETTINGS,
     },
-    utils::{spawn, spawn_tokio_blocking},
-    widgets::{PreferencesWindow, ProvidersDialog, Window},
+    utils::{spawn, spawn_tokio, spawn_tokio_blocking},
+    widgets::{PreferencesWindow, ProvidersDialog, View, Window},
 };
 
+/// The seconds to wait after a change before backing up the accounts.
+const AUTO_BACKUP_CHANGE_DELAY: u32 = 10;
+/// The seconds to wait before retrying a failed scheduled backup.
+const AUTO_BACKUP_RETRY_DELAY: u32 = 60 * 60;
+/// The seconds the answer given to the unidentified callers of the codes
+/// service is kept for.
+const UNKNOWN_CODES_CALLER_DURATION: u64 = 30;
+
 mod imp {
     use std::cell::{Cell, RefCell};
 
@@ -34,11 +50,27 @@ mod imp {
     pub struct Application {
         pub window: RefCell<Option<glib::WeakRef<Window>>>,
         pub model: ProvidersModel,
+        /// Drives the time based codes while they are shown.
+        pub ticker: Ticker,
         #[property(get, set, construct)]
         pub is_locked: Cell<bool>,
         pub lock_timeout_id: RefCell<Option<glib::SourceId>>,
         #[property(get, set, construct)]
         pub can_be_locked: Cell<bool>,
+        pub auto_backup_timeout_id: RefCell<Option<glib::SourceId>>,
+        /// Whether an automatic backup is due once the application is
+        /// unlocked.
+        pub auto_backup_pending: Cell<bool>,
+        pub database_monitor: RefCell<Option<gio::FileMonitor>>,
+        /// Locks the application once the code copied from a search result
+        /// leaves the clipboard.
+        pub clipboard_lock: RefCell<Option<(gtk::gdk::Clipboard, glib::SignalHandlerId)>>,
+        /// Serves the `Codes` D-Bus interface.
+        pub codes_connection: RefCell<Option<zbus::Connection>>,
+        /// The callers of the codes service the user is being asked about.
+        pub codes_prompts: RefCell<HashSet<CodesCaller>>,
+        /// The answer given to the unidentified callers & when.
+        pub unknown_codes_access: Cell<Option<(bool, Instant)>>,
     }
 
     // Sets up the basics for the GObject
@@ -150,6 +182,12 @@ mod imp {
                 }
             });
 
+            SystemClock::set_offset(SETTINGS.time_offset() as i64);
+            SETTINGS.connect_time_offset_changed(clone!(@weak app => move |offset| {
+                SystemClock::set_offset(offset as i64);
+                app.imp().model.regenerate_time_based_otps();
+            }));
+
             SETTINGS.connect_changed(
                 None,
                 clone!(@weak app => move |settings, key| {
@@ -162,14 +200,37 @@ mod imp {
                             }
                         },
                         "auto-lock-timeout" => app.restart_lock_timeout(),
+                        "auto-backup" | "auto-backup-directory" | "auto-backup-interval" => {
+                            app.restart_auto_backup_timeout()
+                        }
                         _ => ()
                     }
                 }),
             );
 
+            app.connect_is_locked_notify(|app| {
+                if app.is_locked() {
+                    app.stop_clipboard_lock();
+                    app.imp().unknown_codes_access.take();
+                    keyring::lock_vault();
+                }
+                app.update_ticker();
+                // The model is loaded right after unlocking
+                if !app.is_locked() && app.imp().auto_backup_pending.get() {
+                    app.schedule_auto_backup(AUTO_BACKUP_CHANGE_DELAY);
+                }
+            });
+            app.setup_auto_backup();
+
             spawn(clone!(@strong app => async move {
                 app.start_search_provider().await;
             }));
+            spawn(clone!(@strong app => async move {
+                app.start_codes_service().await;
+            }));
+            spawn(clone!(@strong app => async move {
+                app.start_session_monitor().await;
+            }));
         }
 
         fn activate(&self) {
@@ -181,8 +242,9 @@ mod imp {
             }
 
             let window = Window::new(&self.model, &app);
-            window.present();
             self.window.replace(Some(window.downgrade()));
+            window.connect_visible_notify(clone!(@weak app => move |_| app.update_ticker()));
+            window.present();
 
             app.set_accels_for_action("app.quit", &["<primary>q"]);
             app.set_accels_for_action("app.lock", &["<primary>l"]);
@@ -261,16 +323,9 @@ impl Application {
             }
         }
 
-        RUNTIME.block_on(async {
-            let keyring = oo7::Keyring::new()
-                .await
-                .expect("Failed to start the keyring service");
-            keyring
-                .unlock()
-                .await
-                .expect("Failed to unlock the default collection");
-            SECRET_SERVICE.set(keyring).unwrap()
-        });
+        RUNTIME
+            .block_on(keyring::init())
+            .expect("Failed to initialize the keyring");
 
         let has_set_password =
             spawn_tokio_blocking(async { keyring::has_set_password().await.unwrap_or(false) });
@@ -299,6 +354,23 @@ impl Application {
             .unwrap()
     }
 
+    /// Ticks only while the codes can be seen, i.e. the window is shown and
+    /// the application unlocked.
+    fn update_ticker(&self) {
+        let imp = self.imp();
+        let is_visible = imp
+            .window
+            .borrow()
+            .as_ref()
+            .and_then(|window| window.upgrade())
+            .is_some_and(|window| window.is_visible());
+        if is_visible && !self.is_locked() {
+            imp.ticker.start(&imp.model);
+        } else {
+            imp.ticker.stop();
+        }
+    }
+
     /// Starts or restarts the lock timeout.
     pub fn restart_lock_timeout(&self) {
         let imp = self.imp();
@@ -329,6 +401,113 @@ impl Application {
         }
     }
 
+    /// Watch the database to back up the accounts when they change.
+    fn setup_auto_backup(&self) {
+        let file = gio::File::for_path(database::path());
+        match file.monitor_file(gio::FileMonitorFlags::NONE, gio::Cancellable::NONE) {
+            Ok(monitor) => {
+                monitor.connect_changed(clone!(@weak self as app => move |_, _, _, event| {
+                    if event == gio::FileMonitorEvent::Changed && SETTINGS.auto_backup_on_change() {
+                        // Wait for the changes to settle, e.g. during a restore
+                        app.schedule_auto_backup(AUTO_BACKUP_CHANGE_DELAY);
+                    }
+                }));
+                self.imp().database_monitor.replace(Some(monitor));
+            }
+            Err(err) => tracing::error!("Failed to watch the database for changes {err}"),
+        }
+        self.restart_auto_backup_timeout();
+    }
+
+    /// Schedules the next automatic backup according to the interval since
+    /// the last one.
+    fn restart_auto_backup_timeout(&self) {
+        self.cancel_auto_backup_timeout();
+        let interval = SETTINGS.auto_backup_interval() as i64 * 60 * 60;
+        if !SETTINGS.auto_backup() || interval == 0 {
+            return;
+        }
+        let now = glib::real_time() / 1_000_000;
+        let delay = SETTINGS.auto_backup_last() + interval - now;
+        // Leave some time to finish setting up the automatic backups
+        let delay = delay.clamp(AUTO_BACKUP_CHANGE_DELAY as i64, u32::MAX as i64);
+        self.schedule_auto_backup(delay as u32);
+    }
+
+    fn schedule_auto_backup(&self, delay: u32) {
+        if !SETTINGS.auto_backup() {
+            return;
+        }
+        self.cancel_auto_backup_timeout();
+        let id = glib::timeout_add_seconds_local(
+            delay,
+            clone!(@weak self as app => @default-return glib::ControlFlow::Break, move || {
+                app.imp().auto_backup_timeout_id.take();
+                app.auto_backup();
+                glib::ControlFlow::Break
+            }),
+        );
+        self.imp().auto_backup_timeout_id.replace(Some(id));
+    }
+
+    fn cancel_auto_backup_timeout(&self) {
+        if let Some(id) = self.imp().auto_backup_timeout_id.take() {
+            id.remove();
+        }
+    }
+
+    fn auto_backup(&self) {
+        let imp = self.imp();
+        // Never replace the previous backups with an empty one
+        if self.is_locked() || !imp.model.is_loaded() {
+            imp.auto_backup_pending.set(true);
+            return;
+        }
+        imp.auto_backup_pending.set(false);
+
+        spawn(clone!(@weak self as app => async move {
+            match app.write_auto_backup().await {
+                Ok(path) => {
+                    tracing::info!("Automatic backup written into {}", path.display());
+                    let now = glib::real_time() / 1_000_000;
+                    if let Err(err) = SETTINGS.set_auto_backup_last(now) {
+                        tracing::error!("Failed to save the last automatic backup time {err}");
+                    }
+                    app.restart_auto_backup_timeout();
+                }
+                Err(err) => {
+                    tracing::error!("Failed to write an automatic backup {err:?}");
+                    let notification = gio::Notification::new(&gettext("Automatic backup failed"));
+                    notification.set_body(Some(&gettext(
+                        "Check the folder and the passphrase of the automatic backups in the preferences",
+                    )));
+                    app.send_notification(Some("auto-backup-failed"), &notification);
+                    if SETTINGS.auto_backup_interval() != 0 {
+                        app.schedule_auto_backup(AUTO_BACKUP_RETRY_DELAY);
+                    }
+                }
+            }
+        }));
+    }
+
+    async fn write_auto_backup(&self) -> anyhow::Result<PathBuf> {
+        let format = auto::Format::find(&SETTINGS.auto_backup_format())
+            .context("Unknown automatic backups format")?;
+        let directory = SETTINGS.auto_backup_directory();
+        anyhow::ensure!(!directory.is_empty(), "No automatic backups folder was set");
+        let password = spawn_tokio(keyring::backup_password())
+            .await?
+            .filter(|password| !password.is_empty())
+            .context("No automatic backups passphrase was set")?;
+        auto::backup_into(
+            &self.imp().model,
+            &format,
+            &password,
+            Path::new(&directory),
+            SETTINGS.auto_backup_keep() as usize,
+        )
+    }
+
     fn account_provider_by_identifier(&self, id: &str) -> Option<(Provider, Account)> {
         let identifier = id.split(':').collect::<Vec<&str>>();
         let provider_id = identifier.first()?.parse::<u32>().ok()?;
@@ -340,6 +519,36 @@ impl Application {
         Some((provider, account))
     }
 
+    /// Lock the application once the code copied from a search result is
+    /// cleared or replaced in the clipboard, if it is automatically locked.
+    fn lock_on_clipboard_change(&self, window: &Window, code: String) {
+        self.stop_clipboard_lock();
+        if !SETTINGS.boolean("auto-lock") || !self.can_be_locked() {
+            return;
+        }
+        let clipboard = window.clipboard();
+        let handler_id = clipboard.connect_changed(clone!(@weak self as app => move |clipboard| {
+            let code = code.clone();
+            spawn(clone!(@weak app, @weak clipboard => async move {
+                // The clipboard might only be told now about the copied code
+                let text = clipboard.read_text_future().await.ok().flatten();
+                if text.as_deref() != Some(code.as_str()) {
+                    app.stop_clipboard_lock();
+                    app.set_is_locked(true);
+                }
+            }));
+        }));
+        self.imp()
+            .clipboard_lock
+            .replace(Some((clipboard, handler_id)));
+    }
+
+    fn stop_clipboard_lock(&self) {
+        if let Some((clipboard, handler_id)) = self.imp().clipboard_lock.take() {
+            clipboard.disconnect(handler_id);
+        }
+    }
+
     async fn start_search_provider(&self) {
         let mut receiver = match start_search_provider().await {
             Err(err) => {
@@ -358,16 +567,32 @@ impl Application {
                     window.imp().search_btn.set_active(true);
                     window.present_with_time(timestamp);
                 }
-                SearchProviderAction::ActivateResult => {
-                    let notification = gio::Notification::new(&gettext("One-Time password copied"));
-                    notification.set_body(Some(&gettext("Password was copied successfully")));
-                    self.send_notification(None, &notification);
+                SearchProviderAction::ActivateResult(id, timestamp) => {
+                    self.activate();
+                    let window = self.active_window();
+                    let result = self
+                        .account_provider_by_identifier(&id)
+                        .filter(|_| !self.is_locked());
+                    // The unlock screen is shown instead if the application was locked since
+                    if let Some((_, account)) = result {
+                        window.set_view(View::Account(account.clone()));
+                        // GNOME Shell copies the code when activating a result
+                        let toast = adw::Toast::new(&gettext("One-Time password copied"));
+                        toast.set_timeout(3);
+                        window.add_toast(toast);
+                        let code = account.code().replace(' ', "");
+                        clipboard::clear_later(&window.clipboard(), &code);
+                        self.lock_on_clipboard_change(&window, code);
+                    }
+                    window.present_with_time(timestamp);
                 }
                 SearchProviderAction::InitialResultSet(terms, sender) => {
                     // don't show any results if the application is locked
                     let response = if self.is_locked() {
                         vec![]
                     } else {
+                        // Searching counts as using the application
+                        self.restart_lock_timeout();
                         self.imp()
                             .model
                             .find_accounts(&terms)
@@ -378,6 +603,8 @@ impl Application {
                     sender.send(response).unwrap();
                 }
                 SearchProviderAction::ResultMetas(identifiers, sender) => {
+                    // The codes are not kept up to date while the window is hidden
+                    self.imp().model.tick();
                     let metas = identifiers
                         .iter()
                         .filter_map(|id| {
@@ -386,6 +613,7 @@ impl Application {
                                     ResultMeta::builder(id.to_owned(), &account.name())
                                         .description(&provider.name())
                                         .clipboard_text(&account.code().replace(' ', ""))
+                                        .gicon(&result_icon(&provider))
                                         .build()
                                 })
                         })
@@ -395,4 +623,222 @@ impl Application {
             }
         }
     }
+
+    async fn start_session_monitor(&self) {
+        let mut receiver = match spawn_tokio(start_session_monitor()).await {
+            Err(err) => {
+                tracing::error!("Failed to start the session monitor {err}");
+                return;
+            }
+            Ok(receiver) => receiver,
+        };
+        while let Some(event) = receiver.next().await {
+            let lock = match event {
+                SessionEvent::ScreenLocked => SETTINGS.lock_on_screen_lock(),
+                SessionEvent::Suspending => SETTINGS.lock_on_suspend(),
+                SessionEvent::Deactivated => SETTINGS.lock_on_user_switch(),
+            };
+            if lock && self.can_be_locked() && !self.is_locked() {
+                tracing::info!("Locking the application, {event:?}");
+                self.set_is_locked(true);
+            }
+        }
+    }
+
+    async fn start_codes_service(&self) {
+        let (connection, mut receiver) = match spawn_tokio(start_codes_service()).await {
+            Err(err) => {
+                tracing::error!("Failed to start the codes service {err}");
+                return;
+            }
+            Ok(service) => service,
+        };
+        self.imp().codes_connection.replace(Some(connection));
+        while let Some(action) = receiver.next().await {
+            // Handled concurrently, so that a caller waiting for the user's answer
+            // doesn't block the others
+            spawn(clone!(@weak self as app => async move {
+                app.handle_codes_action(action).await;
+            }));
+        }
+    }
+
+    async fn handle_codes_action(&self, action: CodesAction) {
+        match action {
+            CodesAction::ListAccounts(caller, sender) => {
+                let response = self
+                    .authorize_codes_access(&caller)
+                    .await
+                    .map(|()| self.codes_accounts());
+                let _ = sender.send(response);
+            }
+            CodesAction::Code(caller, id, sender) => {
+                let response = self
+                    .authorize_codes_access(&caller)
+                    .await
+                    .and_then(|()| self.codes_account(&id))
+                    .map(|(provider, account)| {
+                        account.generate_otp();
+                        let seconds_left = if provider.method().is_time_based() {
+                            provider.seconds_left() as u32
+                        } else {
+                            0
+                        };
+                        (account.code().replace(' ', ""), seconds_left)
+                    });
+                let _ = sender.send(response);
+            }
+            CodesAction::IncrementCounter(caller, id, sender) => {
+                let response = self
+                    .authorize_codes_access(&caller)
+                    .await
+                    .and_then(|()| self.codes_account(&id))
+                    .and_then(|(provider, account)| {
+                        if !provider.method().is_event_based() {
+                            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                                "{id} is not a counter based account"
+                            )));
+                        }
+                        account.increment_counter().map_err(|err| {
+                            zbus::fdo::Error::Failed(format!(
+                                "Failed to increment the counter {err}"
+                            ))
+                        })?;
+                        account.generate_otp();
+                        Ok(account.code().replace(' ', ""))
+                    });
+                let _ = sender.send(response);
+            }
+        }
+    }
+
+    /// Whether `caller` may access the codes, asking the user the first time.
+    /// Its requests are denied while the user is being asked.
+    ///
+    /// The unidentified callers can't be told apart, the answer given to them
+    /// is only kept for a short while.
+    async fn authorize_codes_access(&self, caller: &CodesCaller) -> zbus::fdo::Result<()> {
+        let imp = self.imp();
+        if self.is_locked() || !imp.model.is_loaded() {
+            return Err(zbus::fdo::Error::AccessDenied(
+                "The application is locked".to_owned(),
+            ));
+        }
+        let remembered = match caller {
+            CodesCaller::App(app_id) => SETTINGS.codes_access().get(app_id).copied(),
+            CodesCaller::Unknown => imp
+                .unknown_codes_access
+                .get()
+                .filter(|(_, answered)| {
+                    answered.elapsed() < Duration::from_secs(UNKNOWN_CODES_CALLER_DURATION)
+                })
+                .map(|(allowed, _)| allowed),
+        };
+        let allowed = match remembered {
+            Some(allowed) => allowed,
+            None => {
+                if !imp.codes_prompts.borrow_mut().insert(caller.clone()) {
+                    return Err(zbus::fdo::Error::AccessDenied(
+                        "The access is already being requested".to_owned(),
+                    ));
+                }
+                let answer = self.ask_codes_access(caller).await;
+                imp.codes_prompts.borrow_mut().remove(caller);
+                match caller {
+                    CodesCaller::App(app_id) => {
+                        if let Some(allowed) = answer {
+                            if let Err(err) = SETTINGS.set_codes_access(app_id, allowed) {
+                                tracing::error!(
+                                    "Failed to remember the codes access of {app_id} {err}"
+                                );
+                            }
+                        }
+                    }
+                    // Dismissing counts as denying, not to be asked right away again
+                    CodesCaller::Unknown => imp
+                        .unknown_codes_access
+                        .set(Some((answer.unwrap_or(false), Instant::now()))),
+                }
+                let Some(allowed) = answer else {
+                    return Err(zbus::fdo::Error::AccessDenied(
+                        "The request was dismissed".to_owned(),
+                    ));
+                };
+                allowed
+            }
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(zbus::fdo::Error::AccessDenied(
+                "Not allowed to access the codes".to_owned(),
+            ))
+        }
+    }
+
+    /// Returns `None` if the dialog was dismissed.
+    async fn ask_codes_access(&self, caller: &CodesCaller) -> Option<bool> {
+        self.activate();
+        let window = self.active_window();
+        let body = match caller {
+            CodesCaller::App(app_id) => i18n::i18n_f(
+                "“{}” wants to read the one-time codes of your accounts. The access can be revoked from the preferences.",
+                &[app_id.as_str()],
+            ),
+            CodesCaller::Unknown => gettext(
+                "An application that could not be identified wants to read the one-time codes of your accounts. The answer also applies to the other unidentified applications for a short while.",
+            ),
+        };
+        let dialog = adw::MessageDialog::builder()
+            .heading(gettext("Allow Access to Your Codes?"))
+            .body(body)
+            .default_response("deny")
+            .close_response("cancel")
+            .modal(true)
+            .transient_for(&window)
+            .build();
+        dialog.add_responses(&[("deny", &gettext("_Deny")), ("allow", &gettext("_Allow"))]);
+        dialog.set_response_appearance("allow", adw::ResponseAppearance::Suggested);
+        match dialog.choose_future().await.as_str() {
+            "allow" => Some(true),
+            "deny" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn codes_accounts(&self) -> Vec<CodesAccount> {
+        let model = &self.imp().model;
+        (0..model.n_items())
+            .filter_map(|i| model.item(i).and_downcast::<Provider>())
+            .flat_map(|provider| {
+                let accounts = provider.accounts_model();
+                (0..accounts.n_items())
+                    .filter_map(|j| accounts.item(j).and_downcast::<Account>())
+                    .map(|account| {
+                        (
+                            format!("{}:{}", provider.id(), account.id()),
+                            provider.name(),
+                            account.name(),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn codes_account(&self, id: &str) -> zbus::fdo::Result<(Provider, Account)> {
+        self.account_provider_by_identifier(id)
+            .ok_or_else(|| zbus::fdo::Error::InvalidArgs(format!("Unknown account {id}")))
+    }
+}
+
+/// The favicon of the provider if it was downloaded, the application icon
+/// otherwise, as a serialized `GIcon`, which can be a path or an icon name.
+fn result_icon(provider: &Provider) -> String {
+    provider
+        .image_uri()
+        .map(|uri| FAVICONS_PATH.join(format!("{uri}_96x96")))
+        .filter(|path| path.exists())
+        .and_then(|path| path.to_str().map(ToOwned::to_owned))
+        .unwrap_or_else(|| config::APP_ID.to_owned())
 }
@@ -14,13 +14,12 @@
 use aes_gcm::{aead::Aead, KeyInit};
 use anyhow::{Context, Result};
 use gettextrs::gettext;
-use gtk::prelude::*;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
-use super::{Backupable, Restorable, RestorableItem};
-use crate::models::{Account, Algorithm, Method, Provider, ProvidersModel};
+use super::{Backupable, Restorable, RestorableItem, Selection};
+use crate::models::{Account, Algorithm, Method, ProvidersModel};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -302,6 +301,7 @@ impl Item {
 
         let mut detail = Detail {
             secret: account.otp().secret(),
+            pin: account.otp().pin(),
             algorithm: provider.algorithm(),
             digits: provider.digits(),
             period: None,
@@ -342,6 +342,8 @@ impl Item {
 #[derive(Debug, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct Detail {
     pub secret: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
     #[serde(rename = "algo")]
     #[zeroize(skip)]
     pub algorithm: Algorithm,
@@ -369,6 +371,10 @@ impl RestorableItem for Item {
         self.info.secret.clone()
     }
 
+    fn pin(&self) -> Option<String> {
+        self.info.pin.clone()
+    }
+
     fn period(&self) -> Option<u32> {
         self.info.period
     }
@@ -403,16 +409,12 @@ impl Backupable for Aegis {
         gettext("Into a JSON file containing plain-text or encrypted fields")
     }
 
-    fn backup(model: &ProvidersModel, key: Option<&str>) -> Result<Vec<u8>> {
+    fn backup(model: &ProvidersModel, selection: &Selection, key: Option<&str>) -> Result<Vec<u8>> {
         // Create structure
         let mut aegis_root = Aegis::default();
 
-        for i in 0..model.n_items() {
-            let provider = model.item(i).and_downcast::<Provider>().unwrap();
-            let accounts = provider.accounts_model();
-
-            for j in 0..accounts.n_items() {
-                let account = accounts.item(j).and_downcast::<Account>().unwrap();
+        for (_provider, accounts) in selection.accounts(model) {
+            for account in accounts {
                 let otp_item = Item::new(&account);
                 aegis_root.add_item(otp_item);
             }
@@ -661,6 +663,26 @@ mod tests {
         assert_eq!(items[2].digits(), Some(5));
         assert_eq!(items[2].counter(), None);
         assert_eq!(items[2].method(), Method::Steam);
+
+        assert_eq!(items[3].account(), "Alice");
+        assert_eq!(items[3].issuer(), "Yandex");
+        assert_eq!(
+            items[3].secret(),
+            "6SB2IKNM6OBZPAVBVTOHDKS4FAAAAAAADFUTQMBTRY"
+        );
+        assert_eq!(items[3].pin(), Some("5239".to_owned()));
+        assert_eq!(items[3].period(), Some(30));
+        assert_eq!(items[3].digits(), Some(8));
+        assert_eq!(items[3].method(), Method::Yandex);
+
+        assert_eq!(items[4].account(), "Charlie");
+        assert_eq!(items[4].issuer(), "Legacy VPN");
+        assert_eq!(items[4].secret(), "4MKSV7XGEWM4Q");
+        assert_eq!(items[4].pin(), Some("1234".to_owned()));
+        assert_eq!(items[4].algorithm(), Algorithm::MD5);
+        assert_eq!(items[4].period(), Some(10));
+        assert_eq!(items[4].digits(), Some(6));
+        assert_eq!(items[4].method(), Method::MOTP);
     }
 
     #[test]
@@ -1,11 +1,14 @@
-use anyhow::Result;
+use aes_gcm::{aead::Aead, KeyInit};
+use anyhow::{Context, Result};
 use gettextrs::gettext;
-use gtk::prelude::*;
+use rand::{Rng, RngCore};
 use serde::{Deserialize, Serialize};
-use zeroize::{Zeroize, ZeroizeOnDrop};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-use super::{Backupable, Restorable, RestorableItem};
-use crate::models::{Account, Algorithm, Method, Provider, ProvidersModel};
+use super::{Backupable, Restorable, RestorableItem, Selection};
+use crate::models::{Algorithm, Method, ProvidersModel};
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
@@ -36,6 +39,88 @@ pub struct AndOTP {
     pub period: Option<u32>,
 }
 
+/// The encrypted variants of the backups
+///
+/// The current one is made of the PBKDF2 iterations, the salt, the IV and the
+/// AES-256-GCM encrypted JSON, the key being derived from the password using
+/// PBKDF2-SHA1. The older one lacks the iterations and the salt, the key being
+/// the SHA-256 of the password.
+impl AndOTP {
+    const INT_LENGTH: usize = 4;
+    const SALT_LENGTH: usize = 12;
+    const IV_LENGTH: usize = 12;
+    const KEY_LENGTH: usize = 32;
+    // andOTP picks a random number of iterations within that range
+    const MIN_ITERATIONS: u32 = 140_000;
+    const MAX_ITERATIONS: u32 = 160_000;
+    // Avoids spending ages deriving a key from what is in fact the IV of the
+    // older variant
+    const ITERATIONS_LIMIT: u32 = 10_000_000;
+
+    fn encrypt(data: &[u8], password: &str) -> Result<Vec<u8>> {
+        let mut rng = rand::thread_rng();
+        let iterations = rng.gen_range(Self::MIN_ITERATIONS..=Self::MAX_ITERATIONS);
+        let mut salt = [0u8; Self::SALT_LENGTH];
+        rng.fill_bytes(&mut salt);
+        let mut iv = [0u8; Self::IV_LENGTH];
+        rng.fill_bytes(&mut iv);
+
+        let key = Self::derive_key(password, &salt, iterations);
+        let cipher = aes_gcm::Aes256Gcm::new_from_slice(key.as_ref())?;
+        let ciphertext = cipher
+            .encrypt(aes_gcm::Nonce::from_slice(&iv), data)
+            .map_err(|_| anyhow::anyhow!("Encrypting andOTP backup"))?;
+
+        let mut content = iterations.to_be_bytes().to_vec();
+        content.extend_from_slice(&salt);
+        content.extend_from_slice(&iv);
+        content.extend(ciphertext);
+        Ok(content)
+    }
+
+    fn decrypt(data: &[u8], password: &str) -> Result<Zeroizing<Vec<u8>>> {
+        let header_length = Self::INT_LENGTH + Self::SALT_LENGTH;
+        if data.len() > header_length + Self::IV_LENGTH {
+            let iterations = u32::from_be_bytes(data[..Self::INT_LENGTH].try_into().unwrap());
+            if iterations > 0 && iterations <= Self::ITERATIONS_LIMIT {
+                let salt = &data[Self::INT_LENGTH..header_length];
+                let key = Self::derive_key(password, salt, iterations);
+                if let Ok(content) = Self::decrypt_with_key(key.as_ref(), &data[header_length..]) {
+                    return Ok(content);
+                }
+            }
+        }
+
+        tracing::info!("Failed to decrypt andOTP backup, trying the older format");
+        let key = Zeroizing::new(Sha256::digest(password.as_bytes()).to_vec());
+        Self::decrypt_with_key(&key, data)
+            .context("Failed to decrypt andOTP backup, wrong password?")
+    }
+
+    fn derive_key(
+        password: &str,
+        salt: &[u8],
+        iterations: u32,
+    ) -> Zeroizing<[u8; Self::KEY_LENGTH]> {
+        let mut key = Zeroizing::new([0u8; Self::KEY_LENGTH]);
+        pbkdf2::pbkdf2_hmac::<Sha1>(password.as_bytes(), salt, iterations, key.as_mut());
+        key
+    }
+
+    // `data` is made of the IV followed by the encrypted content
+    fn decrypt_with_key(key: &[u8], data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        if data.len() <= Self::IV_LENGTH {
+            anyhow::bail!("andOTP backup is too short");
+        }
+        let (iv, ciphertext) = data.split_at(Self::IV_LENGTH);
+        let cipher = aes_gcm::Aes256Gcm::new_from_slice(key)?;
+        let content = cipher
+            .decrypt(aes_gcm::Nonce::from_slice(iv), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Decrypting andOTP backup"))?;
+        Ok(Zeroizing::new(content))
+    }
+}
+
 impl RestorableItem for AndOTP {
     fn account(&self) -> String {
         self.label.clone()
@@ -71,7 +156,7 @@ impl RestorableItem for AndOTP {
 }
 
 impl Backupable for AndOTP {
-    const ENCRYPTABLE: bool = false;
+    const ENCRYPTABLE: bool = true;
     const IDENTIFIER: &'static str = "andotp";
 
     fn title() -> String {
@@ -80,19 +165,14 @@ impl Backupable for AndOTP {
     }
 
     fn subtitle() -> String {
-        gettext("Into a plain-text JSON file")
+        gettext("Into a plain-text or encrypted JSON file")
     }
 
-    fn backup(model: &ProvidersModel, _key: Option<&str>) -> Result<Vec<u8>> {
+    fn backup(model: &ProvidersModel, selection: &Selection, key: Option<&str>) -> Result<Vec<u8>> {
         let mut items = Vec::new();
 
-        for i in 0..model.n_items() {
-            let provider = model.item(i).and_downcast::<Provider>().unwrap();
-            let accounts = provider.accounts_model();
-
-            for j in 0..accounts.n_items() {
-                let account = accounts.item(j).and_downcast::<Account>().unwrap();
-
+        for (provider, accounts) in selection.accounts(model) {
+            for account in accounts {
                 let otp_item = AndOTP {
                     secret: account.otp().secret(),
                     issuer: provider.name(),
@@ -112,12 +192,16 @@ impl Backupable for AndOTP {
         }
 
         let content = serde_json::ser::to_string_pretty(&items)?;
-        Ok(content.as_bytes().to_vec())
+        // An empty passphrase means a plain-text backup
+        match key.filter(|key| !key.is_empty()) {
+            Some(password) => Self::encrypt(content.as_bytes(), password),
+            None => Ok(content.as_bytes().to_vec()),
+        }
     }
 }
 
 impl Restorable for AndOTP {
-    const ENCRYPTABLE: bool = false;
+    const ENCRYPTABLE: bool = true;
     const SCANNABLE: bool = false;
     const IDENTIFIER: &'static str = "andotp";
     type Item = Self;
@@ -128,11 +212,16 @@ impl Restorable for AndOTP {
     }
 
     fn subtitle() -> String {
-        gettext("From a plain-text JSON file")
+        gettext("From a plain-text or encrypted JSON file")
     }
 
-    fn restore_from_data(from: &[u8], _key: Option<&str>) -> Result<Vec<Self::Item>> {
-        let items: Vec<AndOTP> = serde_json::de::from_slice(from)?;
+    fn restore_from_data(from: &[u8], key: Option<&str>) -> Result<Vec<Self::Item>> {
+        let items: Vec<AndOTP> = if let Some(password) = key.filter(|key| !key.is_empty()) {
+            let content = Self::decrypt(from, password)?;
+            serde_json::de::from_slice(&content)?
+        } else {
+            serde_json::de::from_slice(from)?
+        };
         Ok(items)
     }
 }
@@ -209,5 +298,51 @@ mod tests {
         assert_eq!(items[6].algorithm(), Algorithm::SHA1);
         assert_eq!(items[6].digits(), Some(5));
         assert_eq!(items[6].counter(), None);
+
+        // No passphrase was entered
+        let items = AndOTP::restore_from_data(data.as_bytes(), Some("")).unwrap();
+        assert_eq!(items.len(), 7);
+        assert_eq!(items[0].account(), "Mason");
+    }
+
+    #[test]
+    fn parse_encrypted() {
+        let data = std::fs::read("./src/backup/tests/andotp_encrypted.json.aes").unwrap();
+        let items = AndOTP::restore_from_data(&data, Some("test")).unwrap();
+
+        assert_eq!(items.len(), 7);
+        assert_eq!(items[0].account(), "Mason");
+        assert_eq!(items[0].issuer(), "Deno");
+        assert_eq!(items[0].secret(), "4SJHB4GSD43FZBAI7C2HLRJGPQ");
+        assert_eq!(items[6].account(), "Sophia");
+        assert_eq!(items[6].method(), Method::Steam);
+
+        assert!(AndOTP::restore_from_data(&data, Some("wrong")).is_err());
+        assert!(AndOTP::restore_from_data(&data, None).is_err());
+    }
+
+    #[test]
+    fn parse_encrypted_old() {
+        let data = std::fs::read("./src/backup/tests/andotp_encrypted_old.json.aes").unwrap();
+        let items = AndOTP::restore_from_data(&data, Some("test")).unwrap();
+
+        assert_eq!(items.len(), 7);
+        assert_eq!(items[1].account(), "James");
+        assert_eq!(items[1].issuer(), "SPDX");
+        assert_eq!(items[1].secret(), "5OM4WOOGPLQEF6UGN3CPEOOLWU");
+        assert_eq!(items[5].counter(), Some(10300));
+
+        assert!(AndOTP::restore_from_data(&data, Some("wrong")).is_err());
+    }
+
+    #[test]
+    fn encrypt() {
+        let data = std::fs::read("./src/backup/tests/andotp_plain.json").unwrap();
+        let encrypted = AndOTP::encrypt(&data, "password").unwrap();
+        let iterations = u32::from_be_bytes(encrypted[..4].try_into().unwrap());
+        assert!((AndOTP::MIN_ITERATIONS..=AndOTP::MAX_ITERATIONS).contains(&iterations));
+
+        let decrypted = AndOTP::decrypt(&encrypted, "password").unwrap();
+        assert_eq!(decrypted.as_slice(), data.as_slice());
     }
 }
@@ -0,0 +1,647 @@
+//! Authenticator Import/Export Module
+//!
+//! The native backup format of Authenticator. A backup is a JSON envelope
+//! containing the format version, the argon2id parameters used to derive the
+//! key from the passphrase and the vault encrypted using AES-256-GCM.
+//!
+//! Unlike the other formats, the vault holds everything stored in the
+//! database: the providers with their settings, website, help URL and image,
+//! and the accounts with their counter. Every provider is exported, including
+//! the ones without accounts, but only the selected accounts.
+
+use std::{path::PathBuf, rc::Rc};
+
+use aes_gcm::{aead::Aead, KeyInit};
+use anyhow::{Context, Result};
+use gettextrs::gettext;
+use gtk::prelude::*;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use super::{restore_item, Backupable, Restorable, RestorableItem, RestoreTransaction, Selection};
+use crate::models::{Account, Algorithm, Method, Provider, ProvidersModel, FAVICONS_PATH};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Authenticator {
+    version: u32,
+    kdf: Kdf,
+    #[serde(with = "hex::serde")]
+    nonce: [u8; 12],
+    /// Base64 encoded encrypted [`Vault`], followed by the authentication tag.
+    vault: String,
+}
+
+impl Authenticator {
+    pub const VERSION: u32 = 1;
+
+    pub fn encrypt(vault: &Vault, password: &str, kdf: Kdf) -> Result<Self> {
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let key = kdf.derive_key(password)?;
+        let cipher = aes_gcm::Aes256Gcm::new_from_slice(&key)?;
+        let plaintext = Zeroizing::new(serde_json::to_vec(vault)?);
+        let ciphertext = cipher
+            .encrypt(aes_gcm::Nonce::from_slice(&nonce), plaintext.as_ref())
+            .map_err(|_| anyhow::anyhow!("Encrypting the vault"))?;
+
+        Ok(Self {
+            version: Self::VERSION,
+            kdf,
+            nonce,
+            vault: data_encoding::BASE64.encode(&ciphertext),
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> Result<Vault> {
+        if self.version > Self::VERSION {
+            anyhow::bail!(
+                "Authenticator backup version expected to be at most {}. Found {} instead.",
+                Self::VERSION,
+                self.version
+            );
+        }
+
+        let ciphertext = data_encoding::BASE64
+            .decode(self.vault.as_bytes())
+            .context("Decoding the vault")?;
+        let key = self.kdf.derive_key(password)?;
+        let cipher = aes_gcm::Aes256Gcm::new_from_slice(&key)?;
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(aes_gcm::Nonce::from_slice(&self.nonce), ciphertext.as_ref())
+                .map_err(|_| anyhow::anyhow!("Failed to decrypt the vault, wrong passphrase?"))?,
+        );
+        serde_json::from_slice(&plaintext).map_err(From::from)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum KdfAlgorithm {
+    Argon2id,
+}
+
+/// Parameters of the key derivation function
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Kdf {
+    algorithm: KdfAlgorithm,
+    #[serde(with = "hex::serde")]
+    salt: [u8; 16],
+    /// Memory cost in KiB
+    memory: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+impl Kdf {
+    /// The largest parameters accepted, they come from the backup and would
+    /// otherwise allow it to exhaust the memory or to never finish.
+    const MAX_MEMORY: u32 = 256 * 1024;
+    const MAX_ITERATIONS: u32 = 16;
+    const MAX_PARALLELISM: u32 = 16;
+
+    fn derive_key(&self, password: &str) -> Result<Zeroizing<Vec<u8>>> {
+        anyhow::ensure!(
+            self.memory <= Self::MAX_MEMORY
+                && self.iterations <= Self::MAX_ITERATIONS
+                && self.parallelism <= Self::MAX_PARALLELISM,
+            "Unsupported key derivation parameters, memory: {} KiB, iterations: {}, parallelism: {}",
+            self.memory,
+            self.iterations,
+            self.parallelism
+        );
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+            mem_cost: self.memory,
+            time_cost: self.iterations,
+            lanes: self.parallelism,
+            hash_length: 32,
+            ..argon2::Config::default()
+        };
+        let key = argon2::hash_raw(password.as_bytes(), &self.salt, &config)?;
+        Ok(Zeroizing::new(key))
+    }
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+
+        // OWASP recommended parameters for argon2id
+        Self {
+            algorithm: KdfAlgorithm::Argon2id,
+            salt,
+            memory: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Decrypted content of the backup
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Vault {
+    pub providers: Vec<ProviderEntry>,
+}
+
+impl Vault {
+    pub fn new(model: &ProvidersModel, selection: &Selection) -> Self {
+        let providers = (0..model.n_items())
+            .map(|i| {
+                let provider = model.item(i).and_downcast::<Provider>().unwrap();
+                ProviderEntry {
+                    details: ProviderDetails::new(&provider),
+                    accounts: selection
+                        .provider_accounts(&provider)
+                        .iter()
+                        .map(AccountEntry::new)
+                        .collect(),
+                }
+            })
+            .collect();
+        Self { providers }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProviderEntry {
+    #[serde(flatten)]
+    pub details: ProviderDetails,
+    pub accounts: Vec<AccountEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProviderDetails {
+    pub name: String,
+    pub website: Option<String>,
+    pub help_url: Option<String>,
+    pub image_uri: Option<String>,
+    pub icon: Option<Icon>,
+    pub method: Method,
+    pub algorithm: Algorithm,
+    pub period: u32,
+    pub digits: u32,
+    pub default_counter: u32,
+}
+
+impl ProviderDetails {
+    fn new(provider: &Provider) -> Self {
+        let image_uri = provider.image_uri();
+        let icon = image_uri.as_deref().and_then(Icon::load);
+
+        Self {
+            name: provider.name(),
+            website: provider.website(),
+            help_url: provider.help_url(),
+            image_uri,
+            icon,
+            method: provider.method(),
+            algorithm: provider.algorithm(),
+            period: provider.period(),
+            digits: provider.digits(),
+            default_counter: provider.default_counter(),
+        }
+    }
+}
+
+/// The cached variants of a provider's image, encoded in Base64.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Icon {
+    small: String,
+    large: String,
+}
+
+impl Icon {
+    /// Whether `image_uri` can be used as a file name in the favicons cache,
+    /// and not to write anywhere else.
+    fn is_valid_name(image_uri: &str) -> bool {
+        !image_uri.is_empty() && !image_uri.contains(['/', '\\', '\0']) && !image_uri.contains("..")
+    }
+
+    fn paths(image_uri: &str) -> Result<(PathBuf, PathBuf)> {
+        anyhow::ensure!(
+            Self::is_valid_name(image_uri),
+            "Invalid image name {image_uri:?}"
+        );
+        Ok((
+            FAVICONS_PATH.join(format!("{image_uri}_32x32")),
+            FAVICONS_PATH.join(format!("{image_uri}_96x96")),
+        ))
+    }
+
+    fn load(image_uri: &str) -> Option<Self> {
+        // See ProviderImage, it is used to avoid re-fetching the favicon
+        if image_uri == "invalid" {
+            return None;
+        }
+        let (small_path, large_path) = Self::paths(image_uri).ok()?;
+        let small = std::fs::read(small_path).ok()?;
+        let large = std::fs::read(large_path).ok()?;
+        Some(Self {
+            small: data_encoding::BASE64.encode(&small),
+            large: data_encoding::BASE64.encode(&large),
+        })
+    }
+
+    /// Stores the icon in the favicons cache unless it is already there.
+    fn save(&self, image_uri: &str) -> Result<()> {
+        let (small_path, large_path) = Self::paths(image_uri)?;
+        if small_path.exists() && large_path.exists() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&*FAVICONS_PATH)?;
+        std::fs::write(
+            small_path,
+            data_encoding::BASE64.decode(self.small.as_bytes())?,
+        )?;
+        std::fs::write(
+            large_path,
+            data_encoding::BASE64.decode(self.large.as_bytes())?,
+        )?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct AccountEntry {
+    #[zeroize(skip)]
+    pub name: String,
+    pub secret: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+    #[zeroize(skip)]
+    pub counter: u32,
+}
+
+impl AccountEntry {
+    fn new(account: &Account) -> Self {
+        Self {
+            name: account.name(),
+            secret: account.otp().secret(),
+            pin: account.otp().pin(),
+            counter: account.counter(),
+        }
+    }
+}
+
+/// An account along with the provider it belongs to
+#[derive(Debug)]
+pub struct Item {
+    provider: Rc<ProviderDetails>,
+    /// `None` for the providers without any account.
+    account: Option<AccountEntry>,
+}
+
+impl RestorableItem for Item {
+    fn account(&self) -> String {
+        self.account
+            .as_ref()
+            .map(|account| account.name.clone())
+            .unwrap_or_default()
+    }
+
+    fn issuer(&self) -> String {
+        self.provider.name.clone()
+    }
+
+    fn secret(&self) -> String {
+        self.account
+            .as_ref()
+            .map(|account| account.secret.clone())
+            .unwrap_or_default()
+    }
+
+    fn pin(&self) -> Option<String> {
+        self.account
+            .as_ref()
+            .and_then(|account| account.pin.clone())
+    }
+
+    fn period(&self) -> Option<u32> {
+        Some(self.provider.period)
+    }
+
+    fn method(&self) -> Method {
+        self.provider.method
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        self.provider.algorithm
+    }
+
+    fn digits(&self) -> Option<u32> {
+        Some(self.provider.digits)
+    }
+
+    fn counter(&self) -> Option<u32> {
+        self.account.as_ref().map(|account| account.counter)
+    }
+
+    fn website(&self) -> Option<String> {
+        self.provider.website.clone()
+    }
+
+    fn help_url(&self) -> Option<String> {
+        self.provider.help_url.clone()
+    }
+
+    fn image_uri(&self) -> Option<String> {
+        self.provider.image_uri.clone()
+    }
+
+    fn default_counter(&self) -> Option<u32> {
+        Some(self.provider.default_counter)
+    }
+
+    fn has_account(&self) -> bool {
+        self.account.is_some()
+    }
+
+    fn restore(&self, transaction: &mut RestoreTransaction) -> Result<()> {
+        if let (Some(image_uri), Some(icon)) = (&self.provider.image_uri, &self.provider.icon) {
+            if let Err(err) = icon.save(image_uri) {
+                tracing::warn!(
+                    "Failed to restore the icon of {}: {err}",
+                    self.provider.name
+                );
+            }
+        }
+        restore_item(self, transaction)
+    }
+}
+
+impl Backupable for Authenticator {
+    const ENCRYPTABLE: bool = true;
+    const IDENTIFIER: &'static str = "authenticator_encrypted";
+
+    fn title() -> String {
+        gettext("Authenticator (_Encrypted)")
+    }
+
+    fn subtitle() -> String {
+        gettext("Into an encrypted JSON file, keeping all the details")
+    }
+
+    fn backup(model: &ProvidersModel, selection: &Selection, key: Option<&str>) -> Result<Vec<u8>> {
+        let password = key.context("A passphrase is required to create an encrypted backup")?;
+        let vault = Vault::new(model, selection);
+        let backup = Self::encrypt(&vault, password, Kdf::default())?;
+        let content = serde_json::ser::to_string_pretty(&backup)?;
+        Ok(content.into_bytes())
+    }
+}
+
+impl Restorable for Authenticator {
+    const ENCRYPTABLE: bool = true;
+    const SCANNABLE: bool = false;
+    const IDENTIFIER: &'static str = "authenticator_encrypted";
+    type Item = Item;
+
+    fn title() -> String {
+        gettext("Authenticator (_Encrypted)")
+    }
+
+    fn subtitle() -> String {
+        gettext("From an encrypted JSON file")
+    }
+
+    fn restore_from_data(from: &[u8], key: Option<&str>) -> Result<Vec<Self::Item>> {
+        let password = key.context("A passphrase is required to restore an encrypted backup")?;
+        let backup: Self = serde_json::de::from_slice(from)?;
+        let vault = backup.decrypt(password)?;
+
+        let items = vault
+            .providers
+            .into_iter()
+            .flat_map(|mut entry| {
+                let details = &mut entry.details;
+                if details
+                    .image_uri
+                    .as_deref()
+                    .is_some_and(|image_uri| !Icon::is_valid_name(image_uri))
+                {
+                    tracing::warn!("Ignoring the invalid image of {}", details.name);
+                    details.image_uri = None;
+                    details.icon = None;
+                }
+                let provider = Rc::new(entry.details);
+                if entry.accounts.is_empty() {
+                    return vec![Item {
+                        provider,
+                        account: None,
+                    }];
+                }
+                entry
+                    .accounts
+                    .into_iter()
+                    .map(|account| Item {
+                        provider: provider.clone(),
+                        account: Some(account),
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let data = std::fs::read_to_string("./src/backup/tests/authenticator_encrypted.json")
+            .expect("file not found");
+        let items = Authenticator::restore_from_data(data.as_bytes(), Some("test")).unwrap();
+
+        assert_eq!(items.len(), 3);
+
+        assert_eq!(items[0].account(), "john.doe@example.com");
+        assert_eq!(items[0].issuer(), "Example");
+        assert_eq!(items[0].secret(), "JBSWY3DPEHPK3PXP");
+        assert_eq!(items[0].method(), Method::TOTP);
+        assert_eq!(items[0].algorithm(), Algorithm::SHA256);
+        assert_eq!(items[0].period(), Some(60));
+        assert_eq!(items[0].digits(), Some(8));
+        assert_eq!(items[0].website(), Some("https://example.com".to_string()));
+        assert_eq!(
+            items[0].help_url(),
+            Some("https://example.com/2fa".to_string())
+        );
+        assert_eq!(items[0].image_uri(), Some("ZXhhbXBsZQ==".to_string()));
+        assert!(items[0].provider.icon.is_some());
+
+        assert_eq!(items[1].account(), "jane.doe@example.com");
+        assert_eq!(items[1].issuer(), "Example");
+
+        assert_eq!(items[2].account(), "Hotp");
+        assert_eq!(items[2].issuer(), "Custom");
+        assert_eq!(items[2].method(), Method::HOTP);
+        assert_eq!(items[2].counter(), Some(42));
+        assert_eq!(items[2].default_counter(), Some(5));
+        assert_eq!(items[2].website(), None);
+        assert_eq!(items[2].image_uri(), None);
+        assert!(items[2].provider.icon.is_none());
+
+        // Wrong passphrase
+        assert!(Authenticator::restore_from_data(data.as_bytes(), Some("wrong")).is_err());
+        assert!(Authenticator::restore_from_data(data.as_bytes(), None).is_err());
+    }
+
+    #[test]
+    fn encrypt_decrypt() {
+        let vault = Vault {
+            providers: vec![ProviderEntry {
+                details: ProviderDetails {
+                    name: "Steam".to_string(),
+                    website: None,
+                    help_url: None,
+                    image_uri: None,
+                    icon: None,
+                    method: Method::Steam,
+                    algorithm: Algorithm::SHA1,
+                    period: 30,
+                    digits: 5,
+                    default_counter: 1,
+                },
+                accounts: vec![AccountEntry {
+                    name: "Gamer".to_string(),
+                    secret: "BASE32SECRET3232".to_string(),
+                    pin: None,
+                    counter: 1,
+                }],
+            }],
+        };
+        let kdf = Kdf {
+            memory: 64,
+            iterations: 1,
+            ..Kdf::default()
+        };
+        let backup = Authenticator::encrypt(&vault, "password", kdf).unwrap();
+        let data = serde_json::to_vec(&backup).unwrap();
+
+        let items = Authenticator::restore_from_data(&data, Some("password")).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].account(), "Gamer");
+        assert_eq!(items[0].issuer(), "Steam");
+        assert_eq!(items[0].secret(), "BASE32SECRET3232");
+        assert_eq!(items[0].method(), Method::Steam);
+
+        let mut backup: Authenticator = serde_json::from_slice(&data).unwrap();
+        backup.version = Authenticator::VERSION + 1;
+        assert!(backup.decrypt("password").is_err());
+
+        // Parameters that would take forever are refused
+        let mut backup: Authenticator = serde_json::from_slice(&data).unwrap();
+        backup.kdf.memory = u32::MAX;
+        assert!(backup.decrypt("password").is_err());
+        let mut backup: Authenticator = serde_json::from_slice(&data).unwrap();
+        backup.kdf.iterations = u32::MAX;
+        assert!(backup.decrypt("password").is_err());
+    }
+
+    #[test]
+    fn image_uri() {
+        assert!(Icon::is_valid_name("ZXhhbXBsZQ=="));
+        for image_uri in [
+            "",
+            "../../../.config/autostart/x",
+            "/home/user/x",
+            "..",
+            "a\\b",
+            "a/b",
+        ] {
+            assert!(!Icon::is_valid_name(image_uri), "{image_uri}");
+            assert!(Icon::paths(image_uri).is_err());
+        }
+
+        let vault = Vault {
+            providers: vec![ProviderEntry {
+                details: ProviderDetails {
+                    name: "Example".to_string(),
+                    website: None,
+                    help_url: None,
+                    image_uri: Some("../../../.config/autostart/x".to_string()),
+                    icon: Some(Icon {
+                        small: data_encoding::BASE64.encode(b"small"),
+                        large: data_encoding::BASE64.encode(b"large"),
+                    }),
+                    method: Method::TOTP,
+                    algorithm: Algorithm::SHA1,
+                    period: 30,
+                    digits: 6,
+                    default_counter: 1,
+                },
+                accounts: vec![AccountEntry {
+                    name: "john.doe@example.com".to_string(),
+                    secret: "JBSWY3DPEHPK3PXP".to_string(),
+                    pin: None,
+                    counter: 1,
+                }],
+            }],
+        };
+        let kdf = Kdf {
+            memory: 64,
+            iterations: 1,
+            ..Kdf::default()
+        };
+        let backup = Authenticator::encrypt(&vault, "password", kdf).unwrap();
+        let data = serde_json::to_vec(&backup).unwrap();
+
+        let items = Authenticator::restore_from_data(&data, Some("password")).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].image_uri(), None);
+        assert!(items[0].provider.icon.is_none());
+    }
+
+    #[test]
+    fn empty_provider() {
+        let vault = Vault {
+            providers: vec![ProviderEntry {
+                details: ProviderDetails {
+                    name: "Custom".to_string(),
+                    website: Some("https://custom.example.com".to_string()),
+                    help_url: Some("https://custom.example.com/2fa".to_string()),
+                    image_uri: None,
+                    icon: None,
+                    method: Method::TOTP,
+                    algorithm: Algorithm::SHA512,
+                    period: 45,
+                    digits: 8,
+                    default_counter: 1,
+                },
+                accounts: vec![],
+            }],
+        };
+        let kdf = Kdf {
+            memory: 64,
+            iterations: 1,
+            ..Kdf::default()
+        };
+        let backup = Authenticator::encrypt(&vault, "password", kdf).unwrap();
+        let data = serde_json::to_vec(&backup).unwrap();
+
+        let items = Authenticator::restore_from_data(&data, Some("password")).unwrap();
+        assert_eq!(items.len(), 1);
+        assert!(!items[0].has_account());
+        assert_eq!(items[0].issuer(), "Custom");
+        assert_eq!(items[0].account(), "");
+        assert_eq!(items[0].counter(), None);
+        assert_eq!(items[0].algorithm(), Algorithm::SHA512);
+        assert_eq!(items[0].period(), Some(45));
+        assert_eq!(items[0].digits(), Some(8));
+        assert_eq!(
+            items[0].website(),
+            Some("https://custom.example.com".to_string())
+        );
+        assert_eq!(
+            items[0].help_url(),
+            Some("https://custom.example.com/2fa".to_string())
+        );
+    }
+}
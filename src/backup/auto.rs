@@ -0,0 +1,136 @@
+//! Automatic Backups
+//!
+//! Encrypted backups written into a directory, either when the accounts change
+//! or on a schedule. Only the latest ones are kept, as many as the
+//! `auto-backup-keep` setting.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use gtk::glib;
+
+use super::{Aegis, AndOTP, Authenticator, Backupable, Selection};
+use crate::models::ProvidersModel;
+
+/// The prefix of the automatic backups file names, the files without it are
+/// never removed.
+const FILE_PREFIX: &str = "authenticator-backup-";
+
+/// An encrypted format the automatic backups can be made into.
+pub struct Format {
+    pub identifier: &'static str,
+    pub extension: &'static str,
+    title: fn() -> String,
+    backup: fn(&ProvidersModel, &Selection, Option<&str>) -> Result<Vec<u8>>,
+}
+
+impl Format {
+    fn new<T: Backupable>(extension: &'static str) -> Self {
+        debug_assert!(T::ENCRYPTABLE && !T::SCANNABLE);
+        Self {
+            identifier: T::IDENTIFIER,
+            extension,
+            title: T::title,
+            backup: T::backup,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        // Drop the mnemonics used in the backup rows
+        (self.title)().replace('_', "")
+    }
+
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::new::<Authenticator>("json"),
+            Self::new::<Aegis>("json"),
+            Self::new::<AndOTP>("json.aes"),
+        ]
+    }
+
+    pub fn find(identifier: &str) -> Option<Self> {
+        Self::all()
+            .into_iter()
+            .find(|format| format.identifier == identifier)
+    }
+}
+
+/// Write a backup of every account into `directory` then remove the oldest
+/// backups, keeping `keep` of them.
+pub fn backup_into(
+    model: &ProvidersModel,
+    format: &Format,
+    password: &str,
+    directory: &Path,
+    keep: usize,
+) -> Result<PathBuf> {
+    let content = (format.backup)(model, &Selection::All, Some(password))?;
+
+    std::fs::create_dir_all(directory)
+        .with_context(|| format!("Failed to create {}", directory.display()))?;
+    let now = glib::DateTime::now_local()?;
+    let path = directory.join(file_name(&now, format.extension)?);
+    // Write into a temporary file first so a failure never leaves a truncated
+    // backup behind
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, content)
+        .with_context(|| format!("Failed to write {}", partial.display()))?;
+    std::fs::rename(&partial, &path)?;
+
+    let names = std::fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect::<Vec<_>>();
+    for name in expired(names, keep) {
+        if let Err(err) = std::fs::remove_file(directory.join(&name)) {
+            tracing::warn!("Failed to remove the old backup {name}: {err}");
+        }
+    }
+    Ok(path)
+}
+
+fn file_name(datetime: &glib::DateTime, extension: &str) -> Result<String> {
+    let timestamp = datetime.format("%Y-%m-%d-%H%M%S")?;
+    Ok(format!("{FILE_PREFIX}{timestamp}.{extension}"))
+}
+
+/// The backups to remove to only keep the `keep` latest ones.
+fn expired(mut names: Vec<String>, keep: usize) -> Vec<String> {
+    names.retain(|name| name.starts_with(FILE_PREFIX) && !name.ends_with(".partial"));
+    // The timestamps are sortable, latest first
+    names.sort_unstable_by(|a, b| b.cmp(a));
+    names.into_iter().skip(keep).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let datetime = glib::DateTime::from_utc(2023, 9, 4, 7, 5, 3.0).unwrap();
+        assert_eq!(
+            file_name(&datetime, "json.aes").unwrap(),
+            "authenticator-backup-2023-09-04-070503.json.aes"
+        );
+    }
+
+    #[test]
+    fn rotation() {
+        let names = vec![
+            "authenticator-backup-2023-09-04-070503.json".to_owned(),
+            "notes.txt".to_owned(),
+            "authenticator-backup-2023-10-01-120000.json".to_owned(),
+            "authenticator-backup-2023-10-01-120001.partial".to_owned(),
+            "authenticator-backup-2022-12-31-235959.json.aes".to_owned(),
+        ];
+
+        assert_eq!(
+            expired(names.clone(), 1),
+            vec![
+                "authenticator-backup-2023-09-04-070503.json",
+                "authenticator-backup-2022-12-31-235959.json.aes",
+            ]
+        );
+        assert!(expired(names, 3).is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+//! Ente Auth Import Module
+//!
+//! The encrypted exports contain the accounts as `otpauth://` URIs, one per
+//! line, encrypted using libsodium's `crypto_secretstream_xchacha20poly1305`.
+//! The key is derived from the password using Argon2id with the parameters
+//! stored alongside the encrypted data.
+
+use anyhow::{Context, Result};
+use chacha20::{
+    cipher::{consts::U10, KeyIvInit, StreamCipher, StreamCipherSeek},
+    ChaCha20,
+};
+use gettextrs::gettext;
+use poly1305::{universal_hash::KeyInit, Poly1305};
+use serde::Deserialize;
+use zeroize::Zeroizing;
+
+use super::Restorable;
+use crate::models::OTPUri;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ente {
+    version: u32,
+    kdf_params: KdfParams,
+    encrypted_data: String,
+    encryption_nonce: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KdfParams {
+    /// The memory cost in bytes.
+    mem_limit: u32,
+    ops_limit: u32,
+    salt: String,
+}
+
+impl KdfParams {
+    /// libsodium's sensitive memory limit, Ente starts from it then halves it
+    /// and doubles the number of operations until it fits in the device's
+    /// memory. Anything larger would allow the backup to exhaust the memory
+    /// or to block the restore forever.
+    const MAX_MEM_LIMIT: u32 = 1024 * 1024 * 1024;
+    const MAX_OPS_LIMIT: u32 = 256;
+
+    fn derive_key(&self, password: &str) -> Result<Zeroizing<Vec<u8>>> {
+        anyhow::ensure!(
+            self.mem_limit <= Self::MAX_MEM_LIMIT && self.ops_limit <= Self::MAX_OPS_LIMIT,
+            "Unsupported key derivation parameters, memory: {} bytes, operations: {}",
+            self.mem_limit,
+            self.ops_limit
+        );
+        let salt = data_encoding::BASE64
+            .decode(self.salt.as_bytes())
+            .context("Cannot decode (base64) salt")?;
+        // Matches libsodium's crypto_pwhash
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+            mem_cost: self.mem_limit / 1024,
+            time_cost: self.ops_limit,
+            lanes: 1,
+            hash_length: 32,
+            ..argon2::Config::default()
+        };
+        let key = argon2::hash_raw(password.as_bytes(), &salt, &config)?;
+        Ok(Zeroizing::new(key))
+    }
+}
+
+impl Ente {
+    const HEADER_LENGTH: usize = 24;
+    const TAG_LENGTH: usize = 16;
+    const BLOCK_LENGTH: usize = 64;
+
+    /// Decrypt a stream made of a single message, as written by
+    /// `crypto_secretstream_xchacha20poly1305_push`.
+    fn decrypt(key: &[u8], header: &[u8], data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        if key.len() != 32 || header.len() != Self::HEADER_LENGTH {
+            anyhow::bail!("Invalid key or header length");
+        }
+        if data.len() < 1 + Self::TAG_LENGTH {
+            anyhow::bail!("Encrypted data is too short");
+        }
+
+        let mut subkey = Zeroizing::new([0u8; 32]);
+        subkey.copy_from_slice(&chacha20::hchacha::<U10>(key.into(), header[..16].into()));
+        // The nonce is made of a 32-bit counter, starting at one, followed by
+        // the end of the header
+        let mut nonce = [0u8; 12];
+        nonce[0] = 1;
+        nonce[4..].copy_from_slice(&header[16..]);
+        let mut cipher = ChaCha20::new(subkey.as_ref().into(), &nonce.into());
+
+        let (ciphertext, mac) = data.split_at(data.len() - Self::TAG_LENGTH);
+        let (tag, message) = ciphertext.split_at(1);
+
+        // The first block is the Poly1305 key, the second one encrypts the tag
+        let mut poly_key = Zeroizing::new([0u8; Self::BLOCK_LENGTH]);
+        cipher.apply_keystream(poly_key.as_mut());
+        let mut block = [0u8; Self::BLOCK_LENGTH];
+        cipher.apply_keystream(&mut block);
+        block[0] = tag[0];
+
+        // There is no additional data, so the authenticated data is the tag
+        // block followed by the padded message and the lengths. Note that
+        // libsodium pads the message with `(0x10 - 64 + mlen) & 0xf` bytes,
+        // which doesn't align it on 16 bytes.
+        let mut authenticated = block.to_vec();
+        authenticated.extend_from_slice(message);
+        let padding = message.len() % 16;
+        authenticated.resize(authenticated.len() + padding, 0);
+        authenticated.extend_from_slice(&0u64.to_le_bytes());
+        authenticated
+            .extend_from_slice(&((Self::BLOCK_LENGTH + message.len()) as u64).to_le_bytes());
+        let expected = Poly1305::new(poly_key[..32].into()).compute_unpadded(&authenticated);
+        let difference = expected
+            .iter()
+            .zip(mac)
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+        if difference != 0 {
+            anyhow::bail!("Cannot decrypt Ente Auth export, wrong password?");
+        }
+
+        let mut content = Zeroizing::new(message.to_vec());
+        cipher.seek(2 * Self::BLOCK_LENGTH);
+        cipher.apply_keystream(&mut content);
+        Ok(content)
+    }
+
+    /// The accounts of the decrypted export, one URI per line.
+    fn parse_uris(uris: &str) -> Result<Vec<OTPUri>> {
+        // Fails instead of silently leaving accounts out
+        uris.lines()
+            .enumerate()
+            .filter(|(_, uri)| !uri.trim().is_empty())
+            .map(|(index, uri)| {
+                uri.trim().parse::<OTPUri>().with_context(|| {
+                    format!(
+                        "Cannot parse the entry {} of the Ente Auth export",
+                        index + 1
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+impl Restorable for Ente {
+    const ENCRYPTABLE: bool = true;
+    const SCANNABLE: bool = false;
+    const IDENTIFIER: &'static str = "ente";
+    type Item = OTPUri;
+
+    fn title() -> String {
+        // Translators: This is for restoring a backup from Ente Auth.
+        gettext("Ente Auth")
+    }
+
+    fn subtitle() -> String {
+        gettext("From an encrypted export file")
+    }
+
+    fn restore_from_data(from: &[u8], key: Option<&str>) -> Result<Vec<Self::Item>> {
+        let root: Ente = serde_json::de::from_slice(from)?;
+        if root.version != 1 {
+            anyhow::bail!(
+                "Ente Auth export version expected to be 1. Found {} instead.",
+                root.version
+            );
+        }
+        let Some(password) = key else {
+            anyhow::bail!("Found encrypted Ente Auth export but no password given.");
+        };
+
+        let key = root.kdf_params.derive_key(password)?;
+        let header = data_encoding::BASE64
+            .decode(root.encryption_nonce.as_bytes())
+            .context("Cannot decode (base64) nonce")?;
+        let data = data_encoding::BASE64
+            .decode(root.encrypted_data.as_bytes())
+            .context("Cannot decode (base64) encrypted data")?;
+        let content = Self::decrypt(&key, &header, &data)?;
+
+        Self::parse_uris(std::str::from_utf8(&content)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::RestorableItem, *};
+    use crate::models::{Algorithm, Method};
+
+    #[test]
+    fn parse() {
+        let data = std::fs::read("./src/backup/tests/ente_encrypted.txt").unwrap();
+        let items = Ente::restore_from_data(&data, Some("test")).unwrap();
+
+        assert_eq!(items.len(), 3);
+
+        assert_eq!(items[0].account(), "Mason");
+        assert_eq!(items[0].issuer(), "Deno");
+        assert_eq!(items[0].secret(), "4SJHB4GSD43FZBAI7C2HLRJGPQ");
+        assert_eq!(items[0].period(), Some(30));
+        assert_eq!(items[0].method(), Method::TOTP);
+        assert_eq!(items[0].algorithm(), Algorithm::SHA1);
+        assert_eq!(items[0].digits(), Some(6));
+
+        assert_eq!(items[1].account(), "James");
+        assert_eq!(items[1].issuer(), "SPDX");
+        assert_eq!(items[1].secret(), "5OM4WOOGPLQEF6UGN3CPEOOLWU");
+        assert_eq!(items[1].period(), Some(20));
+        assert_eq!(items[1].algorithm(), Algorithm::SHA256);
+        assert_eq!(items[1].digits(), Some(7));
+
+        assert_eq!(items[2].account(), "James");
+        assert_eq!(items[2].issuer(), "Issuu");
+        assert_eq!(items[2].secret(), "YOOMIXWS5GN6RTBPUFFWKTW5M4");
+        assert_eq!(items[2].method(), Method::HOTP);
+        assert_eq!(items[2].counter(), Some(1));
+    }
+
+    #[test]
+    fn wrong_password() {
+        let data = std::fs::read("./src/backup/tests/ente_encrypted.txt").unwrap();
+        assert!(Ente::restore_from_data(&data, Some("wrong")).is_err());
+        assert!(Ente::restore_from_data(&data, None).is_err());
+    }
+
+    #[test]
+    fn key_derivation_limits() {
+        let data = std::fs::read("./src/backup/tests/ente_encrypted.txt").unwrap();
+        let ente: Ente = serde_json::from_slice(&data).unwrap();
+
+        let mut params = ente.kdf_params;
+        let mem_limit = params.mem_limit;
+        params.mem_limit = u32::MAX;
+        let err = params.derive_key("test").err().unwrap();
+        assert!(err.to_string().contains("Unsupported"));
+
+        params.mem_limit = mem_limit;
+        params.ops_limit = u32::MAX;
+        let err = params.derive_key("test").err().unwrap();
+        assert!(err.to_string().contains("Unsupported"));
+    }
+
+    #[test]
+    fn invalid_entry() {
+        let uris = "otpauth://totp/Deno:Mason?secret=4SJHB4GSD43FZBAI7C2HLRJGPQ&issuer=Deno\n\n";
+        assert_eq!(Ente::parse_uris(uris).unwrap().len(), 1);
+
+        let uris =
+            "otpauth://totp/Deno:Mason?secret=4SJHB4GSD43FZBAI7C2HLRJGPQ&issuer=Deno\nnot a uri";
+        let err = Ente::parse_uris(uris).err().unwrap();
+        assert!(err.to_string().contains("entry 2"));
+    }
+}
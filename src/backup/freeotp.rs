@@ -1,10 +1,9 @@
 use anyhow::Result;
 use gettextrs::gettext;
-use gtk::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use super::{Backupable, Restorable};
-use crate::models::{Account, OTPUri, Provider, ProvidersModel};
+use super::{Backupable, Restorable, Selection};
+use crate::models::{OTPUri, ProvidersModel};
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize)]
@@ -22,16 +21,15 @@ impl Backupable for FreeOTP {
         gettext("Into a plain-text file, compatible with FreeOTP+")
     }
 
-    fn backup(model: &ProvidersModel, _key: Option<&str>) -> Result<Vec<u8>> {
+    fn backup(
+        model: &ProvidersModel,
+        selection: &Selection,
+        _key: Option<&str>,
+    ) -> Result<Vec<u8>> {
         let mut items: Vec<String> = Vec::new();
 
-        for i in 0..model.n_items() {
-            let provider = model.item(i).and_downcast::<Provider>().unwrap();
-            let accounts = provider.accounts_model();
-
-            for j in 0..accounts.n_items() {
-                let account = accounts.item(j).and_downcast::<Account>().unwrap();
-
+        for (_provider, accounts) in selection.accounts(model) {
+            for account in accounts {
                 items.push(account.otp_uri().into());
             }
         }
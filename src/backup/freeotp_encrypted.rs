@@ -0,0 +1,488 @@
+//! FreeOTP Import Module
+//!
+//! FreeOTP 2.x backups (`externalBackup.xml`) are, despite their extension, a
+//! Java serialized `HashMap<String, String>`. The map contains:
+//!
+//! - `masterKey`: the AES master key, encrypted with a key derived from the
+//!   backup password using PBKDF2.
+//! - `<uuid>`: the token secret, encrypted with the master key.
+//! - `<uuid>-token`: the token details (issuer, label, algorithm, ...).
+//!
+//! Every encrypted key is AES-GCM encrypted, using the name of the key type as
+//! additional authenticated data.
+
+use aes_gcm::{
+    aead::{Aead, Payload},
+    KeyInit,
+};
+use anyhow::{Context, Result};
+use gettextrs::gettext;
+use serde::Deserialize;
+use sha2::Sha512;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use super::{Restorable, RestorableItem};
+use crate::models::{Algorithm, Method};
+
+pub struct FreeOTPEncrypted;
+
+#[derive(Deserialize)]
+struct MasterKey {
+    #[serde(rename = "mAlgorithm")]
+    algorithm: String,
+    #[serde(rename = "mEncryptedKey")]
+    encrypted_key: EncryptedKey,
+    #[serde(rename = "mIterations")]
+    iterations: u32,
+    #[serde(rename = "mSalt")]
+    salt: Vec<i8>,
+}
+
+impl MasterKey {
+    /// FreeOTP uses 100 000 iterations, anything much larger would block
+    /// the restore for minutes, or forever.
+    const MAX_ITERATIONS: u32 = 1_000_000;
+
+    fn decrypt(&self, password: &str) -> Result<Zeroizing<Vec<u8>>> {
+        if !self.algorithm.eq_ignore_ascii_case("PBKDF2withHmacSHA512") {
+            anyhow::bail!("Unsupported key derivation algorithm {}", self.algorithm);
+        }
+        if self.iterations > Self::MAX_ITERATIONS {
+            anyhow::bail!(
+                "Unsupported number of key derivation iterations {}",
+                self.iterations
+            );
+        }
+        let salt = to_bytes(&self.salt);
+        if ![16, 32].contains(&salt.len()) {
+            anyhow::bail!("Unsupported key length {}", salt.len());
+        }
+        // FreeOTP derives a key as long as the salt
+        let mut key = Zeroizing::new(vec![0u8; salt.len()]);
+        pbkdf2::pbkdf2_hmac::<Sha512>(password.as_bytes(), &salt, self.iterations, &mut key);
+
+        self.encrypted_key
+            .decrypt(&key)
+            .context("Cannot decrypt master key, wrong password?")
+    }
+}
+
+#[derive(Deserialize)]
+struct EncryptedKey {
+    #[serde(rename = "mCipher")]
+    cipher: String,
+    #[serde(rename = "mCipherText")]
+    ciphertext: Vec<i8>,
+    /// DER encoded GCM parameters.
+    #[serde(rename = "mParameters")]
+    parameters: Vec<i8>,
+    /// The type of the encrypted key, used as the additional authenticated
+    /// data.
+    #[serde(rename = "mToken")]
+    token: String,
+}
+
+impl EncryptedKey {
+    fn decrypt(&self, key: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        if self.cipher != "AES/GCM/NoPadding" {
+            anyhow::bail!("Unsupported cipher {}", self.cipher);
+        }
+        let nonce = self.nonce()?;
+        let ciphertext = to_bytes(&self.ciphertext);
+        let payload = Payload {
+            msg: &ciphertext,
+            aad: self.token.as_bytes(),
+        };
+        let plaintext = match key.len() {
+            16 => aes_gcm::Aes128Gcm::new_from_slice(key)?
+                .decrypt(aes_gcm::Nonce::from_slice(&nonce), payload),
+            32 => aes_gcm::Aes256Gcm::new_from_slice(key)?
+                .decrypt(aes_gcm::Nonce::from_slice(&nonce), payload),
+            length => anyhow::bail!("Unsupported key length {length}"),
+        }
+        .map_err(|_| anyhow::anyhow!("Cannot decrypt {} key", self.token))?;
+        Ok(Zeroizing::new(plaintext))
+    }
+
+    /// Extract the nonce from the `GCMParameters` sequence, made of the nonce
+    /// as an octet string followed by the tag length as an integer.
+    fn nonce(&self) -> Result<Vec<u8>> {
+        let parameters = to_bytes(&self.parameters);
+        match parameters.as_slice() {
+            [0x30, _, 0x04, 12, rest @ ..] if rest.len() >= 12 => Ok(rest[..12].to_vec()),
+            _ => anyhow::bail!("Invalid GCM parameters"),
+        }
+    }
+}
+
+/// The `<uuid>` entries, wrapping a JSON serialized [`EncryptedKey`].
+#[derive(Deserialize)]
+struct TokenKey {
+    key: String,
+}
+
+#[derive(Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct FreeOTPEncryptedItem {
+    #[zeroize(skip)]
+    algo: Algorithm,
+    #[zeroize(skip)]
+    counter: Option<u32>,
+    #[zeroize(skip)]
+    digits: Option<u32>,
+    #[zeroize(skip)]
+    label: String,
+    #[serde(rename = "issuerExt")]
+    #[zeroize(skip)]
+    issuer: Option<String>,
+    #[serde(rename = "issuerInt")]
+    #[zeroize(skip)]
+    issuer_int: Option<String>,
+    #[zeroize(skip)]
+    period: Option<u32>,
+    #[serde(rename = "type")]
+    #[zeroize(skip)]
+    method: Method,
+    #[serde(skip)]
+    secret: Vec<u8>,
+}
+
+impl RestorableItem for FreeOTPEncryptedItem {
+    fn account(&self) -> String {
+        self.label.clone()
+    }
+
+    fn issuer(&self) -> String {
+        self.issuer
+            .as_ref()
+            .or(self.issuer_int.as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn secret(&self) -> String {
+        data_encoding::BASE32_NOPAD.encode(&self.secret)
+    }
+
+    fn period(&self) -> Option<u32> {
+        self.period
+    }
+
+    fn method(&self) -> Method {
+        self.method
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        self.algo
+    }
+
+    fn digits(&self) -> Option<u32> {
+        self.digits
+    }
+
+    fn counter(&self) -> Option<u32> {
+        if self.method().is_event_based() {
+            // Like FreeOTP+, FreeOTP stores the counter minus one
+            self.counter.map(|c| c + 1)
+        } else {
+            None
+        }
+    }
+}
+
+impl Restorable for FreeOTPEncrypted {
+    const ENCRYPTABLE: bool = true;
+    const SCANNABLE: bool = false;
+    const IDENTIFIER: &'static str = "freeotp_encrypted";
+    type Item = FreeOTPEncryptedItem;
+
+    fn title() -> String {
+        gettext("FreeOTP")
+    }
+
+    fn subtitle() -> String {
+        gettext("From an encrypted externalBackup.xml file")
+    }
+
+    fn restore_from_data(from: &[u8], key: Option<&str>) -> Result<Vec<Self::Item>> {
+        let Some(password) = key else {
+            anyhow::bail!("FreeOTP backups are always encrypted, a password is required");
+        };
+        let entries = java::parse_string_map(from).context("Invalid FreeOTP backup")?;
+
+        let master_key = entries
+            .iter()
+            .find(|(k, _)| k == "masterKey")
+            .map(|(_, v)| serde_json::from_str::<MasterKey>(v))
+            .context("FreeOTP backup has no master key")??;
+        let master_key = master_key.decrypt(password)?;
+
+        let mut items = Vec::new();
+        for (uuid, value) in &entries {
+            let Some(uuid) = uuid.strip_suffix("-token") else {
+                continue;
+            };
+            let mut item: FreeOTPEncryptedItem = serde_json::from_str(value)
+                .with_context(|| format!("Invalid details for token {uuid}"))?;
+
+            let token_key = entries
+                .iter()
+                .find(|(k, _)| k == uuid)
+                .map(|(_, v)| serde_json::from_str::<TokenKey>(v))
+                .with_context(|| format!("Missing key for token {uuid}"))??;
+            let encrypted_key: EncryptedKey = serde_json::from_str(&token_key.key)?;
+            item.secret = encrypted_key.decrypt(&master_key)?.to_vec();
+
+            items.push(item);
+        }
+
+        Ok(items)
+    }
+}
+
+fn to_bytes(bytes: &[i8]) -> Vec<u8> {
+    bytes.iter().map(|b| *b as u8).collect()
+}
+
+/// A minimal reader of the Java Object Serialization Stream Protocol, only
+/// supporting a `java.util.HashMap` of strings.
+///
+/// See <https://docs.oracle.com/en/java/javase/17/docs/specs/serialization/protocol.html>.
+mod java {
+    use anyhow::Result;
+
+    const STREAM_MAGIC: u16 = 0xaced;
+    const STREAM_VERSION: u16 = 5;
+    const BASE_WIRE_HANDLE: u32 = 0x7e0000;
+
+    const TC_NULL: u8 = 0x70;
+    const TC_REFERENCE: u8 = 0x71;
+    const TC_CLASSDESC: u8 = 0x72;
+    const TC_OBJECT: u8 = 0x73;
+    const TC_STRING: u8 = 0x74;
+    const TC_BLOCKDATA: u8 = 0x77;
+    const TC_ENDBLOCKDATA: u8 = 0x78;
+    const TC_LONGSTRING: u8 = 0x7c;
+
+    const SC_WRITE_METHOD: u8 = 0x01;
+
+    struct Reader<'a> {
+        data: &'a [u8],
+        position: usize,
+        /// The objects a handle can refer to, only the strings are kept.
+        handles: Vec<Option<String>>,
+    }
+
+    impl<'a> Reader<'a> {
+        fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+            let end = self
+                .position
+                .checked_add(length)
+                .filter(|end| *end <= self.data.len())
+                .ok_or_else(|| anyhow::anyhow!("Unexpected end of stream"))?;
+            let bytes = &self.data[self.position..end];
+            self.position = end;
+            Ok(bytes)
+        }
+
+        fn u8(&mut self) -> Result<u8> {
+            Ok(self.bytes(1)?[0])
+        }
+
+        fn u16(&mut self) -> Result<u16> {
+            Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+        }
+
+        fn u32(&mut self) -> Result<u32> {
+            Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+        }
+
+        fn u64(&mut self) -> Result<u64> {
+            Ok(u64::from_be_bytes(self.bytes(8)?.try_into()?))
+        }
+
+        fn utf(&mut self, length: usize) -> Result<String> {
+            // Java uses a modified UTF-8, which only differs for the NUL
+            // character & the supplementary ones, none of them are expected.
+            Ok(String::from_utf8(self.bytes(length)?.to_vec())?)
+        }
+
+        fn new_handle(&mut self, value: Option<String>) {
+            self.handles.push(value);
+        }
+
+        fn string(&mut self) -> Result<String> {
+            let value = match self.u8()? {
+                TC_STRING => {
+                    let length = self.u16()? as usize;
+                    self.utf(length)?
+                }
+                TC_LONGSTRING => {
+                    let length = usize::try_from(self.u64()?)?;
+                    self.utf(length)?
+                }
+                TC_REFERENCE => {
+                    let handle = self.u32()?.wrapping_sub(BASE_WIRE_HANDLE) as usize;
+                    return self
+                        .handles
+                        .get(handle)
+                        .cloned()
+                        .flatten()
+                        .ok_or_else(|| anyhow::anyhow!("Invalid string reference"));
+                }
+                tc => anyhow::bail!("Expected a string, found {tc:#x}"),
+            };
+            self.new_handle(Some(value.clone()));
+            Ok(value)
+        }
+
+        /// Read the `java.util.HashMap` class description and returns the
+        /// size of its serialized fields.
+        fn hash_map_class(&mut self) -> Result<usize> {
+            if self.u8()? != TC_CLASSDESC {
+                anyhow::bail!("Expected a class description");
+            }
+            let length = self.u16()? as usize;
+            let name = self.utf(length)?;
+            if name != "java.util.HashMap" {
+                anyhow::bail!("Expected a java.util.HashMap, found {name}");
+            }
+            // serialVersionUID
+            self.u64()?;
+            self.new_handle(None);
+            let flags = self.u8()?;
+            if flags & SC_WRITE_METHOD == 0 {
+                anyhow::bail!("Unexpected class flags {flags:#x}");
+            }
+
+            let mut fields_size = 0;
+            for _ in 0..self.u16()? {
+                fields_size += match self.u8()? {
+                    b'B' | b'Z' => 1,
+                    b'C' | b'S' => 2,
+                    b'F' | b'I' => 4,
+                    b'D' | b'J' => 8,
+                    code => anyhow::bail!("Unexpected field type {code:#x}"),
+                };
+                let length = self.u16()? as usize;
+                self.bytes(length)?;
+            }
+
+            // No class annotations & no super class
+            if self.u8()? != TC_ENDBLOCKDATA || self.u8()? != TC_NULL {
+                anyhow::bail!("Unexpected java.util.HashMap class description");
+            }
+            Ok(fields_size)
+        }
+    }
+
+    /// Parse a serialized `HashMap<String, String>`, keeping the order of the
+    /// stream.
+    pub fn parse_string_map(data: &[u8]) -> Result<Vec<(String, String)>> {
+        let mut reader = Reader {
+            data,
+            position: 0,
+            handles: Vec::new(),
+        };
+        if reader.u16()? != STREAM_MAGIC || reader.u16()? != STREAM_VERSION {
+            anyhow::bail!("Not a Java serialization stream");
+        }
+        if reader.u8()? != TC_OBJECT {
+            anyhow::bail!("Expected an object");
+        }
+        let fields_size = reader.hash_map_class()?;
+        reader.new_handle(None);
+        // loadFactor & threshold
+        reader.bytes(fields_size)?;
+
+        // The capacity & the size written by HashMap.writeObject
+        if reader.u8()? != TC_BLOCKDATA || reader.u8()? != 8 {
+            anyhow::bail!("Expected the java.util.HashMap size");
+        }
+        let _capacity = reader.u32()?;
+        let size = reader.u32()?;
+
+        let mut entries = Vec::new();
+        for _ in 0..size {
+            let key = reader.string()?;
+            let value = reader.string()?;
+            entries.push((key, value));
+        }
+        if reader.u8()? != TC_ENDBLOCKDATA {
+            anyhow::bail!("Expected the end of the java.util.HashMap");
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let data = std::fs::read("./src/backup/tests/freeotp_encrypted.xml").unwrap();
+        let items = FreeOTPEncrypted::restore_from_data(&data, Some("test")).unwrap();
+
+        assert_eq!(items.len(), 3);
+
+        assert_eq!(items[0].account(), "Mason");
+        assert_eq!(items[0].issuer(), "Deno");
+        assert_eq!(items[0].secret(), "4SJHB4GSD43FZBAI7C2HLRJGPQ");
+        assert_eq!(items[0].period(), Some(30));
+        assert_eq!(items[0].method(), Method::TOTP);
+        assert_eq!(items[0].algorithm(), Algorithm::SHA1);
+        assert_eq!(items[0].digits(), Some(6));
+        assert_eq!(items[0].counter(), None);
+
+        assert_eq!(items[1].account(), "James");
+        assert_eq!(items[1].issuer(), "SPDX");
+        assert_eq!(items[1].secret(), "5OM4WOOGPLQEF6UGN3CPEOOLWU");
+        assert_eq!(items[1].period(), Some(20));
+        assert_eq!(items[1].method(), Method::TOTP);
+        assert_eq!(items[1].algorithm(), Algorithm::SHA256);
+        assert_eq!(items[1].digits(), Some(7));
+        assert_eq!(items[1].counter(), None);
+
+        assert_eq!(items[2].account(), "James");
+        assert_eq!(items[2].issuer(), "Issuu");
+        assert_eq!(items[2].secret(), "YOOMIXWS5GN6RTBPUFFWKTW5M4");
+        assert_eq!(items[2].method(), Method::HOTP);
+        assert_eq!(items[2].algorithm(), Algorithm::SHA1);
+        assert_eq!(items[2].digits(), Some(6));
+        assert_eq!(items[2].counter(), Some(1));
+    }
+
+    #[test]
+    fn wrong_password() {
+        let data = std::fs::read("./src/backup/tests/freeotp_encrypted.xml").unwrap();
+        assert!(FreeOTPEncrypted::restore_from_data(&data, Some("wrong")).is_err());
+        assert!(FreeOTPEncrypted::restore_from_data(&data, None).is_err());
+    }
+
+    #[test]
+    fn key_derivation_limits() {
+        let data = std::fs::read("./src/backup/tests/freeotp_encrypted.xml").unwrap();
+        let entries = java::parse_string_map(&data).unwrap();
+        let mut master_key = serde_json::from_str::<MasterKey>(&entries[0].1).unwrap();
+        assert!(master_key.decrypt("test").is_ok());
+
+        master_key.iterations = u32::MAX;
+        assert!(master_key.decrypt("test").is_err());
+
+        master_key.iterations = 100_000;
+        master_key.salt = vec![0; 1 << 20];
+        assert!(master_key.decrypt("test").is_err());
+    }
+
+    #[test]
+    fn parse_string_map() {
+        assert!(java::parse_string_map(b"<?xml version=\"1.0\"?>").is_err());
+
+        let data = std::fs::read("./src/backup/tests/freeotp_encrypted.xml").unwrap();
+        let entries = java::parse_string_map(&data).unwrap();
+        assert_eq!(entries.len(), 7);
+        assert_eq!(entries[0].0, "masterKey");
+        // the stream is truncated
+        assert!(java::parse_string_map(&data[..data.len() - 1]).is_err());
+    }
+}
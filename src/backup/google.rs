@@ -1,29 +1,91 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use gettextrs::gettext;
-use percent_encoding::percent_decode;
+use percent_encoding::{percent_decode, utf8_percent_encode, NON_ALPHANUMERIC};
 use prost::{Enumeration, Message};
 use url::Url;
 
-use super::Restorable;
-use crate::models::{Algorithm, Method, OTPUri};
+use super::{Backupable, Batch, Restorable, Selection};
+use crate::models::{Account, Algorithm, Method, OTPUri, Provider, ProvidersModel};
 
 pub struct Google;
 
-impl Restorable for Google {
-    const ENCRYPTABLE: bool = false;
-    const SCANNABLE: bool = true;
-    const IDENTIFIER: &'static str = "google";
-    type Item = OTPUri;
+impl Google {
+    /// The maximum number of accounts per QR code, like Google Authenticator
+    /// does to keep the codes scannable.
+    const BATCH_SIZE: usize = 10;
+    /// The only period supported by Google Authenticator.
+    const PERIOD: u32 = 30;
 
-    fn title() -> String {
-        gettext("Google Authenticator")
+    /// Convert an account to its migration parameters, if Google Authenticator
+    /// is able to generate the same codes.
+    fn otp_parameters(
+        provider: &Provider,
+        account: &Account,
+    ) -> Option<protobuf::migration_payload::OtpParameters> {
+        use protobuf::migration_payload::{DigitCount, OtpType};
+
+        let (r#type, counter) = match provider.method() {
+            Method::TOTP if provider.period() == Self::PERIOD => (OtpType::OTP_TOTP, 0),
+            Method::HOTP => (OtpType::OTP_HOTP, account.counter() as i64),
+            _ => return None,
+        };
+        let algorithm = match provider.algorithm() {
+            Algorithm::SHA1 => protobuf::migration_payload::Algorithm::ALGO_SHA1,
+            Algorithm::SHA256 => protobuf::migration_payload::Algorithm::ALGO_SHA256,
+            Algorithm::SHA512 => protobuf::migration_payload::Algorithm::ALGO_SHA512,
+            Algorithm::MD5 => protobuf::migration_payload::Algorithm::ALGO_MD5,
+            _ => return None,
+        };
+        let digits = match provider.digits() {
+            6 => DigitCount::DIGIT_COUNT_SIX,
+            8 => DigitCount::DIGIT_COUNT_EIGHT,
+            _ => return None,
+        };
+        let secret = data_encoding::BASE32_NOPAD
+            .decode(account.otp().secret().as_bytes())
+            .ok()?;
+
+        Some(protobuf::migration_payload::OtpParameters {
+            secret,
+            name: account.name(),
+            issuer: provider.name(),
+            algorithm: algorithm as i32,
+            digits: digits as i32,
+            r#type: r#type as i32,
+            counter,
+        })
     }
 
-    fn subtitle() -> String {
-        gettext("From a QR code generated by Google Authenticator")
+    /// Split the accounts into batches of migration URIs sharing the same
+    /// `batch_id`.
+    fn migration_uris(
+        otp_parameters: Vec<protobuf::migration_payload::OtpParameters>,
+        batch_id: i32,
+    ) -> Vec<String> {
+        // `usize::div_ceil` needs Rust 1.73
+        let batch_size = (otp_parameters.len() + Self::BATCH_SIZE - 1) / Self::BATCH_SIZE;
+        let mut otp_parameters = otp_parameters.into_iter();
+
+        (0..batch_size)
+            .map(|batch_index| {
+                let payload = protobuf::MigrationPayload {
+                    otp_parameters: otp_parameters.by_ref().take(Self::BATCH_SIZE).collect(),
+                    version: 1,
+                    batch_size: batch_size as i32,
+                    batch_index: batch_index as i32,
+                    batch_id,
+                };
+                let data = data_encoding::BASE64.encode(&payload.encode_to_vec());
+                format!(
+                    "otpauth-migration://offline?data={}",
+                    utf8_percent_encode(&data, NON_ALPHANUMERIC)
+                )
+            })
+            .collect()
     }
 
-    fn restore_from_data(from: &[u8], _key: Option<&str>) -> Result<Vec<Self::Item>> {
+    /// Decode the payload of an `otpauth-migration` URI.
+    fn migration_payload(from: &[u8]) -> Result<protobuf::MigrationPayload> {
         let string = String::from_utf8(from.into())?;
         let uri = Url::parse(&string)?;
 
@@ -62,12 +124,69 @@ impl Restorable for Google {
             })
         });
 
-        let data = if let Some(data) = data {
-            data
-        } else {
-            anyhow::bail!("Invalid OTP migration uri format, expected a data query parameter");
-        };
+        data.context("Invalid OTP migration uri format, expected a data query parameter")
+    }
+}
+
+impl Backupable for Google {
+    const ENCRYPTABLE: bool = false;
+    const SCANNABLE: bool = true;
+    const IDENTIFIER: &'static str = "google";
+
+    fn title() -> String {
+        gettext("Google Authenticator")
+    }
+
+    fn subtitle() -> String {
+        gettext("Into QR codes to scan with Google Authenticator")
+    }
+
+    /// The migration URIs, one per line.
+    fn backup(
+        model: &ProvidersModel,
+        selection: &Selection,
+        _key: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let mut otp_parameters = Vec::new();
+
+        for (provider, accounts) in selection.accounts(model) {
+            for account in accounts {
+                match Self::otp_parameters(&provider, &account) {
+                    Some(parameters) => otp_parameters.push(parameters),
+                    None => tracing::warn!(
+                        "Account {}/{} is not supported by Google Authenticator",
+                        provider.name(),
+                        account.name()
+                    ),
+                }
+            }
+        }
+
+        if otp_parameters.is_empty() {
+            anyhow::bail!("No account can be exported to Google Authenticator");
+        }
+
+        let uris = Self::migration_uris(otp_parameters, rand::random());
+        Ok(uris.join("\n").into_bytes())
+    }
+}
+
+impl Restorable for Google {
+    const ENCRYPTABLE: bool = false;
+    const SCANNABLE: bool = true;
+    const IDENTIFIER: &'static str = "google";
+    type Item = OTPUri;
+
+    fn title() -> String {
+        gettext("Google Authenticator")
+    }
+
+    fn subtitle() -> String {
+        gettext("From a QR code generated by Google Authenticator")
+    }
 
+    fn restore_from_data(from: &[u8], _key: Option<&str>) -> Result<Vec<Self::Item>> {
+        let data = Self::migration_payload(from)?;
         let data_len = data.otp_parameters.len();
 
         let mut restored = data.otp_parameters.into_iter().fold(
@@ -77,9 +196,13 @@ impl Restorable for Google {
                     algorithm: match otp.algorithm() {
                         protobuf::migration_payload::Algorithm::ALGO_INVALID => return folded,
                         protobuf::migration_payload::Algorithm::ALGO_SHA1 => Algorithm::SHA1,
+                        protobuf::migration_payload::Algorithm::ALGO_SHA256 => Algorithm::SHA256,
+                        protobuf::migration_payload::Algorithm::ALGO_SHA512 => Algorithm::SHA512,
+                        protobuf::migration_payload::Algorithm::ALGO_MD5 => Algorithm::MD5,
                     },
-                    digits: match otp.r#type() {
-                        protobuf::migration_payload::OtpType::OTP_HOTP => Some(otp.digits as u32),
+                    digits: match (otp.r#type(), otp.digits()) {
+                        (_, protobuf::migration_payload::DigitCount::DIGIT_COUNT_EIGHT) => Some(8),
+                        (protobuf::migration_payload::OtpType::OTP_HOTP, _) => Some(6),
                         _ => None,
                     },
                     method: match otp.r#type() {
@@ -94,6 +217,7 @@ impl Restorable for Google {
                             .trim_end_matches(|c| c == '\0' || c == '=')
                             .to_owned()
                     },
+                    pin: None,
                     label: otp.name.clone(),
                     issuer: otp.issuer.clone(),
                     period: None,
@@ -107,6 +231,21 @@ impl Restorable for Google {
 
         Ok(restored)
     }
+
+    fn batch(from: &[u8]) -> Result<Batch> {
+        let data = Self::migration_payload(from)?;
+        // Exports made of a single QR code don't always fill the batch details
+        let size = data.batch_size.max(1) as usize;
+        let index = data.batch_index.max(0) as usize;
+        if index >= size {
+            anyhow::bail!("Invalid OTP migration batch index {index}, expected less than {size}");
+        }
+        Ok(Batch {
+            id: data.batch_id,
+            index,
+            size,
+        })
+    }
 }
 
 #[allow(non_camel_case_types)]
@@ -136,6 +275,16 @@ mod protobuf {
         pub enum Algorithm {
             ALGO_INVALID = 0,
             ALGO_SHA1 = 1,
+            ALGO_SHA256 = 2,
+            ALGO_SHA512 = 3,
+            ALGO_MD5 = 4,
+        }
+
+        #[derive(Debug, Enumeration)]
+        pub enum DigitCount {
+            DIGIT_COUNT_UNSPECIFIED = 0,
+            DIGIT_COUNT_SIX = 1,
+            DIGIT_COUNT_EIGHT = 2,
         }
 
         #[derive(Debug, Enumeration)]
@@ -158,7 +307,7 @@ mod protobuf {
             #[prost(enumeration = "Algorithm")]
             #[zeroize(skip)]
             pub algorithm: i32,
-            #[prost(int32)]
+            #[prost(enumeration = "DigitCount")]
             #[zeroize(skip)]
             pub digits: i32,
             #[prost(enumeration = "OtpType")]
@@ -173,7 +322,10 @@ mod protobuf {
 
 #[cfg(test)]
 mod tests {
-    use super::{super::RestorableItem, *};
+    use super::{
+        super::{RestorableItem, ScannedBatches},
+        *,
+    };
 
     #[test]
     fn parse() {
@@ -188,4 +340,110 @@ mod tests {
         assert_eq!(items[0].digits(), None);
         assert_eq!(items[0].counter(), Some(0));
     }
+
+    fn otp_parameters(index: usize) -> protobuf::migration_payload::OtpParameters {
+        protobuf::migration_payload::OtpParameters {
+            secret: vec![index as u8; 10],
+            name: format!("account{index}"),
+            issuer: "Example".to_owned(),
+            algorithm: protobuf::migration_payload::Algorithm::ALGO_SHA256 as i32,
+            digits: protobuf::migration_payload::DigitCount::DIGIT_COUNT_EIGHT as i32,
+            r#type: protobuf::migration_payload::OtpType::OTP_HOTP as i32,
+            counter: index as i64,
+        }
+    }
+
+    #[test]
+    fn migration_uris() {
+        let uris = Google::migration_uris((0..23).map(otp_parameters).collect(), 42);
+        assert_eq!(uris.len(), 3);
+
+        let mut index = 0;
+        for (batch_index, uri) in uris.iter().enumerate() {
+            let url = Url::parse(uri).unwrap();
+            let data = url.query_pairs().find(|(key, _)| key == "data").unwrap().1;
+            let decoded = data_encoding::BASE64.decode(data.as_bytes()).unwrap();
+            let payload = protobuf::MigrationPayload::decode(&*decoded).unwrap();
+            assert_eq!(payload.version, 1);
+            assert_eq!(payload.batch_size, 3);
+            assert_eq!(payload.batch_index, batch_index as i32);
+            assert_eq!(payload.batch_id, 42);
+
+            let items = Google::restore_from_data(uri.as_bytes(), None).unwrap();
+            assert_eq!(items.len(), if batch_index < 2 { 10 } else { 3 });
+            for item in items {
+                assert_eq!(item.account(), format!("account{index}"));
+                assert_eq!(item.issuer(), "Example");
+                assert_eq!(
+                    item.secret(),
+                    data_encoding::BASE32_NOPAD.encode(&[index as u8; 10])
+                );
+                assert_eq!(item.method(), Method::HOTP);
+                assert_eq!(item.algorithm(), Algorithm::SHA256);
+                assert_eq!(item.digits(), Some(8));
+                assert_eq!(item.counter(), Some(index as u32));
+                index += 1;
+            }
+        }
+        assert_eq!(index, 23);
+    }
+
+    #[test]
+    fn totp_eight_digits() {
+        let mut parameters = otp_parameters(1);
+        parameters.digits = protobuf::migration_payload::DigitCount::DIGIT_COUNT_EIGHT as i32;
+        parameters.r#type = protobuf::migration_payload::OtpType::OTP_TOTP as i32;
+        parameters.counter = 0;
+        let uris = Google::migration_uris(vec![parameters], 42);
+        assert_eq!(uris.len(), 1);
+
+        let items = Google::restore_from_data(uris[0].as_bytes(), None).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].account(), "account1");
+        assert_eq!(items[0].method(), Method::TOTP);
+        assert_eq!(items[0].algorithm(), Algorithm::SHA256);
+        assert_eq!(items[0].digits(), Some(8));
+        assert_eq!(items[0].period(), None);
+    }
+
+    #[test]
+    fn batches() {
+        let uris = Google::migration_uris((0..23).map(otp_parameters).collect(), 42);
+        let other = Google::migration_uris((0..3).map(otp_parameters).collect(), 7);
+        let mut batches = ScannedBatches::default();
+
+        for (uri, added) in [
+            (&uris[2], true),
+            (&uris[2], false),
+            (&other[0], false),
+            (&uris[0], true),
+        ] {
+            let batch = Google::batch(uri.as_bytes()).unwrap();
+            assert_eq!(batches.add(batch, uri.clone()), added);
+        }
+        assert_eq!(batches.n_scanned(), 2);
+        assert_eq!(batches.size(), 3);
+        assert!(!batches.is_complete());
+
+        let batch = Google::batch(uris[1].as_bytes()).unwrap();
+        assert_eq!(
+            batch,
+            Batch {
+                id: 42,
+                index: 1,
+                size: 3
+            }
+        );
+        assert!(batches.add(batch, uris[1].clone()));
+        assert!(batches.is_complete());
+        assert_eq!(batches.into_codes().collect::<Vec<_>>(), uris);
+
+        // Single QR code exports
+        let data = b"otpauth-migration://offline?data=CjYKEExyJfPiZeroMa/MdF%2BnkTISE2pvaG5kb2VAZXhhbXBsZS5jb20aB0Rpc2NvcmQgASgBMAIQARgBIAA%3D";
+        let batch = Google::batch(data).unwrap();
+        assert_eq!((batch.index, batch.size), (0, 1));
+        assert!(
+            Google::batch(b"otpauth://totp/Example?secret=JRZCL47CMXVOQMNPZR2F7J4RGI").is_err()
+        );
+    }
 }
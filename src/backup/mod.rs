@@ -1,9 +1,9 @@
+use std::collections::{BTreeMap, HashSet};
+
 use anyhow::Result;
+use gtk::prelude::*;
 
-use crate::{
-    models::{keyring, Account, Algorithm, Method, ProvidersModel},
-    utils::spawn_tokio_blocking,
-};
+use crate::models::{Account, Algorithm, Method, Provider, ProvidersModel};
 
 pub enum Operation {
     Backup,
@@ -31,69 +31,235 @@ pub trait Restorable: Sized {
     /// If `key` is `None`, then the implementation should assume that the slice
     /// is unencrypted, and error if it only supports encrypted slices.
     fn restore_from_data(from: &[u8], key: Option<&str>) -> Result<Vec<Self::Item>>;
+
+    /// The batch a scanned QR code belongs to, for the backups spanning
+    /// several QR codes.
+    fn batch(_from: &[u8]) -> Result<Batch> {
+        Ok(Batch::default())
+    }
+}
+
+/// The position of a QR code in a backup spanning several of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Batch {
+    pub id: i32,
+    pub index: usize,
+    pub size: usize,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            index: 0,
+            size: 1,
+        }
+    }
+}
+
+/// Collects the scanned QR codes until every batch of the backup was seen.
+#[derive(Default)]
+pub struct ScannedBatches {
+    id: Option<i32>,
+    size: usize,
+    codes: BTreeMap<usize, String>,
+}
+
+impl ScannedBatches {
+    /// Add a scanned QR code, returns whether it wasn't seen before.
+    ///
+    /// The codes belonging to another backup than the first scanned one are
+    /// ignored.
+    pub fn add(&mut self, batch: Batch, code: String) -> bool {
+        if self.id.is_some_and(|id| id != batch.id) || batch.index >= batch.size {
+            return false;
+        }
+        self.id = Some(batch.id);
+        self.size = batch.size;
+        if self.codes.contains_key(&batch.index) {
+            return false;
+        }
+        self.codes.insert(batch.index, code);
+        true
+    }
+
+    pub fn n_scanned(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.id.is_some() && self.codes.len() == self.size
+    }
+
+    /// The scanned codes, ordered by their batch index.
+    pub fn into_codes(self) -> impl Iterator<Item = String> {
+        self.codes.into_values()
+    }
 }
 
 pub trait RestorableItem {
     fn account(&self) -> String;
     fn issuer(&self) -> String;
     fn secret(&self) -> String;
+    /// The PIN used by the Yandex & mOTP methods.
+    fn pin(&self) -> Option<String> {
+        None
+    }
     fn period(&self) -> Option<u32>;
     fn method(&self) -> Method;
     fn algorithm(&self) -> Algorithm;
     fn digits(&self) -> Option<u32>;
     fn counter(&self) -> Option<u32>;
+    // The provider details below are only kept by the formats storing more
+    // than the accounts.
+    fn website(&self) -> Option<String> {
+        None
+    }
+    fn help_url(&self) -> Option<String> {
+        None
+    }
+    fn image_uri(&self) -> Option<String> {
+        None
+    }
+    fn default_counter(&self) -> Option<u32> {
+        None
+    }
+    /// Whether the item holds an account, the formats keeping the providers
+    /// without any restore them as items of their own.
+    fn has_account(&self) -> bool {
+        true
+    }
 
-    fn restore(&self, provider: &ProvidersModel) -> Result<()> {
-        let owned_token = self.secret();
-        let token_exists =
-            spawn_tokio_blocking(async move { keyring::token_exists(&owned_token).await })?;
-        if !token_exists {
-            let provider = provider.find_or_create(
-                &self.issuer(),
-                self.period(),
-                self.method(),
-                None,
-                self.algorithm(),
-                self.digits(),
-                self.counter(),
-                None,
-                None,
-            )?;
-
-            let account =
-                Account::create(&self.account(), &self.secret(), self.counter(), &provider)?;
-            provider.add_account(&account);
-        } else {
-            tracing::info!(
-                "Account {}/{} already exists",
-                self.issuer(),
-                self.account()
-            );
-        }
-        Ok(())
+    fn restore(&self, transaction: &mut RestoreTransaction) -> Result<()> {
+        restore_item(self, transaction)
     }
 }
 
+/// The default implementation of [`RestorableItem::restore`], creating the
+/// provider if needed & the account. Whether the item conflicts with an
+/// existing account is decided beforehand, see [`PreviewItem`].
+fn restore_item<T: RestorableItem + ?Sized>(
+    item: &T,
+    transaction: &mut RestoreTransaction,
+) -> Result<()> {
+    let provider = transaction.find_or_create_provider(
+        &item.issuer(),
+        item.period(),
+        item.method(),
+        item.website(),
+        item.algorithm(),
+        item.digits(),
+        item.default_counter().or_else(|| item.counter()),
+        item.help_url(),
+        item.image_uri(),
+    )?;
+    if !item.has_account() {
+        return Ok(());
+    }
+
+    transaction.create_account(
+        &item.account(),
+        &item.secret(),
+        item.pin().as_deref(),
+        item.counter(),
+        &provider,
+    )
+}
+
 pub trait Backupable: Sized {
     /// Indicates that the GUI might need to prompt for a password.
     const ENCRYPTABLE: bool = false;
+
+    /// Indicates that the GUI needs to show the backup, one line per QR code,
+    /// instead of saving it into a file.
+    const SCANNABLE: bool = false;
+
     // Used to define the `backup.$identifier` action
     const IDENTIFIER: &'static str;
 
     fn title() -> String;
     fn subtitle() -> String;
     // if no key is provided the backup code should save it as plain text
-    fn backup(provider: &ProvidersModel, key: Option<&str>) -> Result<Vec<u8>>;
+    fn backup(model: &ProvidersModel, selection: &Selection, key: Option<&str>) -> Result<Vec<u8>>;
+}
+
+/// The accounts to include in a backup.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum Selection {
+    /// Every account, including the ones added after the selection was made.
+    #[default]
+    All,
+    /// The ids of the selected accounts.
+    Accounts(HashSet<u32>),
+}
+
+impl Selection {
+    pub fn contains(&self, account: &Account) -> bool {
+        match self {
+            Self::All => true,
+            Self::Accounts(ids) => ids.contains(&account.id()),
+        }
+    }
+
+    /// The selected accounts of `provider`.
+    pub fn provider_accounts(&self, provider: &Provider) -> Vec<Account> {
+        let accounts_model = provider.accounts_model();
+        (0..accounts_model.n_items())
+            .map(|j| accounts_model.item(j).and_downcast::<Account>().unwrap())
+            .filter(|account| self.contains(account))
+            .collect()
+    }
+
+    /// The selected accounts of `model` grouped by provider, leaving out the
+    /// providers without any.
+    pub fn accounts(&self, model: &ProvidersModel) -> Vec<(Provider, Vec<Account>)> {
+        let mut selected = Vec::new();
+        for i in 0..model.n_items() {
+            let provider = model.item(i).and_downcast::<Provider>().unwrap();
+            let accounts = self.provider_accounts(&provider);
+            if !accounts.is_empty() {
+                selected.push((provider, accounts));
+            }
+        }
+        selected
+    }
 }
 
 mod aegis;
 mod andotp;
+mod authenticator;
+pub mod auto;
 mod bitwarden;
+mod ente;
 mod freeotp;
+mod freeotp_encrypted;
 mod freeotp_json;
 mod google;
 mod legacy;
+mod preview;
+mod raivo;
+mod transaction;
+mod twofas;
 pub use self::{
-    aegis::Aegis, andotp::AndOTP, bitwarden::Bitwarden, freeotp::FreeOTP,
-    freeotp_json::FreeOTPJSON, google::Google, legacy::LegacyAuthenticator,
+    aegis::Aegis,
+    andotp::AndOTP,
+    authenticator::Authenticator,
+    bitwarden::Bitwarden,
+    ente::Ente,
+    freeotp::FreeOTP,
+    freeotp_encrypted::FreeOTPEncrypted,
+    freeotp_json::FreeOTPJSON,
+    google::Google,
+    legacy::LegacyAuthenticator,
+    preview::{
+        preview_items, restore_preview, AccountIdentity, Conflict, ExistingAccounts, PreviewItem,
+        Resolution, RestoreSummary,
+    },
+    raivo::Raivo,
+    transaction::RestoreTransaction,
+    twofas::TwoFAS,
 };
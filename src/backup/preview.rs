@@ -0,0 +1,334 @@
+//! Restore Preview
+//!
+//! Before importing the items of a backup, they are compared with the existing
+//! accounts so the user can pick which ones to import and what to do with the
+//! conflicting ones.
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use anyhow::{Context, Result};
+
+use super::{RestorableItem, RestoreTransaction};
+use crate::models::{keyring, Account, ProvidersModel};
+
+/// How a restored item clashes with an existing account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    /// An account with the same secret exists already.
+    Duplicate,
+    /// An account of the same provider has the same name but another secret.
+    SameName,
+    /// An earlier item of the backup has the same secret.
+    Repeated,
+}
+
+impl Conflict {
+    pub fn default_resolution(self) -> Resolution {
+        match self {
+            Self::Duplicate | Self::Repeated => Resolution::Skip,
+            Self::SameName => Resolution::KeepBoth,
+        }
+    }
+}
+
+/// What to do with an item conflicting with an existing account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Skip,
+    Replace,
+    KeepBoth,
+}
+
+/// What restoring an item ends up doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Skip,
+    Create,
+    /// Create the account then delete the existing one at the given index.
+    Replace(usize),
+}
+
+/// The details of an existing account needed to detect conflicts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountIdentity {
+    issuer: String,
+    name: String,
+    fingerprint: String,
+}
+
+impl AccountIdentity {
+    pub fn new(issuer: &str, name: &str, secret: &str) -> Self {
+        Self {
+            issuer: issuer.to_owned(),
+            name: name.to_owned(),
+            fingerprint: keyring::fingerprint(secret),
+        }
+    }
+}
+
+impl From<&Account> for AccountIdentity {
+    fn from(account: &Account) -> Self {
+        let mut fingerprint = account.fingerprint();
+        if fingerprint.is_empty() {
+            fingerprint = keyring::fingerprint(&account.otp().secret());
+        }
+        Self {
+            issuer: account.provider().name(),
+            name: account.name(),
+            fingerprint,
+        }
+    }
+}
+
+/// The existing accounts, indexed by their secret fingerprint and by their
+/// provider & account names.
+#[derive(Debug, Default)]
+pub struct ExistingAccounts {
+    fingerprints: HashMap<String, usize>,
+    names: HashMap<(String, String), usize>,
+}
+
+impl FromIterator<AccountIdentity> for ExistingAccounts {
+    fn from_iter<I: IntoIterator<Item = AccountIdentity>>(identities: I) -> Self {
+        let mut existing = Self::default();
+        for (index, identity) in identities.into_iter().enumerate() {
+            // Conflicts refer to the first matching account
+            existing
+                .fingerprints
+                .entry(identity.fingerprint)
+                .or_insert(index);
+            existing
+                .names
+                .entry((identity.issuer, identity.name))
+                .or_insert(index);
+        }
+        existing
+    }
+}
+
+/// An item of a backup along with what the user decided to do with it.
+pub struct PreviewItem {
+    pub item: Box<dyn RestorableItem>,
+    /// The conflict and the index of the existing account it refers to, or of
+    /// the earlier item for [`Conflict::Repeated`].
+    pub conflict: Option<(Conflict, usize)>,
+    pub selected: bool,
+    pub resolution: Resolution,
+}
+
+impl PreviewItem {
+    pub fn new(item: Box<dyn RestorableItem>, existing: &ExistingAccounts) -> Self {
+        let conflict = item
+            .has_account()
+            .then(|| find_conflict(&*item, existing))
+            .flatten();
+        Self {
+            item,
+            conflict,
+            selected: true,
+            resolution: conflict.map_or(Resolution::KeepBoth, |(conflict, _)| {
+                conflict.default_resolution()
+            }),
+        }
+    }
+
+    pub fn action(&self) -> Action {
+        if !self.selected {
+            return Action::Skip;
+        }
+        match (self.conflict, self.resolution) {
+            (None, _) | (Some(_), Resolution::KeepBoth) => Action::Create,
+            (Some(_), Resolution::Skip) => Action::Skip,
+            // There is no existing account to replace
+            (Some((Conflict::Repeated, _)), Resolution::Replace) => Action::Create,
+            (Some((_, index)), Resolution::Replace) => Action::Replace(index),
+        }
+    }
+}
+
+/// Preview `items`, comparing each of them with the existing accounts and
+/// with the items before it, as a backup can hold the same account twice.
+pub fn preview_items(
+    items: Vec<Box<dyn RestorableItem>>,
+    existing: &ExistingAccounts,
+) -> Vec<PreviewItem> {
+    // The first item of each secret
+    let mut previewed = HashMap::new();
+    items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let mut preview = PreviewItem::new(item, existing);
+            if !preview.item.has_account() {
+                return preview;
+            }
+            match previewed.entry(keyring::fingerprint(&preview.item.secret())) {
+                Entry::Vacant(entry) => {
+                    entry.insert(index);
+                }
+                Entry::Occupied(entry) => {
+                    if !matches!(preview.conflict, Some((Conflict::Duplicate, _))) {
+                        preview.conflict = Some((Conflict::Repeated, *entry.get()));
+                        preview.resolution = Conflict::Repeated.default_resolution();
+                    }
+                }
+            }
+            preview
+        })
+        .collect()
+}
+
+/// Looks for an existing account with the same secret first, then for one
+/// with the same provider and account names.
+fn find_conflict(
+    item: &dyn RestorableItem,
+    existing: &ExistingAccounts,
+) -> Option<(Conflict, usize)> {
+    let fingerprint = keyring::fingerprint(&item.secret());
+    if let Some(index) = existing.fingerprints.get(&fingerprint) {
+        return Some((Conflict::Duplicate, *index));
+    }
+
+    existing
+        .names
+        .get(&(item.issuer(), item.account()))
+        .map(|index| (Conflict::SameName, *index))
+}
+
+/// What happened to the previewed items once restored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RestoreSummary {
+    pub imported: u32,
+    pub replaced: u32,
+    pub skipped: u32,
+}
+
+/// Restore the previewed items, `accounts` being the existing accounts the
+/// conflicts refer to.
+///
+/// Either every item is restored or none of them if one fails.
+pub fn restore_preview(
+    items: &[PreviewItem],
+    accounts: &[Account],
+    model: &ProvidersModel,
+) -> Result<RestoreSummary> {
+    let mut transaction = RestoreTransaction::begin(model)?;
+    let mut summary = RestoreSummary::default();
+    for preview in items {
+        let item = &preview.item;
+        let context = || format!("Failed to restore {}/{}", item.issuer(), item.account());
+        match preview.action() {
+            Action::Skip => summary.skipped += 1,
+            Action::Create => {
+                item.restore(&mut transaction).with_context(context)?;
+                // The providers without accounts are restored silently
+                if item.has_account() {
+                    summary.imported += 1;
+                }
+            }
+            Action::Replace(index) => {
+                item.restore(&mut transaction).with_context(context)?;
+                transaction.delete_account(&accounts[index])?;
+                summary.replaced += 1;
+            }
+        }
+    }
+    transaction.commit()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::OTPUri;
+
+    fn item(uri: &str) -> Box<dyn RestorableItem> {
+        Box::new(uri.parse::<OTPUri>().unwrap())
+    }
+
+    fn existing() -> ExistingAccounts {
+        keyring::init_memory_store();
+        [
+            AccountIdentity::new("Deno", "Mason", "4SJHB4GSD43FZBAI7C2HLRJGPQ"),
+            AccountIdentity::new("SPDX", "James", "5OM4WOOGPLQEF6UGN3CPEOOLWU"),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn conflicts() {
+        let existing = existing();
+
+        let new = PreviewItem::new(
+            item("otpauth://totp/Issuu:James?secret=YOOMIXWS5GN6RTBPUFFWKTW5M4&issuer=Issuu"),
+            &existing,
+        );
+        assert_eq!(new.conflict, None);
+        assert_eq!(new.action(), Action::Create);
+
+        // The secrets are compared regardless of their case & padding
+        let duplicate = PreviewItem::new(
+            item(
+                "otpauth://totp/Other:Someone?secret=5om4woogplqef6ugn3cpeoolwu%3D%3D&issuer=Other",
+            ),
+            &existing,
+        );
+        assert_eq!(duplicate.conflict, Some((Conflict::Duplicate, 1)));
+        assert_eq!(duplicate.resolution, Resolution::Skip);
+        assert_eq!(duplicate.action(), Action::Skip);
+
+        let same_name = PreviewItem::new(
+            item("otpauth://totp/Deno:Mason?secret=JRZCL47CMXVOQMNPZR2F7J4RGI&issuer=Deno"),
+            &existing,
+        );
+        assert_eq!(same_name.conflict, Some((Conflict::SameName, 0)));
+        assert_eq!(same_name.resolution, Resolution::KeepBoth);
+        assert_eq!(same_name.action(), Action::Create);
+    }
+
+    #[test]
+    fn actions() {
+        let existing = existing();
+        let mut preview = PreviewItem::new(
+            item("otpauth://totp/Deno:Mason?secret=4SJHB4GSD43FZBAI7C2HLRJGPQ&issuer=Deno"),
+            &existing,
+        );
+        assert_eq!(preview.conflict, Some((Conflict::Duplicate, 0)));
+
+        preview.resolution = Resolution::Replace;
+        assert_eq!(preview.action(), Action::Replace(0));
+        preview.resolution = Resolution::KeepBoth;
+        assert_eq!(preview.action(), Action::Create);
+
+        preview.selected = false;
+        assert_eq!(preview.action(), Action::Skip);
+    }
+
+    #[test]
+    fn repeated() {
+        let existing = existing();
+        let mut items = preview_items(
+            vec![
+                item("otpauth://totp/Issuu:James?secret=YOOMIXWS5GN6RTBPUFFWKTW5M4&issuer=Issuu"),
+                item("otpauth://totp/Deno:Mason?secret=4SJHB4GSD43FZBAI7C2HLRJGPQ&issuer=Deno"),
+                item("otpauth://totp/Other:James?secret=yoomixws5gn6rtbpuffwktw5m4&issuer=Other"),
+                item("otpauth://totp/Deno:Mason?secret=4SJHB4GSD43FZBAI7C2HLRJGPQ&issuer=Deno"),
+            ],
+            &existing,
+        );
+
+        assert_eq!(items[0].conflict, None);
+        assert_eq!(items[0].action(), Action::Create);
+        assert_eq!(items[1].conflict, Some((Conflict::Duplicate, 0)));
+
+        // Refers to the first item with the same secret
+        assert_eq!(items[2].conflict, Some((Conflict::Repeated, 0)));
+        assert_eq!(items[2].action(), Action::Skip);
+        items[2].resolution = Resolution::Replace;
+        assert_eq!(items[2].action(), Action::Create);
+
+        // The existing accounts come first
+        assert_eq!(items[3].conflict, Some((Conflict::Duplicate, 0)));
+    }
+}
@@ -0,0 +1,199 @@
+//! Raivo OTP Import Module
+//!
+//! Raivo exports a password protected ZIP archive containing a JSON file with
+//! the accounts, the extracted JSON file can be restored as well.
+
+use std::io::{Cursor, Read};
+
+use anyhow::{Context, Result};
+use gettextrs::gettext;
+use serde::{de::Deserializer, Deserialize};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use super::{Restorable, RestorableItem};
+use crate::models::{Algorithm, Method};
+
+pub struct Raivo;
+
+#[derive(Deserialize, Zeroize, ZeroizeOnDrop)]
+#[serde(rename_all = "camelCase")]
+pub struct RaivoItem {
+    #[zeroize(skip)]
+    issuer: String,
+    #[zeroize(skip)]
+    account: String,
+    secret: String,
+    #[zeroize(skip)]
+    algorithm: Algorithm,
+    #[serde(deserialize_with = "number")]
+    #[zeroize(skip)]
+    digits: Option<u32>,
+    #[zeroize(skip)]
+    kind: Method,
+    #[serde(default, deserialize_with = "number")]
+    #[zeroize(skip)]
+    timer: Option<u32>,
+    #[serde(default, deserialize_with = "number")]
+    #[zeroize(skip)]
+    counter: Option<u32>,
+}
+
+/// Raivo stores the numbers as strings.
+fn number<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Number {
+        Int(u32),
+        Str(String),
+    }
+
+    match Option::<Number>::deserialize(deserializer)? {
+        Some(Number::Int(n)) => Ok(Some(n)),
+        Some(Number::Str(s)) if s.is_empty() => Ok(None),
+        Some(Number::Str(s)) => s.parse().map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+impl RestorableItem for RaivoItem {
+    fn account(&self) -> String {
+        self.account.clone()
+    }
+
+    fn issuer(&self) -> String {
+        self.issuer.clone()
+    }
+
+    fn secret(&self) -> String {
+        self.secret.trim_end_matches('=').to_owned()
+    }
+
+    fn period(&self) -> Option<u32> {
+        self.timer
+    }
+
+    fn method(&self) -> Method {
+        self.kind
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    fn digits(&self) -> Option<u32> {
+        self.digits
+    }
+
+    fn counter(&self) -> Option<u32> {
+        if self.method().is_event_based() {
+            self.counter
+        } else {
+            None
+        }
+    }
+}
+
+impl Raivo {
+    const ZIP_MAGIC: &'static [u8] = b"PK\x03\x04";
+
+    fn extract(from: &[u8], password: Option<&str>) -> Result<Zeroizing<Vec<u8>>> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(from))?;
+        let name = archive
+            .file_names()
+            .find(|name| name.ends_with(".json"))
+            .map(ToOwned::to_owned)
+            .context("Raivo archive does not contain a JSON file")?;
+
+        let mut file = match password {
+            Some(password) => archive
+                .by_name_decrypt(&name, password.as_bytes())?
+                .map_err(|_| anyhow::anyhow!("Cannot decrypt Raivo archive, wrong password?"))?,
+            None => archive.by_name(&name)?,
+        };
+        let mut content = Zeroizing::new(Vec::new());
+        file.read_to_end(&mut content)
+            .context("Cannot decrypt Raivo archive, wrong password?")?;
+        Ok(content)
+    }
+}
+
+impl Restorable for Raivo {
+    const ENCRYPTABLE: bool = true;
+    const SCANNABLE: bool = false;
+    const IDENTIFIER: &'static str = "raivo";
+    type Item = RaivoItem;
+
+    fn title() -> String {
+        // Translators: This is for restoring a backup from Raivo OTP.
+        gettext("Raivo OTP")
+    }
+
+    fn subtitle() -> String {
+        gettext("From a password protected ZIP archive or a JSON file")
+    }
+
+    fn restore_from_data(from: &[u8], key: Option<&str>) -> Result<Vec<Self::Item>> {
+        let items = if from.starts_with(Self::ZIP_MAGIC) {
+            let content = Self::extract(from, key)?;
+            serde_json::de::from_slice(&content)?
+        } else {
+            serde_json::de::from_slice(from)?
+        };
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_items(items: &[RaivoItem]) {
+        assert_eq!(items.len(), 3);
+
+        assert_eq!(items[0].account(), "Mason");
+        assert_eq!(items[0].issuer(), "Deno");
+        assert_eq!(items[0].secret(), "4SJHB4GSD43FZBAI7C2HLRJGPQ");
+        assert_eq!(items[0].period(), Some(30));
+        assert_eq!(items[0].method(), Method::TOTP);
+        assert_eq!(items[0].algorithm(), Algorithm::SHA1);
+        assert_eq!(items[0].digits(), Some(6));
+        assert_eq!(items[0].counter(), None);
+
+        assert_eq!(items[1].account(), "James");
+        assert_eq!(items[1].issuer(), "SPDX");
+        assert_eq!(items[1].secret(), "5OM4WOOGPLQEF6UGN3CPEOOLWU");
+        assert_eq!(items[1].period(), Some(20));
+        assert_eq!(items[1].method(), Method::TOTP);
+        assert_eq!(items[1].algorithm(), Algorithm::SHA256);
+        assert_eq!(items[1].digits(), Some(7));
+        assert_eq!(items[1].counter(), None);
+
+        assert_eq!(items[2].account(), "James");
+        assert_eq!(items[2].issuer(), "Issuu");
+        assert_eq!(items[2].secret(), "YOOMIXWS5GN6RTBPUFFWKTW5M4");
+        assert_eq!(items[2].method(), Method::HOTP);
+        assert_eq!(items[2].algorithm(), Algorithm::SHA1);
+        assert_eq!(items[2].digits(), Some(6));
+        assert_eq!(items[2].counter(), Some(1));
+    }
+
+    #[test]
+    fn parse() {
+        let data = std::fs::read("./src/backup/tests/raivo.json").unwrap();
+        let items = Raivo::restore_from_data(&data, None).unwrap();
+        assert_items(&items);
+    }
+
+    #[test]
+    fn parse_archive() {
+        let data = std::fs::read("./src/backup/tests/raivo.zip").unwrap();
+        let items = Raivo::restore_from_data(&data, Some("test")).unwrap();
+        assert_items(&items);
+
+        assert!(Raivo::restore_from_data(&data, Some("wrong")).is_err());
+        assert!(Raivo::restore_from_data(&data, None).is_err());
+    }
+}
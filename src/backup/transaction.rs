@@ -0,0 +1,172 @@
+//! Atomic Restore
+//!
+//! The database changes of a restore are made in a single transaction and the
+//! secrets stored into the keyring are removed if it gets rolled back. The
+//! in-memory models are only updated once the transaction is committed, so a
+//! broken backup doesn't leave orphan providers, accounts or secrets behind.
+
+use anyhow::Result;
+use diesel::{
+    connection::{AnsiTransactionManager, TransactionManager},
+    r2d2::{ConnectionManager, PooledConnection},
+    SqliteConnection,
+};
+
+use crate::{
+    models::{
+        database, keyring, Account, Algorithm, Method, Provider, ProviderChange, ProviderPatch,
+        ProvidersModel,
+    },
+    utils::spawn_tokio_blocking,
+};
+
+pub struct RestoreTransaction<'a> {
+    model: &'a ProvidersModel,
+    conn: PooledConnection<ConnectionManager<SqliteConnection>>,
+    /// The secrets stored in the keyring so far.
+    token_ids: Vec<String>,
+    /// The providers created so far, not part of the model yet.
+    providers: Vec<Provider>,
+    /// The changes of the existing providers, applied once committed.
+    patches: Vec<(Provider, ProviderPatch)>,
+    /// The accounts created so far, not added to their provider yet.
+    accounts: Vec<Account>,
+    /// The accounts deleted so far, their secrets are removed once committed.
+    deleted: Vec<Account>,
+    committed: bool,
+}
+
+impl<'a> RestoreTransaction<'a> {
+    pub fn begin(model: &'a ProvidersModel) -> Result<Self> {
+        let mut conn = database::connection().get()?;
+        AnsiTransactionManager::begin_transaction(&mut *conn)?;
+        Ok(Self {
+            model,
+            conn,
+            token_ids: Vec::new(),
+            providers: Vec::new(),
+            patches: Vec::new(),
+            accounts: Vec::new(),
+            deleted: Vec::new(),
+            committed: false,
+        })
+    }
+
+    /// See [`ProvidersModel::find_or_create`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn find_or_create_provider(
+        &mut self,
+        name: &str,
+        period: Option<u32>,
+        method: Method,
+        website: Option<String>,
+        algorithm: Algorithm,
+        digits: Option<u32>,
+        default_counter: Option<u32>,
+        help_url: Option<String>,
+        image_uri: Option<String>,
+    ) -> Result<Provider> {
+        let (provider, change) = self.model.find_or_insert(
+            &mut self.conn,
+            &self.providers,
+            name,
+            period,
+            method,
+            website,
+            algorithm,
+            digits,
+            default_counter,
+            help_url,
+            image_uri,
+        )?;
+        match change {
+            ProviderChange::Unchanged => (),
+            ProviderChange::Created => self.providers.push(provider.clone()),
+            ProviderChange::Updated(patch) => self.patches.push((provider.clone(), patch)),
+        }
+        Ok(provider)
+    }
+
+    /// See [`Account::create`].
+    pub fn create_account(
+        &mut self,
+        name: &str,
+        token: &str,
+        pin: Option<&str>,
+        counter: Option<u32>,
+        provider: &Provider,
+    ) -> Result<()> {
+        let token_id = Account::store_secret(name, token, pin, provider)?;
+        self.token_ids.push(token_id.clone());
+        let account = Account::insert(
+            &mut self.conn,
+            name,
+            &token_id,
+            token,
+            pin,
+            counter,
+            provider,
+        )?;
+        self.accounts.push(account);
+        Ok(())
+    }
+
+    pub fn delete_account(&mut self, account: &Account) -> Result<()> {
+        if self
+            .deleted
+            .iter()
+            .any(|deleted| deleted.id() == account.id())
+        {
+            return Ok(());
+        }
+        account.delete_with(&mut self.conn)?;
+        self.deleted.push(account.clone());
+        Ok(())
+    }
+
+    pub fn commit(mut self) -> Result<()> {
+        AnsiTransactionManager::commit_transaction(&mut *self.conn)?;
+        self.committed = true;
+
+        for (provider, patch) in self.patches.drain(..) {
+            if let Err(err) = provider.apply_patch(&patch) {
+                tracing::warn!("Failed to update provider {}: {err}", provider.name());
+            }
+        }
+        for provider in self.providers.drain(..) {
+            self.model.append(&provider);
+        }
+        for account in self.deleted.drain(..) {
+            account.remove_secret();
+            account.provider().remove_account(&account);
+        }
+        for account in self.accounts.drain(..) {
+            self.model.add_account(&account, &account.provider());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RestoreTransaction<'_> {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        tracing::info!("Rolling back the restore");
+        if let Err(err) = AnsiTransactionManager::rollback_transaction(&mut *self.conn) {
+            tracing::error!("Failed to rollback the restore transaction {err}");
+        }
+
+        let token_ids = std::mem::take(&mut self.token_ids);
+        if token_ids.is_empty() {
+            return;
+        }
+        spawn_tokio_blocking(async move {
+            for token_id in token_ids {
+                if let Err(err) = keyring::remove_token(&token_id).await {
+                    tracing::error!("Failed to remove a restored token from the keyring {err}");
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,211 @@
+//! 2FAS Import Module
+//!
+//! The `.2fas` files are JSON documents. When encrypted, the services are
+//! replaced by `servicesEncrypted`, made of the base64 encoded AES-GCM
+//! ciphertext, salt and IV separated by colons. The key is derived from the
+//! password using PBKDF2-SHA256.
+
+use aes_gcm::{aead::Aead, KeyInit};
+use anyhow::{Context, Result};
+use gettextrs::gettext;
+use serde::Deserialize;
+use sha2::Sha256;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use super::{Restorable, RestorableItem};
+use crate::models::{Algorithm, Method};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TwoFAS {
+    #[serde(default)]
+    services: Vec<TwoFASItem>,
+    services_encrypted: Option<String>,
+    schema_version: u32,
+}
+
+impl TwoFAS {
+    const ITERATIONS: u32 = 10_000;
+    const KEY_LENGTH: usize = 32;
+
+    fn decrypt_services(encrypted: &str, password: &str) -> Result<Vec<TwoFASItem>> {
+        let mut parts = encrypted.split(':').map(|part| {
+            data_encoding::BASE64
+                .decode(part.as_bytes())
+                .context("Cannot decode (base64) encrypted services")
+        });
+        let (Some(ciphertext), Some(salt), Some(iv), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            anyhow::bail!("Invalid 2FAS encrypted services");
+        };
+        let (ciphertext, salt, iv) = (ciphertext?, salt?, iv?);
+        if iv.len() != 12 {
+            anyhow::bail!("Invalid 2FAS IV length {}", iv.len());
+        }
+
+        let mut key = Zeroizing::new([0u8; Self::KEY_LENGTH]);
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, Self::ITERATIONS, key.as_mut());
+        let cipher = aes_gcm::Aes256Gcm::new_from_slice(key.as_ref())?;
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(aes_gcm::Nonce::from_slice(&iv), ciphertext.as_ref())
+                .map_err(|_| anyhow::anyhow!("Cannot decrypt 2FAS services, wrong password?"))?,
+        );
+
+        serde_json::de::from_slice(&plaintext).context("Deserialize decrypted services failed")
+    }
+}
+
+#[derive(Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct TwoFASItem {
+    #[zeroize(skip)]
+    name: String,
+    secret: String,
+    #[serde(default)]
+    #[zeroize(skip)]
+    otp: TwoFASDetails,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TwoFASDetails {
+    account: Option<String>,
+    issuer: Option<String>,
+    digits: Option<u32>,
+    period: Option<u32>,
+    algorithm: Option<Algorithm>,
+    counter: Option<u32>,
+    token_type: Option<Method>,
+}
+
+impl RestorableItem for TwoFASItem {
+    fn account(&self) -> String {
+        self.otp.account.clone().unwrap_or_default()
+    }
+
+    fn issuer(&self) -> String {
+        self.otp
+            .issuer
+            .clone()
+            .filter(|issuer| !issuer.is_empty())
+            .unwrap_or_else(|| self.name.clone())
+    }
+
+    fn secret(&self) -> String {
+        self.secret.trim_end_matches('=').to_owned()
+    }
+
+    fn period(&self) -> Option<u32> {
+        self.otp.period
+    }
+
+    fn method(&self) -> Method {
+        self.otp.token_type.unwrap_or_default()
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        self.otp.algorithm.unwrap_or_default()
+    }
+
+    fn digits(&self) -> Option<u32> {
+        self.otp.digits
+    }
+
+    fn counter(&self) -> Option<u32> {
+        if self.method().is_event_based() {
+            self.otp.counter
+        } else {
+            None
+        }
+    }
+}
+
+impl Restorable for TwoFAS {
+    const ENCRYPTABLE: bool = true;
+    const SCANNABLE: bool = false;
+    const IDENTIFIER: &'static str = "twofas";
+    type Item = TwoFASItem;
+
+    fn title() -> String {
+        // Translators: This is for restoring a backup from the 2FAS app.
+        gettext("2FAS")
+    }
+
+    fn subtitle() -> String {
+        gettext("From a plain-text or encrypted 2FAS file")
+    }
+
+    fn restore_from_data(from: &[u8], key: Option<&str>) -> Result<Vec<Self::Item>> {
+        let root: TwoFAS = serde_json::de::from_slice(from)?;
+        tracing::info!(
+            "Found 2FAS backup with schema version {}",
+            root.schema_version
+        );
+
+        match (root.services_encrypted, key) {
+            (Some(encrypted), Some(password)) => Self::decrypt_services(&encrypted, password),
+            (Some(_), None) => anyhow::bail!("Found encrypted 2FAS backup but no password given."),
+            (None, _) => Ok(root.services),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_items(items: &[TwoFASItem]) {
+        assert_eq!(items.len(), 4);
+
+        assert_eq!(items[0].account(), "Mason");
+        assert_eq!(items[0].issuer(), "Deno");
+        assert_eq!(items[0].secret(), "4SJHB4GSD43FZBAI7C2HLRJGPQ");
+        assert_eq!(items[0].period(), Some(30));
+        assert_eq!(items[0].method(), Method::TOTP);
+        assert_eq!(items[0].algorithm(), Algorithm::SHA1);
+        assert_eq!(items[0].digits(), Some(6));
+        assert_eq!(items[0].counter(), None);
+
+        assert_eq!(items[1].account(), "James");
+        assert_eq!(items[1].issuer(), "SPDX");
+        assert_eq!(items[1].secret(), "5OM4WOOGPLQEF6UGN3CPEOOLWU");
+        assert_eq!(items[1].period(), Some(20));
+        assert_eq!(items[1].method(), Method::TOTP);
+        assert_eq!(items[1].algorithm(), Algorithm::SHA256);
+        assert_eq!(items[1].digits(), Some(7));
+        assert_eq!(items[1].counter(), None);
+
+        assert_eq!(items[2].account(), "James");
+        assert_eq!(items[2].issuer(), "Issuu");
+        assert_eq!(items[2].secret(), "YOOMIXWS5GN6RTBPUFFWKTW5M4");
+        assert_eq!(items[2].method(), Method::HOTP);
+        assert_eq!(items[2].algorithm(), Algorithm::SHA1);
+        assert_eq!(items[2].digits(), Some(6));
+        assert_eq!(items[2].counter(), Some(1));
+
+        // Falls back to the service name
+        assert_eq!(items[3].account(), "Sophia");
+        assert_eq!(items[3].issuer(), "Boeing");
+        assert_eq!(items[3].secret(), "JRZCL47CMXVOQMNPZR2F7J4RGI");
+        assert_eq!(items[3].method(), Method::Steam);
+        assert_eq!(items[3].digits(), Some(5));
+    }
+
+    #[test]
+    fn parse() {
+        let data = std::fs::read("./src/backup/tests/twofas_plain.2fas").unwrap();
+        let items = TwoFAS::restore_from_data(&data, None).unwrap();
+        assert_items(&items);
+    }
+
+    #[test]
+    fn parse_encrypted() {
+        let data = std::fs::read("./src/backup/tests/twofas_encrypted.2fas").unwrap();
+        let items = TwoFAS::restore_from_data(&data, Some("test")).unwrap();
+        assert_items(&items);
+
+        assert!(TwoFAS::restore_from_data(&data, Some("wrong")).is_err());
+        assert!(TwoFAS::restore_from_data(&data, None).is_err());
+    }
+}
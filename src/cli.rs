@@ -0,0 +1,336 @@
+//! Command Line Interface
+//!
+//! A headless mode for scripts, to get the codes or to import and export
+//! backups without starting the application, e.g.
+//! `authenticator --code "GitHub/octocat"`.
+
+use std::{
+    io::{IsTerminal, Write},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use gtk::glib;
+use zeroize::Zeroizing;
+
+use crate::{
+    backup::{
+        preview_items, restore_preview, AccountIdentity, Aegis, AndOTP, Authenticator, Backupable,
+        Bitwarden, Ente, ExistingAccounts, FreeOTP, FreeOTPEncrypted, FreeOTPJSON, Google,
+        LegacyAuthenticator, Raivo, Restorable, RestorableItem, Selection, TwoFAS,
+    },
+    models::{keyring, Account, ProvidersModel, SystemClock, RUNTIME, SETTINGS},
+    utils::spawn_tokio_blocking,
+};
+
+pub const USAGE: &str = "Usage:
+  authenticator --list
+  authenticator --code <issuer/account>
+  authenticator --import <format> <file>
+  authenticator --export <format> <file>
+
+The passwords and passphrases are read from the standard input.";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// List the accounts, one `issuer/account` per line.
+    List,
+    /// Print the current code of an `issuer/account`.
+    Code(String),
+    Import {
+        format: String,
+        file: PathBuf,
+    },
+    Export {
+        format: String,
+        file: PathBuf,
+    },
+}
+
+impl Command {
+    /// Parse the command line arguments, without the program name.
+    ///
+    /// Returns `None` if the application should be started instead.
+    pub fn parse(args: &[String]) -> Result<Option<Self>> {
+        let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        let command = match args.as_slice() {
+            ["--list"] => Self::List,
+            ["--code", name] => Self::Code(name.to_string()),
+            ["--import", format, file] => Self::Import {
+                format: format.to_string(),
+                file: file.into(),
+            },
+            ["--export", format, file] => Self::Export {
+                format: format.to_string(),
+                file: file.into(),
+            },
+            [flag, ..] if ["--list", "--code", "--import", "--export"].contains(flag) => {
+                anyhow::bail!("Invalid arguments for {flag}\n\n{USAGE}")
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(command))
+    }
+}
+
+pub fn run(command: Command) -> glib::ExitCode {
+    match execute(command) {
+        Ok(()) => glib::ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err:#}");
+            glib::ExitCode::FAILURE
+        }
+    }
+}
+
+fn execute(command: Command) -> Result<()> {
+    RUNTIME.block_on(keyring::init())?;
+    unlock()?;
+
+    SystemClock::set_offset(SETTINGS.time_offset() as i64);
+    let model = ProvidersModel::default();
+    model.load();
+
+    match command {
+        Command::List => {
+            for account in accounts(&model) {
+                println!("{}/{}", account.provider().name(), account.name());
+            }
+        }
+        Command::Code(name) => {
+            let account = accounts(&model)
+                .into_iter()
+                .find(|account| format!("{}/{}", account.provider().name(), account.name()) == name)
+                .with_context(|| format!("No account named {name}"))?;
+            println!("{}", account.code().replace(' ', ""));
+        }
+        Command::Import { format, file } => {
+            let data = std::fs::read(&file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            let items = restore(&format, &data)?;
+
+            let accounts = accounts(&model);
+            let existing = accounts
+                .iter()
+                .map(AccountIdentity::from)
+                .collect::<ExistingAccounts>();
+            // The conflicts are resolved as the preview does by default, skipping
+            // the accounts that were already added
+            let items = preview_items(items, &existing);
+            let summary = restore_preview(&items, &accounts, &model)?;
+            eprintln!("{} imported, {} skipped", summary.imported, summary.skipped);
+        }
+        Command::Export { format, file } => {
+            let content = backup(&format, &model)?;
+            std::fs::write(&file, content)
+                .with_context(|| format!("Failed to write {}", file.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Check the application password, if one was set.
+fn unlock() -> Result<()> {
+    if !spawn_tokio_blocking(keyring::has_set_password())? {
+        return Ok(());
+    }
+    let password = read_secret("Password: ")?;
+    spawn_tokio_blocking(async move {
+        anyhow::ensure!(
+            keyring::is_current_password(&password).await?,
+            "Wrong password"
+        );
+        keyring::unlock_vault(&password).await
+    })
+}
+
+/// Every account of `model`.
+fn accounts(model: &ProvidersModel) -> Vec<Account> {
+    Selection::All
+        .accounts(model)
+        .into_iter()
+        .flat_map(|(_, accounts)| accounts)
+        .collect()
+}
+
+type RestoreFn = fn(&[u8], Option<&str>) -> Result<Vec<Box<dyn RestorableItem>>>;
+
+fn restorable<T: Restorable>() -> (&'static str, bool, RestoreFn)
+where
+    T::Item: 'static,
+{
+    let restore: RestoreFn = |data, key| {
+        let items = if T::SCANNABLE {
+            // One scanned code per line
+            let data = std::str::from_utf8(data)?;
+            let mut items = Vec::new();
+            for line in data.lines().filter(|line| !line.trim().is_empty()) {
+                items.extend(T::restore_from_data(line.trim().as_bytes(), key)?);
+            }
+            items
+        } else {
+            T::restore_from_data(data, key)?
+        };
+        Ok(items
+            .into_iter()
+            .map(|item| Box::new(item) as Box<dyn RestorableItem>)
+            .collect())
+    };
+    (T::IDENTIFIER, T::ENCRYPTABLE, restore)
+}
+
+fn restore(format: &str, data: &[u8]) -> Result<Vec<Box<dyn RestorableItem>>> {
+    let formats = [
+        restorable::<Authenticator>(),
+        restorable::<FreeOTP>(),
+        restorable::<FreeOTPJSON>(),
+        restorable::<FreeOTPEncrypted>(),
+        restorable::<Aegis>(),
+        restorable::<AndOTP>(),
+        restorable::<Bitwarden>(),
+        restorable::<TwoFAS>(),
+        restorable::<Raivo>(),
+        restorable::<Ente>(),
+        restorable::<Google>(),
+        restorable::<LegacyAuthenticator>(),
+    ];
+    let Some((_, encryptable, restore_from_data)) = formats
+        .iter()
+        .find(|(identifier, _, _)| *identifier == format)
+    else {
+        let identifiers = formats
+            .iter()
+            .map(|(identifier, _, _)| *identifier)
+            .collect::<Vec<_>>();
+        anyhow::bail!(
+            "Unknown format {format}, expected one of {}",
+            identifiers.join(", ")
+        );
+    };
+
+    let key = if *encryptable {
+        Some(read_secret("Passphrase (empty if not encrypted): ")?)
+    } else {
+        None
+    };
+    restore_from_data(
+        data,
+        key.as_deref()
+            .map(String::as_str)
+            .filter(|key| !key.is_empty()),
+    )
+}
+
+type BackupFn = fn(&ProvidersModel, &Selection, Option<&str>) -> Result<Vec<u8>>;
+
+fn backupable<T: Backupable>() -> (&'static str, bool, BackupFn) {
+    (T::IDENTIFIER, T::ENCRYPTABLE, T::backup)
+}
+
+fn backup(format: &str, model: &ProvidersModel) -> Result<Vec<u8>> {
+    let formats = [
+        backupable::<Authenticator>(),
+        backupable::<FreeOTP>(),
+        backupable::<Aegis>(),
+        backupable::<AndOTP>(),
+        backupable::<Google>(),
+    ];
+    let Some((_, encryptable, backup_to_data)) = formats
+        .iter()
+        .find(|(identifier, _, _)| *identifier == format)
+    else {
+        let identifiers = formats
+            .iter()
+            .map(|(identifier, _, _)| *identifier)
+            .collect::<Vec<_>>();
+        anyhow::bail!(
+            "Unknown format {format}, expected one of {}",
+            identifiers.join(", ")
+        );
+    };
+
+    let key = if *encryptable {
+        Some(read_secret("Passphrase (empty to not encrypt): ")?)
+    } else {
+        None
+    };
+    backup_to_data(
+        model,
+        &Selection::All,
+        key.as_deref()
+            .map(String::as_str)
+            .filter(|key| !key.is_empty()),
+    )
+}
+
+/// Read a line from the standard input, without echoing it when typed in a
+/// terminal.
+fn read_secret(prompt: &str) -> Result<Zeroizing<String>> {
+    let stdin = std::io::stdin();
+    let is_terminal = stdin.is_terminal();
+    if is_terminal {
+        eprint!("{prompt}");
+        std::io::stderr().flush()?;
+        set_echo(false);
+    }
+    let mut line = Zeroizing::new(String::new());
+    let result = stdin.read_line(&mut line);
+    if is_terminal {
+        set_echo(true);
+        eprintln!();
+    }
+    result.context("Failed to read from the standard input")?;
+    Ok(Zeroizing::new(
+        line.trim_end_matches(['\n', '\r']).to_owned(),
+    ))
+}
+
+fn set_echo(enabled: bool) {
+    // SAFETY: termios is a plain C struct, filled by tcgetattr before being
+    // modified
+    unsafe {
+        let mut termios = std::mem::zeroed::<libc::termios>();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+            return;
+        }
+        if enabled {
+            termios.c_lflag |= libc::ECHO;
+        } else {
+            termios.c_lflag &= !libc::ECHO;
+        }
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Command>> {
+        let args = args.iter().map(ToString::to_string).collect::<Vec<_>>();
+        Command::parse(&args)
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse(&["--list"]).unwrap(), Some(Command::List));
+        assert_eq!(
+            parse(&["--code", "GitHub/octocat"]).unwrap(),
+            Some(Command::Code("GitHub/octocat".to_owned()))
+        );
+        assert_eq!(
+            parse(&["--export", "aegis", "backup.json"]).unwrap(),
+            Some(Command::Export {
+                format: "aegis".to_owned(),
+                file: "backup.json".into()
+            })
+        );
+
+        // Started as usual
+        assert_eq!(parse(&[]).unwrap(), None);
+        assert_eq!(parse(&["otpauth://totp/Deno:Mason"]).unwrap(), None);
+
+        assert!(parse(&["--code"]).is_err());
+        assert!(parse(&["--import", "aegis"]).is_err());
+    }
+}
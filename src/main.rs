@@ -9,6 +9,7 @@ mod utils;
 use gettextrs::*;
 mod application;
 mod backup;
+mod cli;
 mod config;
 mod models;
 mod schema;
@@ -25,6 +26,23 @@ fn init_i18n() -> anyhow::Result<()> {
 }
 
 fn main() -> glib::ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match cli::Command::parse(&args) {
+        Ok(Some(command)) => {
+            // Keep the standard output for the command results
+            tracing_subscriber::fmt()
+                .with_writer(std::io::stderr)
+                .with_max_level(tracing::Level::WARN)
+                .init();
+            return cli::run(command);
+        }
+        Ok(None) => (),
+        Err(err) => {
+            eprintln!("{err}");
+            return glib::ExitCode::FAILURE;
+        }
+    }
+
     tracing_subscriber::fmt::init();
     gtk::init().expect("failed to init gtk");
     gst::init().expect("failed to init gstreamer");
@@ -7,7 +7,7 @@ use gtk::{
 };
 
 use crate::{
-    models::{database, keyring, DieselProvider, Method, OTPUri, Provider, OTP, RUNTIME},
+    models::{clipboard, database, keyring, DieselProvider, OTPUri, Provider, OTP, RUNTIME},
     schema::accounts,
     utils::spawn_tokio_blocking,
 };
@@ -19,6 +19,7 @@ struct NewAccount {
     pub token_id: String,
     pub provider_id: i32,
     pub counter: i32,
+    pub fingerprint: Option<String>,
 }
 
 #[derive(Identifiable, Queryable, Associations)]
@@ -30,6 +31,7 @@ pub struct DieselAccount {
     pub counter: i32,
     pub token_id: String,
     pub provider_id: i32,
+    pub fingerprint: Option<String>,
 }
 
 #[doc(hidden)]
@@ -47,6 +49,8 @@ mod imp {
         pub id: Cell<u32>,
         #[property(get, set)]
         pub code: RefCell<String>,
+        #[property(get, set)]
+        pub next_code: RefCell<String>,
         #[property(get, set = Self::set_name)]
         pub name: RefCell<String>,
         #[property(get, set = Self::set_counter, default = OTP::DEFAULT_COUNTER)]
@@ -54,6 +58,9 @@ mod imp {
         pub otp: OnceCell<OTP>,
         #[property(get, set, construct_only)]
         pub token_id: RefCell<String>,
+        /// See [`keyring::fingerprint`].
+        #[property(get)]
+        pub fingerprint: RefCell<String>,
         // We don't use property here as we can't mark the getter as not nullable
         pub provider: RefCell<Option<Provider>>,
     }
@@ -69,7 +76,9 @@ mod imp {
                 counter: Cell::new(OTP::DEFAULT_COUNTER),
                 name: RefCell::default(),
                 code: RefCell::default(),
+                next_code: RefCell::default(),
                 token_id: RefCell::default(),
+                fingerprint: RefCell::default(),
                 provider: RefCell::default(),
                 otp: OnceCell::default(),
             }
@@ -159,47 +168,91 @@ impl Account {
     pub fn create(
         name: &str,
         token: &str,
+        pin: Option<&str>,
         counter: Option<u32>,
         provider: &Provider,
     ) -> Result<Account> {
         let db = database::connection();
         let mut conn = db.get()?;
 
+        let token_id = Self::store_secret(name, token, pin, provider)?;
+        Self::insert(&mut conn, name, &token_id, token, pin, counter, provider)
+    }
+
+    /// Store the token & the PIN in the keyring, returning the token
+    /// identifier. Nothing is left behind if storing the PIN fails.
+    pub(crate) fn store_secret(
+        name: &str,
+        token: &str,
+        pin: Option<&str>,
+        provider: &Provider,
+    ) -> Result<String> {
         let label = format!("{} - {name}", provider.name());
         let token_send = token.to_owned();
-        let token_id = spawn_tokio_blocking(async move {
-            keyring::store(&label, &token_send)
+        let pin_send = pin.map(ToOwned::to_owned);
+        spawn_tokio_blocking(async move {
+            let token_id = keyring::store(&label, &token_send)
                 .await
-                .context("Failed to save token")
-        })?;
+                .context("Failed to save token")?;
+            if let Some(pin) = pin_send {
+                if let Err(err) = keyring::store_pin(&label, &token_id, &pin).await {
+                    keyring::remove_token(&token_id).await?;
+                    return Err(err.context("Failed to save PIN"));
+                }
+            }
+            anyhow::Ok(token_id)
+        })
+    }
 
+    /// Insert the account whose secret was stored as `token_id` using `conn`,
+    /// for example as part of a transaction.
+    pub(crate) fn insert(
+        conn: &mut SqliteConnection,
+        name: &str,
+        token_id: &str,
+        token: &str,
+        pin: Option<&str>,
+        counter: Option<u32>,
+        provider: &Provider,
+    ) -> Result<Account> {
+        let fingerprint = keyring::fingerprint(token);
         diesel::insert_into(accounts::table)
             .values(NewAccount {
                 name: name.to_string(),
-                token_id,
+                token_id: token_id.to_owned(),
                 provider_id: provider.id() as i32,
                 counter: counter.unwrap_or_else(|| provider.default_counter()) as i32,
+                fingerprint: Some(fingerprint.clone()),
             })
-            .execute(&mut conn)?;
+            .execute(conn)?;
 
         accounts::table
             .order(accounts::columns::id.desc())
-            .first::<DieselAccount>(&mut conn)
+            .first::<DieselAccount>(conn)
             .map_err(From::from)
             .map(|account| {
-                Self::new(
+                let account = Self::new(
                     account.id as u32,
                     &account.name,
                     &account.token_id,
                     account.counter as u32,
                     provider,
                     Some(token),
+                    pin,
                 )
-                .unwrap()
+                .unwrap();
+                account.imp().fingerprint.replace(fingerprint);
+                account
             })
     }
 
-    pub fn load(p: &Provider) -> Result<impl Iterator<Item = Self>> {
+    /// Load the accounts of `p`, with their secrets taken from `secrets`, and
+    /// whether their stored fingerprint is outdated, see
+    /// [`Account::store_fingerprints`].
+    pub fn load<'a>(
+        p: &Provider,
+        secrets: &'a keyring::Secrets,
+    ) -> Result<impl Iterator<Item = (Self, bool)> + 'a> {
         let db = database::connection();
         let mut conn = db.get()?;
 
@@ -214,10 +267,14 @@ impl Account {
                     &account.token_id,
                     account.counter as u32,
                     &p,
-                    None,
+                    secrets.tokens.get(&account.token_id).map(|token| token.as_str()),
+                    secrets.pins.get(&account.token_id).map(|pin| pin.as_str()),
                 )
                 {
-                    Ok(account) => Some(account),
+                    Ok(loaded) => {
+                        let is_outdated = loaded.load_fingerprint(account.fingerprint);
+                        Some((loaded, is_outdated))
+                    }
                     Err(e) => {
                         let name = account.name;
                         let provider = p.name();
@@ -237,6 +294,7 @@ impl Account {
         counter: u32,
         provider: &Provider,
         secret: Option<&str>,
+        pin: Option<&str>,
     ) -> Result<Account> {
         let account = glib::Object::builder::<Self>()
             .property("id", id)
@@ -256,19 +314,75 @@ impl Account {
                 })
             })?
         };
-        let otp = OTP::from_str(&secret, provider.algorithm(), provider.digits())?;
+        let pin = if let Some(p) = pin {
+            Some(p.to_string())
+        } else if provider.method().requires_pin() {
+            let token_id = token_id.to_owned();
+            spawn_tokio_blocking(async move { keyring::pin(&token_id).await })?
+        } else {
+            None
+        };
+        let otp = OTP::from_str(&secret, provider.algorithm(), provider.digits())?
+            .with_pin(pin.as_deref());
         account.imp().otp.set(otp).unwrap();
         account.generate_otp();
         Ok(account)
     }
 
+    /// Use the stored fingerprint, it is computed again for the accounts that
+    /// were added before the fingerprints were introduced or whose fingerprint
+    /// was computed with a previous key. Returns whether it was.
+    fn load_fingerprint(&self, fingerprint: Option<String>) -> bool {
+        let (fingerprint, is_outdated) = match fingerprint {
+            Some(fingerprint) if keyring::is_current_fingerprint(&fingerprint) => {
+                (fingerprint, false)
+            }
+            _ => (keyring::fingerprint(&self.otp().secret()), true),
+        };
+        self.imp().fingerprint.replace(fingerprint);
+        is_outdated
+    }
+
+    /// Store the fingerprints of the `outdated` accounts, which were computed
+    /// again while loading them, in a single transaction.
+    pub(crate) fn store_fingerprints(
+        conn: &mut SqliteConnection,
+        outdated: &[Account],
+    ) -> Result<()> {
+        conn.transaction(|conn| {
+            for account in outdated {
+                let target = accounts::table.filter(accounts::columns::id.eq(account.id() as i32));
+                diesel::update(target)
+                    .set(accounts::columns::fingerprint.eq(account.fingerprint()))
+                    .execute(conn)?;
+            }
+            diesel::QueryResult::Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// The identifier of the account whose secret has `fingerprint`, if any.
+    pub fn find_by_fingerprint(fingerprint: &str) -> Result<Option<u32>> {
+        let db = database::connection();
+        let mut conn = db.get()?;
+
+        let id = accounts::table
+            .filter(accounts::columns::fingerprint.eq(fingerprint))
+            .select(accounts::columns::id)
+            .first::<i32>(&mut conn)
+            .optional()?;
+        Ok(id.map(|id| id as u32))
+    }
+
     pub fn generate_otp(&self) {
         let provider = self.provider();
 
-        let otp_password = match provider.method() {
-            Method::Steam => self.otp().steam(None),
-            Method::TOTP => self.otp().totp_formatted(Some(provider.period())),
-            Method::HOTP => self.otp().hotp_formatted(self.counter() as u64),
+        let method = provider.method();
+        let otp_password = if method.is_event_based() {
+            self.otp().hotp_formatted(self.counter() as u64)
+        } else {
+            self.otp()
+                .generate_at(method, provider.period(), &*provider.clock())
         };
 
         let label = match otp_password {
@@ -280,6 +394,32 @@ impl Account {
         };
 
         self.set_code(label);
+
+        // Computed ahead of time so it can be shown before the current one expires
+        if method.is_time_based() {
+            match self
+                .otp()
+                .generate_next_at(method, provider.period(), &*provider.clock())
+            {
+                Ok(next_code) => self.set_next_code(next_code),
+                Err(err) => tracing::warn!("Failed to generate the next OTP {}", err),
+            }
+        }
+    }
+
+    /// Looks for `code` within `window` steps around the current one.
+    ///
+    /// Returns the offset in steps of the matching code, if any.
+    pub fn verify(&self, code: &str, window: u32) -> Result<Option<i64>> {
+        let provider = self.provider();
+        let method = provider.method();
+        if method.is_event_based() {
+            self.otp()
+                .verify_counter(code, method, self.counter() as u64, window)
+        } else {
+            self.otp()
+                .verify(code, method, provider.period(), window, &*provider.clock())
+        }
     }
 
     /// Increment the internal counter in case of a HOTP account
@@ -298,11 +438,9 @@ impl Account {
     }
 
     pub fn copy_otp(&self) {
-        let display = gtk::gdk::Display::default().unwrap();
-        let clipboard = display.clipboard();
         // The codes come with the white space shown in the label.
         let code = self.code().replace(' ', "");
-        clipboard.set_text(&code);
+        clipboard::copy(&code);
 
         // Indirectly increment the counter once the token was copied
         if self.provider().method().is_event_based() {
@@ -310,6 +448,11 @@ impl Account {
         }
     }
 
+    pub fn copy_next_otp(&self) {
+        let code = self.next_code().replace(' ', "");
+        clipboard::copy(&code);
+    }
+
     pub fn provider(&self) -> Provider {
         self.imp().provider.borrow().clone().unwrap()
     }
@@ -336,16 +479,26 @@ impl Account {
     }
 
     pub fn delete(&self) -> Result<()> {
+        self.remove_secret();
+        let db = database::connection();
+        let mut conn = db.get()?;
+        self.delete_with(&mut conn)
+    }
+
+    /// Delete the account from the database using `conn`, the secret is kept
+    /// in the keyring until [`Account::remove_secret`] is called.
+    pub(crate) fn delete_with(&self, conn: &mut SqliteConnection) -> Result<()> {
+        diesel::delete(accounts::table.filter(accounts::columns::id.eq(self.id() as i32)))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub(crate) fn remove_secret(&self) {
         let token_id = self.token_id();
         RUNTIME.spawn(async move {
             if let Err(err) = keyring::remove_token(&token_id).await {
                 tracing::error!("Failed to remove the token from secret service {}", err);
             }
         });
-        let db = database::connection();
-        let mut conn = db.get()?;
-        diesel::delete(accounts::table.filter(accounts::columns::id.eq(self.id() as i32)))
-            .execute(&mut conn)?;
-        Ok(())
     }
 }
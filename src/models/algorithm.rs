@@ -2,9 +2,10 @@ use std::{str::FromStr, string::ToString};
 
 use gettextrs::gettext;
 use gtk::glib;
-use ring::hmac;
 use serde::{de::Deserializer, ser::Serializer, Deserialize, Serialize};
 
+use super::OTP;
+
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Default, Eq, PartialEq, Clone, Copy, glib::Enum)]
 #[repr(u32)]
@@ -16,6 +17,9 @@ pub enum Method {
     #[enum_value(name = "HOTP")]
     HOTP = 1,
     Steam = 2,
+    Yandex = 3,
+    #[enum_value(name = "mOTP", nick = "motp")]
+    MOTP = 4,
 }
 
 impl Serialize for Method {
@@ -32,7 +36,7 @@ impl<'de> Deserialize<'de> for Method {
     where
         D: Deserializer<'de>,
     {
-        Ok(Self::from_str(&String::deserialize(deserializer)?).unwrap())
+        Self::from_str(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
     }
 }
 
@@ -41,6 +45,8 @@ impl From<u32> for Method {
         match u {
             1 => Self::HOTP,
             2 => Self::Steam,
+            3 => Self::Yandex,
+            4 => Self::MOTP,
             _ => Self::default(),
         }
     }
@@ -48,19 +54,46 @@ impl From<u32> for Method {
 
 impl Method {
     pub fn is_time_based(self) -> bool {
-        matches!(self, Self::TOTP | Self::Steam)
+        matches!(self, Self::TOTP | Self::Steam | Self::Yandex | Self::MOTP)
     }
 
     pub fn is_event_based(self) -> bool {
         matches!(self, Self::HOTP)
     }
 
+    /// Whether the method needs a PIN in addition to the secret.
+    pub fn requires_pin(self) -> bool {
+        matches!(self, Self::Yandex | Self::MOTP)
+    }
+
+    pub fn default_period(self) -> u32 {
+        match self {
+            Self::Steam => OTP::STEAM_DEFAULT_PERIOD,
+            Self::Yandex => OTP::YANDEX_DEFAULT_PERIOD,
+            Self::MOTP => OTP::MOTP_DEFAULT_PERIOD,
+            _ => OTP::DEFAULT_PERIOD,
+        }
+    }
+
+    pub fn default_digits(self) -> u32 {
+        match self {
+            Self::Steam => OTP::STEAM_DEFAULT_DIGITS,
+            Self::Yandex => OTP::YANDEX_DEFAULT_DIGITS,
+            Self::MOTP => OTP::MOTP_DEFAULT_DIGITS,
+            _ => OTP::DEFAULT_DIGITS,
+        }
+    }
+
     pub fn to_locale_string(self) -> String {
         match self {
             Self::HOTP => gettext("Counter-based"),
             Self::TOTP => gettext("Time-based"),
             // Translators: Steam refers to the gaming application by Valve.
             Self::Steam => gettext("Steam"),
+            // Translators: Yandex refers to the Russian web services company.
+            Self::Yandex => gettext("Yandex"),
+            // Translators: mOTP refers to the Mobile-OTP algorithm.
+            Self::MOTP => gettext("mOTP"),
         }
     }
 }
@@ -72,6 +105,8 @@ impl FromStr for Method {
             "totp" | "otp" => Ok(Self::TOTP),
             "hotp" => Ok(Self::HOTP),
             "steam" => Ok(Self::Steam),
+            "yandex" | "yaotp" => Ok(Self::Yandex),
+            "motp" => Ok(Self::MOTP),
             _ => anyhow::bail!("Unsupported Method"),
         }
     }
@@ -83,6 +118,8 @@ impl ToString for Method {
             Self::TOTP => "totp",
             Self::HOTP => "hotp",
             Self::Steam => "steam",
+            Self::Yandex => "yandex",
+            Self::MOTP => "motp",
         }
         .to_string()
     }
@@ -100,6 +137,12 @@ pub enum Algorithm {
     SHA256 = 1,
     #[enum_value(name = "SHA512")]
     SHA512 = 2,
+    #[enum_value(name = "SHA224")]
+    SHA224 = 3,
+    #[enum_value(name = "SHA384")]
+    SHA384 = 4,
+    #[enum_value(name = "MD5")]
+    MD5 = 5,
 }
 
 impl Serialize for Algorithm {
@@ -116,7 +159,7 @@ impl<'de> Deserialize<'de> for Algorithm {
     where
         D: Deserializer<'de>,
     {
-        Ok(Self::from_str(&String::deserialize(deserializer)?).unwrap())
+        Self::from_str(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
     }
 }
 
@@ -126,6 +169,9 @@ impl Algorithm {
             Self::SHA1 => gettext("SHA-1"),
             Self::SHA256 => gettext("SHA-256"),
             Self::SHA512 => gettext("SHA-512"),
+            Self::SHA224 => gettext("SHA-224"),
+            Self::SHA384 => gettext("SHA-384"),
+            Self::MD5 => gettext("MD5"),
         }
     }
 }
@@ -133,10 +179,16 @@ impl Algorithm {
 impl FromStr for Algorithm {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_ref() {
+        // Accept the various spellings used by the other applications, e.g. SHA-256 or
+        // HmacSHA256
+        let s = s.to_lowercase().replace(['-', '_'], "");
+        match s.trim_start_matches("hmac") {
             "sha1" => Ok(Self::SHA1),
             "sha256" => Ok(Self::SHA256),
             "sha512" => Ok(Self::SHA512),
+            "sha224" => Ok(Self::SHA224),
+            "sha384" => Ok(Self::SHA384),
+            "md5" => Ok(Self::MD5),
             _ => anyhow::bail!("Unsupported HMAC-algorithm"),
         }
     }
@@ -148,26 +200,22 @@ impl ToString for Algorithm {
             Self::SHA1 => "sha1",
             Self::SHA256 => "sha256",
             Self::SHA512 => "sha512",
+            Self::SHA224 => "sha224",
+            Self::SHA384 => "sha384",
+            Self::MD5 => "md5",
         }
         .to_string()
     }
 }
 
-impl From<Algorithm> for hmac::Algorithm {
-    fn from(h: Algorithm) -> Self {
-        match h {
-            Algorithm::SHA1 => hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY,
-            Algorithm::SHA256 => hmac::HMAC_SHA256,
-            Algorithm::SHA512 => hmac::HMAC_SHA512,
-        }
-    }
-}
-
 impl From<u32> for Algorithm {
     fn from(u: u32) -> Self {
         match u {
             1 => Self::SHA256,
             2 => Self::SHA512,
+            3 => Self::SHA224,
+            4 => Self::SHA384,
+            5 => Self::MD5,
             _ => Self::default(),
         }
     }
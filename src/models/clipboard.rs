@@ -0,0 +1,76 @@
+//! Copying the codes into the clipboard, they are cleared from it once the
+//! `clipboard-clear-timeout` setting elapsed.
+
+use std::cell::RefCell;
+
+use gtk::{
+    gdk,
+    glib::{self, clone},
+    prelude::*,
+};
+
+use super::SETTINGS;
+use crate::utils::spawn;
+
+/// The mime type used by the password managers to hint the clipboard managers
+/// to not keep the content in their history.
+const PASSWORD_MANAGER_HINT: &str = "x-kde-passwordManagerHint";
+
+thread_local! {
+    static CLEAR_TIMEOUT: RefCell<Option<glib::SourceId>> = RefCell::default();
+}
+
+/// Copy `code` into the clipboard of the default display.
+pub fn copy(code: &str) {
+    let Some(display) = gdk::Display::default() else {
+        return;
+    };
+    let clipboard = display.clipboard();
+    let content = gdk::ContentProvider::new_union(&[
+        gdk::ContentProvider::for_value(&code.to_value()),
+        gdk::ContentProvider::for_bytes(
+            PASSWORD_MANAGER_HINT,
+            &glib::Bytes::from_static(b"secret"),
+        ),
+    ]);
+    if let Err(err) = clipboard.set_content(Some(&content)) {
+        tracing::error!("Failed to copy the code {err}");
+        return;
+    }
+    clear_later(&clipboard, code);
+}
+
+/// Clear `clipboard` once the timeout elapsed, if it still contains `code`.
+pub fn clear_later(clipboard: &gdk::Clipboard, code: &str) {
+    let timeout = SETTINGS.clipboard_clear_timeout();
+    cancel_clear();
+    if timeout == 0 {
+        return;
+    }
+    let code = code.to_owned();
+    let source_id = glib::timeout_add_seconds_local_once(
+        timeout,
+        clone!(@weak clipboard => move || {
+            CLEAR_TIMEOUT.with(|cell| cell.take());
+            spawn(clone!(@weak clipboard => async move {
+                // Something else might have been copied since
+                match clipboard.read_text_future().await {
+                    Ok(Some(text)) if text == code => {
+                        if let Err(err) = clipboard.set_content(gdk::ContentProvider::NONE) {
+                            tracing::error!("Failed to clear the clipboard {err}");
+                        }
+                    }
+                    Ok(_) => (),
+                    Err(err) => tracing::warn!("Failed to read the clipboard {err}"),
+                }
+            }));
+        }),
+    );
+    CLEAR_TIMEOUT.with(|cell| cell.replace(Some(source_id)));
+}
+
+fn cancel_clear() {
+    if let Some(source_id) = CLEAR_TIMEOUT.with(|cell| cell.take()) {
+        source_id.remove();
+    }
+}
@@ -0,0 +1,93 @@
+#[cfg(test)]
+use std::{cell::Cell, rc::Rc};
+use std::{
+    fmt::Debug,
+    sync::atomic::{AtomicI64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Offset in seconds added to the system clock when computing time based
+/// codes, used to compensate a drifting clock.
+static TIME_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+/// Source of the time used to compute the time based codes.
+pub trait Clock: Debug {
+    /// The current UNIX timestamp in seconds.
+    fn timestamp(&self) -> u64;
+}
+
+/// The system clock with the user defined time offset applied.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl SystemClock {
+    pub fn offset() -> i64 {
+        TIME_OFFSET.load(Ordering::Relaxed)
+    }
+
+    pub fn set_offset(offset: i64) {
+        TIME_OFFSET.store(offset, Ordering::Relaxed);
+    }
+}
+
+impl Clock for SystemClock {
+    fn timestamp(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        now.saturating_add_signed(Self::offset())
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time.
+#[cfg(test)]
+#[derive(Debug, Default, Clone)]
+pub struct FakeClock(Rc<Cell<u64>>);
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new(timestamp: u64) -> Self {
+        Self(Rc::new(Cell::new(timestamp)))
+    }
+
+    pub fn set(&self, timestamp: u64) {
+        self.0.set(timestamp);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.0.set(self.0.get() + seconds);
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn timestamp(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, FakeClock, SystemClock};
+
+    #[test]
+    fn fake_clock() {
+        let clock = FakeClock::new(59);
+        let shared = clock.clone();
+        clock.advance(1);
+        assert_eq!(shared.timestamp(), 60);
+        shared.set(1111111109);
+        assert_eq!(clock.timestamp(), 1111111109);
+    }
+
+    #[test]
+    fn system_clock_offset() {
+        let timestamp = SystemClock.timestamp();
+        SystemClock::set_offset(-120);
+        let with_offset = SystemClock.timestamp();
+        SystemClock::set_offset(0);
+        assert!(with_offset + 120 >= timestamp);
+        assert!(with_offset + 120 < timestamp + 5);
+    }
+}
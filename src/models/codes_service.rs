@@ -0,0 +1,379 @@
+//! The `com.belmoussaoui.Authenticator.Codes` D-Bus interface, letting other
+//! applications like browser extensions request the codes of the accounts.
+//!
+//! Every request is forwarded to the application, which asks the user whether
+//! the calling application should be allowed to access the codes and remembers
+//! the answer. The requests of a caller are denied while the user is asked.
+//!
+//! The callers are identified from their process, pinned with a pidfd so that
+//! its PID can't be reused by another process in the meantime. When
+//! Authenticator runs sandboxed, the processes of the host are not visible to
+//! it, such callers can't be identified and the answer given to them is only
+//! kept for a short while instead.
+
+use std::{
+    collections::HashMap,
+    io,
+    os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+use futures_channel::{
+    mpsc::{UnboundedReceiver as Receiver, UnboundedSender as Sender},
+    oneshot,
+};
+use zbus::{
+    dbus_interface, fdo,
+    zvariant::{Fd, OwnedValue},
+    Connection, MessageHeader,
+};
+
+use crate::config;
+
+/// The executables running the scripts passed to them, which are what has to
+/// be identified instead.
+const INTERPRETERS: &[&str] = &[
+    "bash", "bun", "dash", "deno", "fish", "gjs", "ksh", "lua", "node", "nodejs", "perl", "php",
+    "python", "ruby", "sh", "tclsh", "zsh",
+];
+
+/// An account, as its identifier, issuer & name.
+pub type CodesAccount = (String, String, String);
+
+/// The application that sent a request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CodesCaller {
+    /// The Flatpak ID of a sandboxed application, the executable otherwise,
+    /// followed by the script it runs for the interpreters.
+    App(String),
+    /// The process of the caller is not visible from the sandbox Authenticator
+    /// runs in.
+    Unknown,
+}
+
+pub enum CodesAction {
+    ListAccounts(CodesCaller, oneshot::Sender<fdo::Result<Vec<CodesAccount>>>),
+    /// The current code of an account & the seconds it is still valid for, 0
+    /// for the counter based accounts.
+    Code(
+        CodesCaller,
+        String,
+        oneshot::Sender<fdo::Result<(String, u32)>>,
+    ),
+    /// Increment the counter of a counter based account, replying with the
+    /// new code.
+    IncrementCounter(CodesCaller, String, oneshot::Sender<fdo::Result<String>>),
+}
+
+pub struct Codes {
+    sender: Sender<CodesAction>,
+}
+
+impl Codes {
+    async fn request<T>(
+        &self,
+        action: impl FnOnce(oneshot::Sender<fdo::Result<T>>) -> CodesAction,
+    ) -> fdo::Result<T> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .unbounded_send(action(sender))
+            .map_err(|_| fdo::Error::Failed("The application is shutting down".to_owned()))?;
+        receiver
+            .await
+            .map_err(|_| fdo::Error::Failed("The request was cancelled".to_owned()))?
+    }
+}
+
+#[dbus_interface(name = "com.belmoussaoui.Authenticator.Codes")]
+impl Codes {
+    async fn list_accounts(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<Vec<CodesAccount>> {
+        let caller = caller(connection, &header).await?;
+        self.request(|sender| CodesAction::ListAccounts(caller, sender))
+            .await
+    }
+
+    async fn get_code(
+        &self,
+        id: String,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<(String, u32)> {
+        let caller = caller(connection, &header).await?;
+        self.request(|sender| CodesAction::Code(caller, id, sender))
+            .await
+    }
+
+    async fn increment_counter(
+        &self,
+        id: String,
+        #[zbus(header)] header: MessageHeader<'_>,
+        #[zbus(connection)] connection: &Connection,
+    ) -> fdo::Result<String> {
+        let caller = caller(connection, &header).await?;
+        self.request(|sender| CodesAction::IncrementCounter(caller, id, sender))
+            .await
+    }
+}
+
+/// Identifies the application that sent a message.
+async fn caller(connection: &Connection, header: &MessageHeader<'_>) -> fdo::Result<CodesCaller> {
+    let sender = header
+        .sender()?
+        .ok_or_else(|| fdo::Error::AccessDenied("Unknown caller".to_owned()))?;
+    // Not using `DBusProxy::get_connection_credentials` as it doesn't expose
+    // the `ProcessFD` of the caller
+    let reply = connection
+        .call_method(
+            Some("org.freedesktop.DBus"),
+            "/org/freedesktop/DBus",
+            Some("org.freedesktop.DBus"),
+            "GetConnectionCredentials",
+            &sender.as_str(),
+        )
+        .await?;
+    let credentials = reply.body::<HashMap<String, OwnedValue>>()?;
+    let unidentified = |err: io::Error| {
+        fdo::Error::AccessDenied(format!("The caller could not be identified {err}"))
+    };
+
+    let process = if let Some(fd) = credentials.get("ProcessFD") {
+        let fd = Fd::try_from(fd).map_err(zbus::Error::from)?;
+        Process::from_pidfd(fd.as_raw_fd()).map_err(unidentified)?
+    } else if Path::new("/.flatpak-info").exists() {
+        // The PID is the one of the host, it doesn't match the same process in
+        // the PID namespace of the sandbox
+        None
+    } else {
+        let pid = credentials
+            .get("ProcessID")
+            .and_then(|pid| u32::try_from(pid).ok())
+            .ok_or_else(|| fdo::Error::AccessDenied("Unknown caller process".to_owned()))?;
+        // The bus doesn't pin the process, it could have exited and its PID
+        // been reused before being opened, which can't be avoided
+        Some(Process::open(pid).map_err(unidentified)?)
+    };
+    match process {
+        Some(process) => process.app_id().map(CodesCaller::App).map_err(unidentified),
+        None => Ok(CodesCaller::Unknown),
+    }
+}
+
+/// A process pinned by a pidfd.
+struct Process {
+    pidfd: OwnedFd,
+    pid: i32,
+}
+
+impl Process {
+    /// Returns `None` if the process is not visible from the PID namespace of
+    /// Authenticator.
+    fn from_pidfd(fd: RawFd) -> io::Result<Option<Self>> {
+        // The file descriptor is owned by the message it was received with
+        let pidfd = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+        let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", pidfd.as_raw_fd()))?;
+        match fdinfo_pid(&fdinfo) {
+            Some(0) => Ok(None),
+            Some(pid) if pid > 0 => Ok(Some(Self { pidfd, pid })),
+            _ => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "The process exited",
+            )),
+        }
+    }
+
+    fn open(pid: u32) -> io::Result<Self> {
+        let pid =
+            i32::try_from(pid).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let pidfd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        Ok(Self { pidfd, pid })
+    }
+
+    /// As the PID of a running process can't be reused, anything read from
+    /// its `/proc` directory before is about this process.
+    fn is_running(&self) -> bool {
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.pidfd.as_raw_fd(),
+                0,
+                std::ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+        ret == 0
+    }
+
+    fn app_id(&self) -> io::Result<String> {
+        let path = PathBuf::from(format!("/proc/{}", self.pid));
+        let app_id = match std::fs::read_to_string(path.join("root/.flatpak-info")) {
+            Ok(info) => flatpak_app_id(&info).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Invalid Flatpak information")
+            })?,
+            Err(_) => {
+                let executable = std::fs::read_link(path.join("exe"))?;
+                let cmdline = std::fs::read(path.join("cmdline"))?;
+                let cwd = std::fs::read_link(path.join("cwd"))?;
+                let args = String::from_utf8_lossy(&cmdline);
+                let args = args.trim_end_matches('\0').split('\0').collect::<Vec<_>>();
+                executable_id(&executable, &args, &cwd)
+            }
+        };
+        if !self.is_running() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "The process exited",
+            ));
+        }
+        Ok(app_id)
+    }
+}
+
+/// The PID of the process referred to by a pidfd, from its `fdinfo`. 0 if it
+/// is not visible from the PID namespace, -1 once it exited.
+fn fdinfo_pid(fdinfo: &str) -> Option<i32> {
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("Pid:"))
+        .and_then(|pid| pid.trim().parse().ok())
+}
+
+/// The executable, followed by the script for the interpreters. Their whole
+/// command line is used if the script can't be told apart from their
+/// options, e.g. for `python -c`.
+fn executable_id(executable: &Path, args: &[&str], cwd: &Path) -> String {
+    let is_interpreter = executable
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'))
+        .is_some_and(|name| INTERPRETERS.contains(&name));
+    let executable = executable.display();
+    if !is_interpreter {
+        return executable.to_string();
+    }
+    match args.get(1..).unwrap_or_default() {
+        [] => executable.to_string(),
+        [script, ..] if !script.starts_with('-') => {
+            format!("{executable} {}", cwd.join(script).display())
+        }
+        args => format!("{executable} {}", args.join(" ")),
+    }
+}
+
+fn flatpak_app_id(info: &str) -> Option<String> {
+    let mut in_application = false;
+    for line in info.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_application = line == "[Application]";
+        } else if let Some(name) = line.strip_prefix("name=").filter(|_| in_application) {
+            return Some(name.to_owned());
+        }
+    }
+    None
+}
+
+/// Export the interface, the connection has to be kept around for as long as
+/// it should be served.
+pub async fn start() -> anyhow::Result<(Connection, Receiver<CodesAction>)> {
+    let (sender, receiver) = futures_channel::mpsc::unbounded();
+    let name = format!("{}.Codes", config::APP_ID);
+    let connection = zbus::ConnectionBuilder::session()?
+        .name(name)?
+        .serve_at(config::OBJECT_PATH, Codes { sender })?
+        .build()
+        .await?;
+    tracing::info!("Codes service started");
+    Ok((connection, receiver))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flatpak_info() {
+        let info = "[Application]
+name=org.mozilla.firefox
+runtime=runtime/org.freedesktop.Platform/x86_64/23.08
+
+[Instance]
+instance-id=1234
+";
+        assert_eq!(flatpak_app_id(info).as_deref(), Some("org.mozilla.firefox"));
+
+        let info = "[Runtime]
+name=org.freedesktop.Platform
+";
+        assert_eq!(flatpak_app_id(info), None);
+    }
+
+    #[test]
+    fn interpreters() {
+        let cwd = Path::new("/home/user");
+        assert_eq!(
+            executable_id(
+                Path::new("/usr/bin/firefox"),
+                &["firefox", "--new-window"],
+                cwd
+            ),
+            "/usr/bin/firefox"
+        );
+        assert_eq!(
+            executable_id(
+                Path::new("/usr/bin/python3.12"),
+                &["python3", "bin/codes.py", "--all"],
+                cwd
+            ),
+            "/usr/bin/python3.12 /home/user/bin/codes.py"
+        );
+        assert_eq!(
+            executable_id(Path::new("/usr/bin/bash"), &["bash", "/opt/codes.sh"], cwd),
+            "/usr/bin/bash /opt/codes.sh"
+        );
+        assert_eq!(
+            executable_id(
+                Path::new("/usr/bin/python3"),
+                &["python3", "-c", "print(1)"],
+                cwd
+            ),
+            "/usr/bin/python3 -c print(1)"
+        );
+        assert_eq!(
+            executable_id(Path::new("/usr/bin/node"), &["node"], cwd),
+            "/usr/bin/node"
+        );
+    }
+
+    #[test]
+    fn pidfd() {
+        let pid = std::process::id();
+        let process = Process::open(pid).unwrap();
+        assert!(process.is_running());
+        let fdinfo =
+            std::fs::read_to_string(format!("/proc/self/fdinfo/{}", process.pidfd.as_raw_fd()))
+                .unwrap();
+        assert_eq!(fdinfo_pid(&fdinfo), Some(pid as i32));
+
+        let process = Process::from_pidfd(process.pidfd.as_raw_fd())
+            .unwrap()
+            .unwrap();
+        assert_eq!(process.pid, pid as i32);
+        assert_eq!(
+            process.app_id().unwrap(),
+            std::env::current_exe().unwrap().display().to_string()
+        );
+
+        assert_eq!(fdinfo_pid("pos:\t0\nflags:\t02000002\nPid:\t0\n"), Some(0));
+        assert_eq!(
+            fdinfo_pid("pos:\t0\nflags:\t02000002\nPid:\t-1\n"),
+            Some(-1)
+        );
+    }
+}
@@ -16,9 +16,14 @@ pub(crate) fn connection() -> Pool {
     POOL.clone()
 }
 
+/// The path of the SQLite database file.
+pub(crate) fn path() -> PathBuf {
+    DB_PATH.join("authenticator.db")
+}
+
 fn init_pool() -> Result<Pool> {
     fs::create_dir_all(&*DB_PATH)?;
-    let db_path = DB_PATH.join("authenticator.db");
+    let db_path = path();
     if !db_path.exists() {
         File::create(&db_path)?;
     }
@@ -0,0 +1,269 @@
+//! A secrets store for the systems with no Secret Service.
+//!
+//! The items are kept in a single file encrypted with AES-256-GCM, the key is
+//! randomly generated and only readable by the user. As the key is stored next
+//! to the file, anyone able to read the user's data can decrypt it: the file
+//! is as good as plaintext on its own. The vault is required with this store,
+//! so the secrets are only stored encrypted with the application password, see
+//! [`super::requires_vault`].
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use aes_gcm::{aead::Aead, KeyInit};
+use anyhow::{Context, Result};
+use futures_util::future::BoxFuture;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::store::{Attributes, SecretItem, SecretStore};
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    version: u32,
+    #[serde(with = "hex::serde")]
+    nonce: [u8; 12],
+    /// Encrypted JSON list of [`StoredItem`], followed by the authentication
+    /// tag.
+    #[serde(with = "hex::serde")]
+    items: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredItem {
+    label: String,
+    attributes: HashMap<String, String>,
+    #[serde(with = "hex::serde")]
+    secret: Vec<u8>,
+}
+
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    key: Zeroizing<[u8; 32]>,
+    items: Mutex<Vec<SecretItem>>,
+}
+
+impl EncryptedFileStore {
+    const VERSION: u32 = 1;
+
+    /// Whether the store was created in the data directory already.
+    pub fn default_exists() -> bool {
+        default_directory().join("secrets.json").exists()
+    }
+
+    /// Opens the store in the data directory, creating it along with its key
+    /// if needed.
+    pub fn open_default() -> Result<Self> {
+        let directory = default_directory();
+        fs::create_dir_all(&directory)?;
+        let key = load_or_create_key(&directory.join("secrets.key"))?;
+        Self::open(directory.join("secrets.json"), key)
+    }
+
+    /// Opens the store at `path` encrypted with `key`, it is created once an
+    /// item is stored.
+    pub fn open(path: impl Into<PathBuf>, key: Zeroizing<[u8; 32]>) -> Result<Self> {
+        let path = path.into();
+        let items = if path.exists() {
+            let content =
+                fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            decrypt(&serde_json::from_slice(&content)?, &key)?
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            path,
+            key,
+            items: Mutex::new(items),
+        })
+    }
+
+    /// Applies `change` to the items & writes them to the disk, the items are
+    /// left untouched if they fail to be written.
+    fn update(&self, change: impl FnOnce(&mut Vec<SecretItem>)) -> Result<()> {
+        let mut items = self.items.lock().unwrap();
+        let mut updated = items.clone();
+        change(&mut updated);
+        let file = encrypt(&updated, &self.key)?;
+        write_private(&self.path, &serde_json::to_vec(&file)?)?;
+        *items = updated;
+        Ok(())
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn store<'a>(
+        &'a self,
+        label: &'a str,
+        attributes: Attributes<'a>,
+        secret: &'a [u8],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.update(|items| {
+                items.retain(|item| item.attributes() != attributes);
+                items.push(SecretItem {
+                    label: label.to_owned(),
+                    attributes: attributes
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect(),
+                    secret: Zeroizing::new(secret.to_vec()),
+                });
+            })
+        })
+    }
+
+    fn search<'a>(&'a self, attributes: Attributes<'a>) -> BoxFuture<'a, Result<Vec<SecretItem>>> {
+        Box::pin(async move {
+            let items = self.items.lock().unwrap();
+            Ok(items
+                .iter()
+                .filter(|item| item.matches(&attributes))
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn delete<'a>(&'a self, attributes: Attributes<'a>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(
+            async move { self.update(|items| items.retain(|item| !item.matches(&attributes))) },
+        )
+    }
+}
+
+fn default_directory() -> PathBuf {
+    gtk::glib::user_data_dir().join("authenticator")
+}
+
+fn encrypt(items: &[SecretItem], key: &[u8; 32]) -> Result<EncryptedFile> {
+    let mut items = items
+        .iter()
+        .map(|item| StoredItem {
+            label: item.label.clone(),
+            attributes: item.attributes.clone(),
+            secret: item.secret.to_vec(),
+        })
+        .collect::<Vec<_>>();
+    let plaintext = Zeroizing::new(serde_json::to_vec(&items)?);
+    items.iter_mut().for_each(|item| item.secret.zeroize());
+
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let cipher = aes_gcm::Aes256Gcm::new_from_slice(key)?;
+    let ciphertext = cipher
+        .encrypt(aes_gcm::Nonce::from_slice(&nonce), plaintext.as_ref())
+        .map_err(|_| anyhow::anyhow!("Encrypting the secrets"))?;
+    Ok(EncryptedFile {
+        version: EncryptedFileStore::VERSION,
+        nonce,
+        items: ciphertext,
+    })
+}
+
+fn decrypt(file: &EncryptedFile, key: &[u8; 32]) -> Result<Vec<SecretItem>> {
+    anyhow::ensure!(
+        file.version <= EncryptedFileStore::VERSION,
+        "Secrets file version expected to be at most {}. Found {} instead.",
+        EncryptedFileStore::VERSION,
+        file.version
+    );
+    let cipher = aes_gcm::Aes256Gcm::new_from_slice(key)?;
+    let plaintext = Zeroizing::new(
+        cipher
+            .decrypt(aes_gcm::Nonce::from_slice(&file.nonce), file.items.as_ref())
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt the secrets, wrong key?"))?,
+    );
+    let items: Vec<StoredItem> = serde_json::from_slice(&plaintext)?;
+    Ok(items
+        .into_iter()
+        .map(|item| SecretItem {
+            label: item.label,
+            attributes: item.attributes,
+            secret: Zeroizing::new(item.secret),
+        })
+        .collect())
+}
+
+fn load_or_create_key(path: &Path) -> Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    if path.exists() {
+        let content = Zeroizing::new(fs::read(path)?);
+        anyhow::ensure!(
+            content.len() == key.len(),
+            "Invalid secrets key {}",
+            path.display()
+        );
+        key.copy_from_slice(&content);
+    } else {
+        rand::thread_rng().fill_bytes(key.as_mut());
+        write_private(path, key.as_ref())?;
+    }
+    Ok(key)
+}
+
+/// Writes `content` into a file only readable by the user, replacing `path`
+/// once it is completely written.
+fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temporary)
+        .with_context(|| format!("Failed to write {}", temporary.display()))?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::store::conformance, *};
+    use crate::models::RUNTIME;
+
+    #[test]
+    fn encrypted_file_store() {
+        let directory =
+            std::env::temp_dir().join(format!("authenticator-file-store-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        let key = load_or_create_key(&directory.join("secrets.key")).unwrap();
+        let path = directory.join("secrets.json");
+
+        let store = EncryptedFileStore::open(&path, key.clone()).unwrap();
+        RUNTIME.block_on(conformance(&store));
+        RUNTIME
+            .block_on(store.store(
+                "Token",
+                HashMap::from([("type", "token")]),
+                b"JBSWY3DPEHPK3PXP",
+            ))
+            .unwrap();
+
+        // Nothing is written in clear
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("JBSWY3DPEHPK3PXP"));
+        assert!(!content.contains("token"));
+
+        // Reopened with the same key
+        let key = load_or_create_key(&directory.join("secrets.key")).unwrap();
+        let store = EncryptedFileStore::open(&path, key).unwrap();
+        let items = RUNTIME
+            .block_on(store.search(HashMap::from([("type", "token")])))
+            .unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].secret.as_slice(), b"JBSWY3DPEHPK3PXP");
+
+        assert!(EncryptedFileStore::open(&path, Zeroizing::new([0u8; 32])).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
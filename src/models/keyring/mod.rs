@@ -0,0 +1,935 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+
+use aes_gcm::{aead::Aead, KeyInit};
+use anyhow::Context;
+use gtk::glib::once_cell::sync::{Lazy, OnceCell};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+#[cfg(test)]
+pub use self::store::MemoryStore;
+pub use self::store::SecretStore;
+use self::{file_store::EncryptedFileStore, store::Oo7Store};
+use crate::{config, models::SETTINGS};
+
+mod file_store;
+mod secrets_session;
+mod store;
+
+static SECRET_STORE: OnceCell<Box<dyn SecretStore>> = OnceCell::new();
+
+type VaultKey = Zeroizing<[u8; 32]>;
+
+/// Whether the tokens & PINs are encrypted with a key derived from the
+/// application password.
+static VAULT_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Whether the secrets have to be encrypted with the application password, as
+/// the store does not protect them on its own.
+static VAULT_REQUIRED: AtomicBool = AtomicBool::new(false);
+/// The key of the vault, only known while the application is unlocked.
+static VAULT_KEY: Lazy<RwLock<Option<VaultKey>>> = Lazy::new(RwLock::default);
+
+const ENCRYPTED_PREFIX: &str = "encrypted:";
+
+const SECRET_SERVICE: &str = "org.freedesktop.secrets";
+
+/// Key of the secrets fingerprints, generated once per installation.
+static FINGERPRINT_KEY: OnceCell<Zeroizing<[u8; 32]>> = OnceCell::new();
+
+/// Starts the keyring service and unlocks the default collection, the secrets
+/// are stored in an encrypted file instead if there is no Secret Service.
+///
+/// The store is chosen on the first start and kept afterwards, failing to
+/// start the Secret Service later on is an error instead of silently storing
+/// the new secrets elsewhere.
+pub async fn init() -> anyhow::Result<()> {
+    let mut backend = SETTINGS.secret_store();
+    if backend == "auto" {
+        backend = if EncryptedFileStore::default_exists() || !has_secret_service().await {
+            "file".to_owned()
+        } else {
+            "secret-service".to_owned()
+        };
+        tracing::info!("Storing the secrets in the {backend} store");
+        SETTINGS.set_secret_store(&backend)?;
+    }
+    VAULT_REQUIRED.store(backend == "file", Ordering::SeqCst);
+    let store: Box<dyn SecretStore> = match backend.as_str() {
+        "file" => Box::new(EncryptedFileStore::open_default()?),
+        _ => Box::new(Oo7Store::new().await?),
+    };
+    init_with(store).await
+}
+
+/// Whether a Secret Service is running or can be activated, the sandboxed
+/// applications go through the secret portal instead.
+async fn has_secret_service() -> bool {
+    if oo7::is_sandboxed().await {
+        return true;
+    }
+    async fn lookup() -> anyhow::Result<bool> {
+        let connection = zbus::Connection::session().await?;
+        let proxy = zbus::fdo::DBusProxy::new(&connection).await?;
+        let name = zbus::names::BusName::try_from(SECRET_SERVICE)?;
+        if proxy.name_has_owner(name).await? {
+            return Ok(true);
+        }
+        let activatable = proxy.list_activatable_names().await?;
+        Ok(activatable
+            .iter()
+            .any(|name| name.as_str() == SECRET_SERVICE))
+    }
+    match lookup().await {
+        Ok(found) => found,
+        Err(err) => {
+            tracing::warn!("Failed to look for the Secret Service {err}");
+            false
+        }
+    }
+}
+
+/// Stores the secrets in `store`.
+pub async fn init_with(store: Box<dyn SecretStore>) -> anyhow::Result<()> {
+    let vault_enabled = !store.search(vault_attributes()).await?.is_empty();
+    VAULT_ENABLED.store(vault_enabled, Ordering::SeqCst);
+    let fingerprint_key = load_fingerprint_key(store.as_ref()).await?;
+    FINGERPRINT_KEY.get_or_init(|| fingerprint_key);
+    SECRET_STORE
+        .set(store)
+        .map_err(|_| anyhow::anyhow!("The keyring service was already started"))
+}
+
+/// Stores the secrets in memory, for the tests that go through the keyring.
+#[cfg(test)]
+pub fn init_memory_store() {
+    SECRET_STORE.get_or_init(|| Box::<MemoryStore>::default());
+    FINGERPRINT_KEY.get_or_init(|| {
+        let mut key = Zeroizing::new([0u8; 32]);
+        rand::thread_rng().fill_bytes(key.as_mut());
+        key
+    });
+}
+
+fn secret_store() -> &'static dyn SecretStore {
+    SECRET_STORE
+        .get()
+        .expect("The keyring service was not started")
+        .as_ref()
+}
+
+fn token_attributes(token_id: &str) -> HashMap<&str, &str> {
+    HashMap::from([
+        ("application", config::APP_ID),
+        ("type", "token"),
+        ("token_id", token_id),
+    ])
+}
+
+fn pin_attributes(token_id: &str) -> HashMap<&str, &str> {
+    HashMap::from([
+        ("application", config::APP_ID),
+        ("type", "pin"),
+        ("token_id", token_id),
+    ])
+}
+
+fn password_attributes() -> HashMap<&'static str, &'static str> {
+    HashMap::from([("application", config::APP_ID), ("type", "password")])
+}
+
+fn backup_password_attributes() -> HashMap<&'static str, &'static str> {
+    HashMap::from([("application", config::APP_ID), ("type", "backup-password")])
+}
+
+fn vault_attributes() -> HashMap<&'static str, &'static str> {
+    HashMap::from([("application", config::APP_ID), ("type", "vault")])
+}
+
+fn pending_vault_attributes() -> HashMap<&'static str, &'static str> {
+    HashMap::from([("application", config::APP_ID), ("type", "vault-pending")])
+}
+
+fn fingerprint_key_attributes() -> HashMap<&'static str, &'static str> {
+    HashMap::from([("application", config::APP_ID), ("type", "fingerprint-key")])
+}
+
+fn encode_argon2(secret: &str) -> anyhow::Result<String> {
+    let password = secret.as_bytes();
+    let mut salt = [0u8; 64];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let config = argon2::Config::default();
+    let hash = argon2::hash_encoded(password, &salt, &config)?;
+
+    Ok(hash)
+}
+
+async fn load_fingerprint_key(store: &dyn SecretStore) -> anyhow::Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    match store.search(fingerprint_key_attributes()).await?.first() {
+        Some(item) => hex::decode_to_slice(&item.secret, key.as_mut())?,
+        None => {
+            rand::thread_rng().fill_bytes(key.as_mut());
+            let encoded_key = Zeroizing::new(hex::encode(key.as_ref()));
+            store
+                .store(
+                    "Authenticator fingerprint key",
+                    fingerprint_key_attributes(),
+                    encoded_key.as_bytes(),
+                )
+                .await?;
+        }
+    }
+    Ok(key)
+}
+
+/// A keyed fingerprint of an OTP `secret`, so the duplicated accounts can be
+/// looked up without storing or comparing the secrets themselves.
+///
+/// It is prefixed by the identifier of the key it was computed with, the
+/// stored fingerprints have to be computed again once the key changes.
+pub fn fingerprint(secret: &str) -> String {
+    format!(
+        "{}:{}",
+        fingerprint_key_id(),
+        fingerprint_mac(normalize_secret(secret).as_bytes())
+    )
+}
+
+/// Whether `fingerprint` was computed with the current key.
+pub fn is_current_fingerprint(fingerprint: &str) -> bool {
+    fingerprint
+        .split_once(':')
+        .is_some_and(|(key_id, _)| key_id == fingerprint_key_id())
+}
+
+/// A check value of the fingerprints key, telling the fingerprints computed
+/// with a previous key apart.
+fn fingerprint_key_id() -> String {
+    fingerprint_mac(b"Authenticator fingerprint key")[..16].to_owned()
+}
+
+fn fingerprint_mac(data: &[u8]) -> String {
+    let key = FINGERPRINT_KEY
+        .get()
+        .expect("The keyring service was not started");
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_slice()).unwrap();
+    mac.update(data);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// The secrets are base32 encoded, ignore the padding, spaces and case.
+fn normalize_secret(secret: &str) -> String {
+    secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// Derives the key of the vault from the application password, with the
+/// OWASP recommended parameters for argon2id.
+fn derive_key(password: &str, salt: &[u8]) -> anyhow::Result<VaultKey> {
+    let config = argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        version: argon2::Version::Version13,
+        mem_cost: 19 * 1024,
+        time_cost: 2,
+        lanes: 1,
+        hash_length: 32,
+        ..argon2::Config::default()
+    };
+    let hash = Zeroizing::new(argon2::hash_raw(password.as_bytes(), salt, &config)?);
+    let mut key = Zeroizing::new([0u8; 32]);
+    key.copy_from_slice(&hash);
+    Ok(key)
+}
+
+/// Encodes a secret before storing it in the keyring, it is encrypted with
+/// AES-256-GCM if a `key` is given.
+fn encode_secret(value: &str, key: Option<&VaultKey>) -> anyhow::Result<String> {
+    let Some(key) = key else {
+        return Ok(hex::encode(value.as_bytes()));
+    };
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let cipher = aes_gcm::Aes256Gcm::new_from_slice(key.as_slice())?;
+    let ciphertext = cipher
+        .encrypt(aes_gcm::Nonce::from_slice(&nonce), value.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Encrypting the secret"))?;
+    Ok(format!(
+        "{ENCRYPTED_PREFIX}{}{}",
+        hex::encode(nonce),
+        hex::encode(ciphertext)
+    ))
+}
+
+/// Decodes a secret stored by [`encode_secret`], the unencrypted ones are
+/// still accepted as the vault could have been enabled partially.
+fn decode_secret(secret: &[u8], key: Option<&VaultKey>) -> anyhow::Result<String> {
+    let secret = std::str::from_utf8(secret)?;
+    let Some(encrypted) = secret.strip_prefix(ENCRYPTED_PREFIX) else {
+        return Ok(String::from_utf8(hex::decode(secret)?)?);
+    };
+    let key = key.context("The vault is locked")?;
+    let data = hex::decode(encrypted)?;
+    anyhow::ensure!(data.len() > 12, "The encrypted secret is too short");
+    let (nonce, ciphertext) = data.split_at(12);
+    let cipher = aes_gcm::Aes256Gcm::new_from_slice(key.as_slice())?;
+    let plaintext = Zeroizing::new(
+        cipher
+            .decrypt(aes_gcm::Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt the secret, wrong key?"))?,
+    );
+    Ok(String::from_utf8(plaintext.to_vec())?)
+}
+
+/// Decodes a secret with the first of `keys` it was encrypted with.
+fn decode_secret_with_any(secret: &[u8], keys: &[&VaultKey]) -> anyhow::Result<String> {
+    let Some((key, others)) = keys.split_first() else {
+        return decode_secret(secret, None);
+    };
+    decode_secret(secret, Some(key)).or_else(|err| {
+        if others.is_empty() {
+            Err(err)
+        } else {
+            decode_secret_with_any(secret, others)
+        }
+    })
+}
+
+/// The key the secrets have to be encrypted with, `None` if the vault is not
+/// enabled.
+fn vault_key() -> anyhow::Result<Option<VaultKey>> {
+    if !is_vault_enabled() {
+        return Ok(None);
+    }
+    let key = VAULT_KEY.read().unwrap().clone();
+    key.map(Some).context("The vault is locked")
+}
+
+/// The key the new secrets have to be encrypted with, they cannot be stored
+/// before the vault is enabled if it is required.
+fn storing_key() -> anyhow::Result<Option<VaultKey>> {
+    anyhow::ensure!(
+        !requires_vault() || is_vault_enabled(),
+        "A passphrase has to be set to store the secrets without a keyring"
+    );
+    vault_key()
+}
+
+pub async fn store(label: &str, token: &str) -> anyhow::Result<String> {
+    let token_id = encode_argon2(token)?;
+    let attributes = token_attributes(&token_id);
+    let encoded_token = encode_secret(token, storing_key()?.as_ref())?;
+    secret_store()
+        .store(label, attributes, encoded_token.as_bytes())
+        .await?;
+    Ok(token_id)
+}
+
+pub async fn token(token_id: &str) -> anyhow::Result<Option<String>> {
+    let attributes = token_attributes(token_id);
+    let items = secret_store().search(attributes).await?;
+    Ok(match items.get(0) {
+        Some(e) => Some(decode_secret(&e.secret, vault_key()?.as_ref())?),
+        _ => None,
+    })
+}
+
+/// The tokens & PINs of the accounts, keyed by their token identifier.
+#[derive(Default)]
+pub struct Secrets {
+    pub tokens: HashMap<String, Zeroizing<String>>,
+    pub pins: HashMap<String, Zeroizing<String>>,
+}
+
+/// Every token & PIN, fetched with a search per kind of secret when loading
+/// the accounts.
+pub async fn secrets() -> anyhow::Result<Secrets> {
+    secrets_from(secret_store(), vault_key()?.as_ref()).await
+}
+
+async fn secrets_from(store: &dyn SecretStore, key: Option<&VaultKey>) -> anyhow::Result<Secrets> {
+    let mut secrets = Secrets::default();
+    for (kind, found) in [("token", &mut secrets.tokens), ("pin", &mut secrets.pins)] {
+        let attributes = HashMap::from([("application", config::APP_ID), ("type", kind)]);
+        for item in store.search_unlabeled(attributes).await? {
+            let Some(token_id) = item.attributes.get("token_id") else {
+                continue;
+            };
+            // The accounts whose secret is unreadable fail to load on their own
+            match decode_secret(&item.secret, key) {
+                Ok(secret) => {
+                    found.insert(token_id.clone(), Zeroizing::new(secret));
+                }
+                Err(err) => tracing::warn!("Failed to decode the {kind} {token_id} {err}"),
+            }
+        }
+    }
+    Ok(secrets)
+}
+
+pub async fn remove_token(token_id: &str) -> anyhow::Result<()> {
+    let attributes = token_attributes(token_id);
+    secret_store().delete(attributes).await?;
+    remove_pin(token_id).await?;
+    Ok(())
+}
+
+/// Stores the PIN used by the Yandex & mOTP methods next to the token
+/// identified by `token_id`.
+pub async fn store_pin(label: &str, token_id: &str, pin: &str) -> anyhow::Result<()> {
+    let attributes = pin_attributes(token_id);
+    let encoded_pin = encode_secret(pin, storing_key()?.as_ref())?;
+    secret_store()
+        .store(label, attributes, encoded_pin.as_bytes())
+        .await?;
+    Ok(())
+}
+
+pub async fn pin(token_id: &str) -> anyhow::Result<Option<String>> {
+    let attributes = pin_attributes(token_id);
+    let items = secret_store().search(attributes).await?;
+    Ok(match items.get(0) {
+        Some(e) => Some(decode_secret(&e.secret, vault_key()?.as_ref())?),
+        _ => None,
+    })
+}
+
+pub async fn remove_pin(token_id: &str) -> anyhow::Result<()> {
+    let attributes = pin_attributes(token_id);
+    secret_store().delete(attributes).await?;
+    Ok(())
+}
+
+pub async fn has_set_password() -> anyhow::Result<bool> {
+    let attributes = password_attributes();
+    match secret_store().search(attributes).await {
+        Ok(items) => Ok(matches!(items.get(0), Some(_))),
+        _ => Ok(false),
+    }
+}
+
+/// Stores password using the Argon2 algorithm with a random 128bit salt.
+///
+/// The secrets are re-encrypted with the new password if the vault is enabled,
+/// it gets enabled if it is required.
+pub async fn set_password(password: &str) -> anyhow::Result<()> {
+    if let Some(old_key) = vault_key()? {
+        let key = change_vault_password(secret_store(), &old_key, password).await?;
+        *VAULT_KEY.write().unwrap() = Some(key);
+        return Ok(());
+    }
+    store_password_hash(secret_store(), &encode_argon2(password)?).await?;
+    if requires_vault() {
+        enable_vault(password).await?;
+    }
+    Ok(())
+}
+
+async fn store_password_hash(store: &dyn SecretStore, hash: &str) -> anyhow::Result<()> {
+    store
+        .store(
+            "Authenticator password",
+            password_attributes(),
+            hash.as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// A change of the password of the vault, stored before re-encrypting the
+/// secrets so that it can be resumed if it gets interrupted.
+#[derive(Serialize, Deserialize)]
+struct PendingVault {
+    /// The salt of the new key, hex encoded.
+    salt: String,
+    /// The new key, encrypted with the previous one.
+    key: String,
+    /// The argon2 hash of the new password.
+    password: String,
+}
+
+/// Re-encrypts the secrets with a key derived from the new `password`,
+/// returning it.
+async fn change_vault_password(
+    store: &dyn SecretStore,
+    old_key: &VaultKey,
+    password: &str,
+) -> anyhow::Result<VaultKey> {
+    let (salt, key) = new_vault_key(password)?;
+    let encoded_key = Zeroizing::new(hex::encode(key.as_ref()));
+    let pending = PendingVault {
+        salt: hex::encode(salt),
+        key: encode_secret(&encoded_key, Some(old_key))?,
+        password: encode_argon2(password)?,
+    };
+    store
+        .store(
+            "Authenticator pending vault",
+            pending_vault_attributes(),
+            &serde_json::to_vec(&pending)?,
+        )
+        .await?;
+    complete_password_change(store, &pending, Some(old_key), key).await
+}
+
+/// Stores every secret encrypted with the new `key`, then the new password &
+/// salt. Each step can be done again if a later one fails.
+async fn complete_password_change(
+    store: &dyn SecretStore,
+    pending: &PendingVault,
+    old_key: Option<&VaultKey>,
+    key: VaultKey,
+) -> anyhow::Result<VaultKey> {
+    // The secrets that were already re-encrypted are decoded with the new key
+    let keys = [&key].into_iter().chain(old_key).collect::<Vec<_>>();
+    rewrite_secrets(store, &keys, Some(&key)).await?;
+    store_password_hash(store, &pending.password).await?;
+    store_vault_salt(store, &hex::decode(&pending.salt)?).await?;
+    store.delete(pending_vault_attributes()).await?;
+    Ok(key)
+}
+
+/// Removes the password, the secrets are stored unencrypted again if the vault
+/// was enabled. It cannot be removed if the vault is required.
+pub async fn reset_password() -> anyhow::Result<()> {
+    disable_vault().await?;
+    let attributes = password_attributes();
+    secret_store().delete(attributes).await?;
+    Ok(())
+}
+
+pub async fn is_current_password(password: &str) -> anyhow::Result<bool> {
+    let attributes = password_attributes();
+    let items = secret_store().search(attributes).await?;
+    Ok(match items.get(0) {
+        Some(i) => {
+            // Verifies that the hash generated by `password` corresponds
+            // to `hash`.
+            argon2::verify_encoded(&String::from_utf8_lossy(&i.secret), password.as_bytes())?
+        }
+        None => false,
+    })
+}
+
+pub fn is_vault_enabled() -> bool {
+    VAULT_ENABLED.load(Ordering::SeqCst)
+}
+
+/// Whether the secrets are stored in a file, which is as good as plaintext
+/// unless the vault is enabled. The vault cannot be disabled then, nor can the
+/// secrets be stored before a password is set.
+pub fn requires_vault() -> bool {
+    VAULT_REQUIRED.load(Ordering::SeqCst)
+}
+
+/// Derives the key of the vault from the application password, which has to
+/// be verified with [`is_current_password`] first.
+pub async fn unlock_vault(password: &str) -> anyhow::Result<()> {
+    if !is_vault_enabled() {
+        return Ok(());
+    }
+    let key = vault_key_from(secret_store(), password).await?;
+    *VAULT_KEY.write().unwrap() = Some(key);
+    Ok(())
+}
+
+/// Derives the key of the vault, completing the password change that was
+/// interrupted if any.
+async fn vault_key_from(store: &dyn SecretStore, password: &str) -> anyhow::Result<VaultKey> {
+    let items = store.search(vault_attributes()).await?;
+    let item = items.first().context("The vault salt is missing")?;
+    let salt = hex::decode(&item.secret)?;
+    let key = derive_key(password, &salt)?;
+
+    let Some(item) = store.search(pending_vault_attributes()).await?.pop() else {
+        return Ok(key);
+    };
+    tracing::info!("Resuming the interrupted change of the password");
+    let pending: PendingVault = serde_json::from_slice(&item.secret)?;
+    // `password` is the previous one until the hash of the new one was stored
+    match decode_secret(pending.key.as_bytes(), Some(&key)) {
+        Ok(encoded_key) => {
+            let encoded_key = Zeroizing::new(encoded_key);
+            let mut new_key = Zeroizing::new([0u8; 32]);
+            hex::decode_to_slice(encoded_key.as_str(), new_key.as_mut())?;
+            complete_password_change(store, &pending, Some(&key), new_key).await
+        }
+        Err(_) => {
+            let new_key = derive_key(password, &hex::decode(&pending.salt)?)?;
+            complete_password_change(store, &pending, None, new_key).await
+        }
+    }
+}
+
+/// Forgets the key of the vault, until it is unlocked again.
+pub fn lock_vault() {
+    VAULT_KEY.write().unwrap().take();
+}
+
+/// Encrypts every token, PIN & the automatic backups passphrase with a key
+/// derived from the application `password`.
+pub async fn enable_vault(password: &str) -> anyhow::Result<()> {
+    if is_vault_enabled() {
+        return Ok(());
+    }
+    let (salt, key) = new_vault_key(password)?;
+    // The vault is enabled first, as the unencrypted secrets can still be read
+    // if they fail to be re-encrypted
+    store_vault_salt(secret_store(), &salt).await?;
+    VAULT_ENABLED.store(true, Ordering::SeqCst);
+    *VAULT_KEY.write().unwrap() = Some(key.clone());
+    rewrite_secrets(secret_store(), &[], Some(&key)).await
+}
+
+/// Stores every secret unencrypted again, the vault has to be unlocked.
+pub async fn disable_vault() -> anyhow::Result<()> {
+    if !is_vault_enabled() {
+        return Ok(());
+    }
+    anyhow::ensure!(
+        !requires_vault(),
+        "The secrets cannot be stored unencrypted without a keyring"
+    );
+    let key = vault_key()?;
+    rewrite_secrets(secret_store(), &key.iter().collect::<Vec<_>>(), None).await?;
+    secret_store().delete(vault_attributes()).await?;
+    secret_store().delete(pending_vault_attributes()).await?;
+    VAULT_ENABLED.store(false, Ordering::SeqCst);
+    lock_vault();
+    Ok(())
+}
+
+fn new_vault_key(password: &str) -> anyhow::Result<([u8; 16], VaultKey)> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let key = derive_key(password, &salt)?;
+    Ok((salt, key))
+}
+
+async fn store_vault_salt(store: &dyn SecretStore, salt: &[u8]) -> anyhow::Result<()> {
+    store
+        .store(
+            "Authenticator vault salt",
+            vault_attributes(),
+            hex::encode(salt).as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Decodes every token, PIN & the automatic backups passphrase with one of
+/// `old_keys` and stores them again encoded with `new_key`.
+async fn rewrite_secrets(
+    store: &dyn SecretStore,
+    old_keys: &[&VaultKey],
+    new_key: Option<&VaultKey>,
+) -> anyhow::Result<()> {
+    let mut items = Vec::new();
+    for kind in ["token", "pin", "backup-password"] {
+        let attributes = HashMap::from([("application", config::APP_ID), ("type", kind)]);
+        for item in store.search(attributes).await? {
+            // Everything is decoded first, to not leave the keyring half
+            // re-encrypted if one of the secrets is unreadable
+            let secret = Zeroizing::new(decode_secret_with_any(&item.secret, old_keys)?);
+            items.push((item, secret));
+        }
+    }
+    for (item, secret) in items {
+        let encoded = encode_secret(&secret, new_key)?;
+        store
+            .store(&item.label, item.attributes(), encoded.as_bytes())
+            .await?;
+    }
+    Ok(())
+}
+
+/// Stores the passphrase the automatic backups are encrypted with, in the
+/// vault like the tokens & PINs.
+pub async fn store_backup_password(password: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !password.is_empty(),
+        "The automatic backups passphrase cannot be empty"
+    );
+    let attributes = backup_password_attributes();
+    let encoded_password = encode_secret(password, storing_key()?.as_ref())?;
+    secret_store()
+        .store(
+            "Authenticator automatic backups passphrase",
+            attributes,
+            encoded_password.as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn backup_password() -> anyhow::Result<Option<String>> {
+    let attributes = backup_password_attributes();
+    let items = secret_store().search(attributes).await?;
+    Ok(match items.get(0) {
+        Some(e) => Some(decode_secret(&e.secret, vault_key()?.as_ref())?),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::RUNTIME;
+
+    #[test]
+    fn encrypted_secrets() {
+        let key = derive_key("password", b"0123456789abcdef").unwrap();
+        let encoded = encode_secret("JBSWY3DPEHPK3PXP", Some(&key)).unwrap();
+        assert!(encoded.starts_with(ENCRYPTED_PREFIX));
+        assert!(!encoded.contains(&hex::encode("JBSWY3DPEHPK3PXP")));
+        assert_eq!(
+            decode_secret(encoded.as_bytes(), Some(&key)).unwrap(),
+            "JBSWY3DPEHPK3PXP"
+        );
+
+        // The same password & salt derive the same key
+        let same_key = derive_key("password", b"0123456789abcdef").unwrap();
+        assert_eq!(*same_key, *key);
+        let other_key = derive_key("password", b"fedcba9876543210").unwrap();
+        assert!(decode_secret(encoded.as_bytes(), Some(&other_key)).is_err());
+        assert!(decode_secret(encoded.as_bytes(), None).is_err());
+
+        // Unencrypted secrets are still readable
+        let encoded = encode_secret("JBSWY3DPEHPK3PXP", None).unwrap();
+        assert_eq!(encoded, hex::encode("JBSWY3DPEHPK3PXP"));
+        assert_eq!(
+            decode_secret(encoded.as_bytes(), Some(&key)).unwrap(),
+            "JBSWY3DPEHPK3PXP"
+        );
+    }
+
+    #[test]
+    fn fingerprints() {
+        init_memory_store();
+        // The secrets are compared regardless of their case & padding
+        assert_eq!(
+            fingerprint("5OM4WOOGPLQEF6UGN3CPEOOLWU"),
+            fingerprint("5om4 woog plqe f6ug n3cp eool wu==")
+        );
+        assert_ne!(
+            fingerprint("5OM4WOOGPLQEF6UGN3CPEOOLWU"),
+            fingerprint("4SJHB4GSD43FZBAI7C2HLRJGPQ")
+        );
+
+        // The ones computed with another key are outdated
+        let current = fingerprint("5OM4WOOGPLQEF6UGN3CPEOOLWU");
+        assert!(is_current_fingerprint(&current));
+        let (_, mac) = current.split_once(':').unwrap();
+        assert!(!is_current_fingerprint(&format!("0123456789abcdef:{mac}")));
+        assert!(!is_current_fingerprint(mac));
+    }
+
+    #[test]
+    fn memory_store_vault() {
+        init_memory_store();
+        RUNTIME.block_on(async {
+            let token_id = store("GitHub", "JBSWY3DPEHPK3PXP").await.unwrap();
+            store_pin("GitHub", &token_id, "1234").await.unwrap();
+            store_backup_password("passphrase").await.unwrap();
+            set_password("password").await.unwrap();
+            assert!(is_current_password("password").await.unwrap());
+
+            enable_vault("password").await.unwrap();
+            let items = secret_store()
+                .search(token_attributes(&token_id))
+                .await
+                .unwrap();
+            assert!(items[0].secret.starts_with(ENCRYPTED_PREFIX.as_bytes()));
+            assert_eq!(
+                token(&token_id).await.unwrap().as_deref(),
+                Some("JBSWY3DPEHPK3PXP")
+            );
+            let items = secret_store()
+                .search(backup_password_attributes())
+                .await
+                .unwrap();
+            assert!(items[0].secret.starts_with(ENCRYPTED_PREFIX.as_bytes()));
+
+            lock_vault();
+            assert!(token(&token_id).await.is_err());
+            assert!(backup_password().await.is_err());
+            unlock_vault("password").await.unwrap();
+
+            // Changing the password re-encrypts the secrets
+            set_password("new password").await.unwrap();
+            lock_vault();
+            unlock_vault("new password").await.unwrap();
+            assert_eq!(
+                token(&token_id).await.unwrap().as_deref(),
+                Some("JBSWY3DPEHPK3PXP")
+            );
+            assert_eq!(pin(&token_id).await.unwrap().as_deref(), Some("1234"));
+            let items = secret_store()
+                .search(backup_password_attributes())
+                .await
+                .unwrap();
+            let new_key = vault_key().unwrap().unwrap();
+            assert_eq!(
+                decode_secret(&items[0].secret, Some(&new_key)).unwrap(),
+                "passphrase"
+            );
+
+            // As does removing it
+            reset_password().await.unwrap();
+            assert!(!is_vault_enabled());
+            assert!(!has_set_password().await.unwrap());
+            let items = secret_store()
+                .search(token_attributes(&token_id))
+                .await
+                .unwrap();
+            assert_eq!(
+                items[0].secret.as_slice(),
+                hex::encode("JBSWY3DPEHPK3PXP").as_bytes()
+            );
+            assert_eq!(pin(&token_id).await.unwrap().as_deref(), Some("1234"));
+            assert_eq!(
+                backup_password().await.unwrap().as_deref(),
+                Some("passphrase")
+            );
+
+            remove_token(&token_id).await.unwrap();
+            assert_eq!(token(&token_id).await.unwrap(), None);
+            assert_eq!(pin(&token_id).await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn interrupted_password_change() {
+        const ACCOUNTS: usize = 2;
+
+        async fn current_password(store: &MemoryStore) -> &'static str {
+            let items = store.search(password_attributes()).await.unwrap();
+            let hash = String::from_utf8(items[0].secret.to_vec()).unwrap();
+            ["password", "new password"]
+                .into_iter()
+                .find(|password| argon2::verify_encoded(&hash, password.as_bytes()).unwrap())
+                .unwrap()
+        }
+
+        let salt = b"0123456789abcdef";
+        let key = derive_key("password", salt).unwrap();
+
+        // Interrupted at every step, including before it starts & once it is done
+        for stores in 0..ACCOUNTS + 5 {
+            let store = MemoryStore::default();
+            RUNTIME.block_on(async {
+                store_vault_salt(&store, salt).await.unwrap();
+                store_password_hash(&store, &encode_argon2("password").unwrap())
+                    .await
+                    .unwrap();
+                for i in 0..ACCOUNTS {
+                    let token = encode_secret(&format!("TOKEN{i}"), Some(&key)).unwrap();
+                    store
+                        .store(
+                            "Account",
+                            token_attributes(&format!("token-{i}")),
+                            token.as_bytes(),
+                        )
+                        .await
+                        .unwrap();
+                }
+                let passphrase = encode_secret("passphrase", Some(&key)).unwrap();
+                store
+                    .store(
+                        "Backups",
+                        backup_password_attributes(),
+                        passphrase.as_bytes(),
+                    )
+                    .await
+                    .unwrap();
+
+                store.fail_after(Some(stores));
+                let changed = change_vault_password(&store, &key, "new password").await;
+                assert_eq!(changed.is_ok(), stores == ACCOUNTS + 4);
+                store.fail_after(None);
+
+                // Unlocking with whichever password is current completes the
+                // change, unless it failed before being started
+                let password = current_password(&store).await;
+                let new_key = vault_key_from(&store, password).await.unwrap();
+                assert!(store
+                    .search(pending_vault_attributes())
+                    .await
+                    .unwrap()
+                    .is_empty());
+                let expected = if stores == 0 {
+                    "password"
+                } else {
+                    "new password"
+                };
+                assert_eq!(current_password(&store).await, expected);
+                assert_eq!(*vault_key_from(&store, expected).await.unwrap(), *new_key);
+                let secrets = secrets_from(&store, Some(&new_key)).await.unwrap();
+                assert_eq!(secrets.tokens.len(), ACCOUNTS);
+                assert_eq!(secrets.tokens["token-1"].as_str(), "TOKEN1");
+                let items = store.search(backup_password_attributes()).await.unwrap();
+                assert_eq!(
+                    decode_secret(&items[0].secret, Some(&new_key)).unwrap(),
+                    "passphrase"
+                );
+            });
+        }
+    }
+
+    #[test]
+    fn batched_secrets() {
+        const ACCOUNTS: usize = 1000;
+
+        let store = MemoryStore::default();
+        let key = derive_key("password", b"0123456789abcdef").unwrap();
+        RUNTIME.block_on(async {
+            for i in 0..ACCOUNTS {
+                let token_id = format!("token-{i}");
+                // Half of them were added before enabling the vault
+                let key = (i % 2 == 0).then_some(&key);
+                let token = encode_secret(&format!("TOKEN{i}"), key).unwrap();
+                store
+                    .store("Account", token_attributes(&token_id), token.as_bytes())
+                    .await
+                    .unwrap();
+                if i % 10 == 0 {
+                    let pin = encode_secret(&format!("{i}"), key).unwrap();
+                    store
+                        .store("Account", pin_attributes(&token_id), pin.as_bytes())
+                        .await
+                        .unwrap();
+                }
+            }
+            store
+                .store(
+                    "Account",
+                    token_attributes("unreadable"),
+                    b"encrypted:not-hex",
+                )
+                .await
+                .unwrap();
+            store.reset_searches();
+
+            let secrets = secrets_from(&store, Some(&key)).await.unwrap();
+            // A search per kind of secret, not per account
+            assert_eq!(store.searches(), 2);
+            assert_eq!(secrets.tokens.len(), ACCOUNTS);
+            assert_eq!(secrets.pins.len(), ACCOUNTS / 10);
+            assert_eq!(secrets.tokens["token-41"].as_str(), "TOKEN41");
+            assert_eq!(secrets.tokens["token-42"].as_str(), "TOKEN42");
+            assert_eq!(secrets.pins["token-40"].as_str(), "40");
+            assert!(!secrets.tokens.contains_key("unreadable"));
+        });
+    }
+}
@@ -0,0 +1,254 @@
+//! A session with the Secret Service fetching many secrets with a single
+//! `GetSecrets` call, which the wrapper API of `oo7` does not provide.
+//!
+//! The secrets are encrypted during the transfer with the
+//! `dh-ietf1024-sha256-aes128-cbc-pkcs7` algorithm, the same one `oo7` uses.
+use std::collections::HashMap;
+
+use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use anyhow::Context;
+use futures_util::{StreamExt, TryStreamExt};
+use hkdf::Hkdf;
+use num_bigint::BigUint;
+use rand::RngCore;
+use sha2::Sha256;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+use zeroize::Zeroizing;
+
+use super::store::{Attributes, SecretItem};
+
+type DecAlg = cbc::Decryptor<aes::Aes128>;
+
+const DESTINATION: &str = "org.freedesktop.secrets";
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+const SERVICE_INTERFACE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION_INTERFACE: &str = "org.freedesktop.Secret.Collection";
+const ITEM_INTERFACE: &str = "org.freedesktop.Secret.Item";
+const ALGORITHM: &str = "dh-ietf1024-sha256-aes128-cbc-pkcs7";
+
+/// The 1024 bits MODP group of RFC 2409, with 2 as generator.
+const DH_PRIME: [u8; 128] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xC9, 0x0F, 0xDA, 0xA2, 0x21, 0x68, 0xC2, 0x34,
+    0xC4, 0xC6, 0x62, 0x8B, 0x80, 0xDC, 0x1C, 0xD1, 0x29, 0x02, 0x4E, 0x08, 0x8A, 0x67, 0xCC, 0x74,
+    0x02, 0x0B, 0xBE, 0xA6, 0x3B, 0x13, 0x9B, 0x22, 0x51, 0x4A, 0x08, 0x79, 0x8E, 0x34, 0x04, 0xDD,
+    0xEF, 0x95, 0x19, 0xB3, 0xCD, 0x3A, 0x43, 0x1B, 0x30, 0x2B, 0x0A, 0x6D, 0xF2, 0x5F, 0x14, 0x37,
+    0x4F, 0xE1, 0x35, 0x6D, 0x6D, 0x51, 0xC2, 0x45, 0xE4, 0x85, 0xB5, 0x76, 0x62, 0x5E, 0x7E, 0xC6,
+    0xF4, 0x4C, 0x42, 0xE9, 0xA6, 0x37, 0xED, 0x6B, 0x0B, 0xFF, 0x5C, 0xB6, 0xF4, 0x06, 0xB7, 0xED,
+    0xEE, 0x38, 0x6B, 0xFB, 0x5A, 0x89, 0x9F, 0xA5, 0xAE, 0x9F, 0x24, 0x11, 0x7C, 0x4B, 0x1F, 0xE6,
+    0x49, 0x28, 0x66, 0x51, 0xEC, 0xE6, 0x53, 0x81, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+/// How many items have their attributes fetched at the same time.
+const CONCURRENT_FETCHES: usize = 16;
+
+/// A secret as sent by the Secret Service: the session, the IV, the encrypted
+/// value & its content type.
+type Secret = (OwnedObjectPath, Vec<u8>, Vec<u8>, String);
+
+pub struct SecretsSession {
+    connection: zbus::Connection,
+    collection: OwnedObjectPath,
+    session: OwnedObjectPath,
+    key: Zeroizing<[u8; 16]>,
+}
+
+impl SecretsSession {
+    /// Opens an encrypted session with the Secret Service, for the default
+    /// collection.
+    pub async fn open() -> anyhow::Result<Self> {
+        let connection = zbus::Connection::session().await?;
+        let collection: OwnedObjectPath = connection
+            .call_method(
+                Some(DESTINATION),
+                SERVICE_PATH,
+                Some(SERVICE_INTERFACE),
+                "ReadAlias",
+                &("default"),
+            )
+            .await?
+            .body()?;
+        anyhow::ensure!(collection.as_str() != "/", "There is no default collection");
+
+        let mut private_key = Zeroizing::new([0; 128]);
+        rand::thread_rng().fill_bytes(private_key.as_mut());
+        let public_key = public_key(private_key.as_ref());
+        let (server_key, session): (OwnedValue, OwnedObjectPath) = connection
+            .call_method(
+                Some(DESTINATION),
+                SERVICE_PATH,
+                Some(SERVICE_INTERFACE),
+                "OpenSession",
+                &(ALGORITHM, Value::from(public_key)),
+            )
+            .await?
+            .body()?;
+        let server_key = Vec::<u8>::try_from(server_key).context("Invalid server public key")?;
+        let key = aes_key(private_key.as_ref(), &server_key);
+
+        Ok(Self {
+            connection,
+            collection,
+            session,
+            key,
+        })
+    }
+
+    /// The items of the default collection that have all of `attributes`,
+    /// their secrets fetched at once. Their labels are only fetched if
+    /// `labels` is set.
+    pub async fn search(
+        &self,
+        attributes: Attributes<'_>,
+        labels: bool,
+    ) -> anyhow::Result<Vec<SecretItem>> {
+        let paths: Vec<OwnedObjectPath> = self
+            .connection
+            .call_method(
+                Some(DESTINATION),
+                self.collection.as_str(),
+                Some(COLLECTION_INTERFACE),
+                "SearchItems",
+                &(attributes),
+            )
+            .await?
+            .body()?;
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut secrets: HashMap<OwnedObjectPath, Secret> = self
+            .connection
+            .call_method(
+                Some(DESTINATION),
+                SERVICE_PATH,
+                Some(SERVICE_INTERFACE),
+                "GetSecrets",
+                &(&paths, &self.session),
+            )
+            .await?
+            .body()?;
+
+        futures_util::stream::iter(paths)
+            .map(|path| {
+                let secret = secrets.remove(&path);
+                async move {
+                    let (_, iv, value, _) =
+                        secret.with_context(|| format!("The secret of {path} is missing"))?;
+                    let item = zbus::ProxyBuilder::<zbus::Proxy>::new_bare(&self.connection)
+                        .destination(DESTINATION)?
+                        .path(path.as_str())?
+                        .interface(ITEM_INTERFACE)?
+                        .cache_properties(zbus::CacheProperties::No)
+                        .build()
+                        .await?;
+                    let label = if labels {
+                        item.get_property("Label").await?
+                    } else {
+                        String::new()
+                    };
+                    anyhow::Ok(SecretItem {
+                        label,
+                        attributes: item.get_property("Attributes").await?,
+                        secret: decrypt(&self.key, &iv, &value)?,
+                    })
+                }
+            })
+            .buffered(CONCURRENT_FETCHES)
+            .try_collect()
+            .await
+    }
+}
+
+fn public_key(private_key: &[u8]) -> Vec<u8> {
+    BigUint::from(2u8)
+        .modpow(
+            &BigUint::from_bytes_be(private_key),
+            &BigUint::from_bytes_be(&DH_PRIME),
+        )
+        .to_bytes_be()
+}
+
+fn aes_key(private_key: &[u8], server_key: &[u8]) -> Zeroizing<[u8; 16]> {
+    let shared_secret = BigUint::from_bytes_be(server_key)
+        .modpow(
+            &BigUint::from_bytes_be(private_key),
+            &BigUint::from_bytes_be(&DH_PRIME),
+        )
+        .to_bytes_be();
+    let mut input = Zeroizing::new([0; 128]);
+    input[128 - shared_secret.len()..].copy_from_slice(&shared_secret);
+
+    let mut key = Zeroizing::new([0; 16]);
+    Hkdf::<Sha256>::new(None, input.as_ref())
+        .expand(&[], key.as_mut())
+        .expect("16 bytes is a valid output length");
+    key
+}
+
+fn decrypt(key: &[u8; 16], iv: &[u8], value: &[u8]) -> anyhow::Result<Zeroizing<Vec<u8>>> {
+    let mut data = Zeroizing::new(value.to_vec());
+    let len = DecAlg::new_from_slices(key, iv)
+        .map_err(|_| anyhow::anyhow!("Invalid secret parameters"))?
+        .decrypt_padded_mut::<Pkcs7>(&mut data)
+        .map_err(|_| anyhow::anyhow!("Failed to decrypt the secret"))?
+        .len();
+    data.truncate(len);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use aes::cipher::BlockEncryptMut;
+
+    use super::*;
+
+    #[test]
+    fn key_exchange() {
+        // The vectors of `oo7`
+        let private_key = [
+            41, 20, 63, 236, 246, 132, 109, 70, 172, 121, 45, 66, 129, 21, 247, 91, 96, 217, 56,
+            201, 205, 56, 17, 178, 202, 81, 71, 104, 233, 89, 87, 32, 88, 146, 107, 224, 56, 103,
+            111, 74, 143, 80, 170, 40, 5, 52, 48, 90, 75, 71, 193, 224, 222, 57, 91, 81, 66, 1, 6,
+            88, 137, 66, 102, 207, 55, 95, 67, 92, 140, 227, 242, 153, 185, 195, 89, 236, 146, 242,
+            88, 215, 1, 7, 135, 254, 85, 165, 236, 110, 22, 79, 107, 254, 149, 164, 243, 94, 129,
+            198, 45, 208, 132, 166, 0, 153, 243, 160, 255, 188, 59, 216, 99, 221, 85, 162, 116,
+            210, 160, 117, 201, 39, 179, 123, 107, 8, 242, 139, 207, 250,
+        ];
+        let server_key = [
+            50, 233, 76, 88, 47, 206, 235, 107, 9, 232, 98, 14, 188, 214, 209, 77, 35, 66, 109,
+            119, 24, 191, 120, 90, 242, 198, 240, 115, 200, 66, 51, 180, 8, 164, 89, 9, 229, 31,
+            160, 31, 156, 101, 169, 60, 63, 247, 37, 255, 75, 198, 62, 235, 50, 29, 221, 245, 29,
+            248, 140, 209, 62, 215, 2, 137, 82, 77, 248, 242, 56, 176, 118, 183, 124, 74, 26, 133,
+            188, 47, 31, 141, 232, 194, 92, 18, 69, 3, 56, 153, 42, 9, 143, 81, 197, 159, 200, 197,
+            221, 74, 186, 157, 158, 36, 74, 125, 11, 234, 33, 2, 5, 36, 206, 248, 155, 157, 145,
+            159, 238, 19, 185, 194, 134, 3, 195, 198, 60, 100, 159, 31,
+        ];
+        let public = [
+            9, 192, 210, 81, 212, 191, 74, 119, 22, 172, 81, 142, 124, 89, 17, 71, 118, 190, 81,
+            71, 49, 149, 200, 204, 14, 47, 111, 165, 119, 103, 216, 102, 111, 93, 242, 64, 73, 224,
+            165, 11, 127, 219, 197, 188, 168, 222, 254, 10, 104, 81, 8, 206, 237, 119, 225, 100,
+            78, 196, 89, 163, 63, 169, 77, 236, 80, 241, 189, 49, 27, 40, 243, 229, 66, 53, 80, 86,
+            44, 213, 87, 186, 68, 55, 216, 56, 236, 51, 229, 44, 174, 18, 87, 141, 85, 71, 185,
+            203, 208, 144, 190, 117, 141, 255, 153, 106, 123, 28, 152, 200, 237, 189, 176, 20, 80,
+            211, 33, 158, 232, 194, 145, 45, 194, 35, 108, 106, 214, 221, 159, 137,
+        ];
+        assert_eq!(public_key(&private_key), public);
+        assert_eq!(
+            *aes_key(&private_key, &server_key),
+            [132, 3, 113, 222, 81, 209, 49, 43, 81, 232, 243, 46, 1, 103, 184, 42]
+        );
+    }
+
+    #[test]
+    fn decryption() {
+        let key = [7; 16];
+        let iv = [1; 16];
+        let mut buffer = [0; 16];
+        let encrypted = cbc::Encryptor::<aes::Aes128>::new_from_slices(&key, &iv)
+            .unwrap()
+            .encrypt_padded_b2b_mut::<Pkcs7>(b"TOKEN", &mut buffer)
+            .unwrap();
+        assert_eq!(decrypt(&key, &iv, encrypted).unwrap().as_slice(), b"TOKEN");
+        assert!(decrypt(&key, &iv, &encrypted[1..]).is_err());
+        assert!(decrypt(&key, &iv[1..], encrypted).is_err());
+    }
+}
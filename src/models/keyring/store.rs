@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+#[cfg(test)]
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+
+use anyhow::{Context, Result};
+use futures_util::{future::BoxFuture, StreamExt, TryStreamExt};
+use zeroize::Zeroizing;
+
+use super::secrets_session::SecretsSession;
+
+pub type Attributes<'a> = HashMap<&'a str, &'a str>;
+
+/// A secret, identified by its attributes.
+#[derive(Debug, Clone)]
+pub struct SecretItem {
+    pub label: String,
+    pub attributes: HashMap<String, String>,
+    pub secret: Zeroizing<Vec<u8>>,
+}
+
+impl SecretItem {
+    pub fn attributes(&self) -> Attributes<'_> {
+        self.attributes
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect()
+    }
+
+    /// Whether the item has all of `attributes`.
+    pub fn matches(&self, attributes: &Attributes<'_>) -> bool {
+        attributes
+            .iter()
+            .all(|(key, value)| self.attributes.get(*key).map(String::as_str) == Some(*value))
+    }
+}
+
+/// Where the tokens, PINs & passwords are stored.
+pub trait SecretStore: Send + Sync {
+    /// Stores `secret`, replacing the item with the same `attributes`.
+    fn store<'a>(
+        &'a self,
+        label: &'a str,
+        attributes: Attributes<'a>,
+        secret: &'a [u8],
+    ) -> BoxFuture<'a, Result<()>>;
+
+    /// The items that have all of `attributes`.
+    fn search<'a>(&'a self, attributes: Attributes<'a>) -> BoxFuture<'a, Result<Vec<SecretItem>>>;
+
+    /// Same as [`SecretStore::search`], leaving the labels of the items empty
+    /// when the store can skip fetching them.
+    fn search_unlabeled<'a>(
+        &'a self,
+        attributes: Attributes<'a>,
+    ) -> BoxFuture<'a, Result<Vec<SecretItem>>> {
+        self.search(attributes)
+    }
+
+    /// Removes the items that have all of `attributes`.
+    fn delete<'a>(&'a self, attributes: Attributes<'a>) -> BoxFuture<'a, Result<()>>;
+}
+
+/// The Secret Service, or the file backend of `oo7` when sandboxed.
+pub struct Oo7Store {
+    keyring: oo7::Keyring,
+    /// Fetches the secrets in bulk from the Secret Service.
+    secrets: Option<SecretsSession>,
+}
+
+/// How many items are fetched at the same time from the keyring.
+const CONCURRENT_FETCHES: usize = 16;
+
+impl Oo7Store {
+    /// Starts the keyring service and unlocks the default collection.
+    pub async fn new() -> Result<Self> {
+        let keyring = oo7::Keyring::new()
+            .await
+            .context("Failed to start the keyring service")?;
+        keyring
+            .unlock()
+            .await
+            .context("Failed to unlock the default collection")?;
+        let secrets = match keyring {
+            oo7::Keyring::DBus(_) => match SecretsSession::open().await {
+                Ok(session) => Some(session),
+                Err(err) => {
+                    tracing::warn!("Failed to open a session with the Secret Service {err}");
+                    None
+                }
+            },
+            oo7::Keyring::File(_) => None,
+        };
+        Ok(Self { keyring, secrets })
+    }
+
+    async fn search_items(
+        &self,
+        attributes: Attributes<'_>,
+        labels: bool,
+    ) -> Result<Vec<SecretItem>> {
+        if let Some(session) = &self.secrets {
+            return session.search(attributes, labels).await;
+        }
+        let items = self.keyring.search_items(attributes).await?;
+        futures_util::stream::iter(items)
+            .map(|item| async move {
+                let label = if labels {
+                    item.label().await?
+                } else {
+                    String::new()
+                };
+                anyhow::Ok(SecretItem {
+                    label,
+                    attributes: item.attributes().await?,
+                    secret: item.secret().await?,
+                })
+            })
+            .buffered(CONCURRENT_FETCHES)
+            .try_collect()
+            .await
+    }
+}
+
+impl SecretStore for Oo7Store {
+    fn store<'a>(
+        &'a self,
+        label: &'a str,
+        attributes: Attributes<'a>,
+        secret: &'a [u8],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.keyring
+                .create_item(label, attributes, secret, true)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn search<'a>(&'a self, attributes: Attributes<'a>) -> BoxFuture<'a, Result<Vec<SecretItem>>> {
+        Box::pin(self.search_items(attributes, true))
+    }
+
+    fn search_unlabeled<'a>(
+        &'a self,
+        attributes: Attributes<'a>,
+    ) -> BoxFuture<'a, Result<Vec<SecretItem>>> {
+        Box::pin(self.search_items(attributes, false))
+    }
+
+    fn delete<'a>(&'a self, attributes: Attributes<'a>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.keyring.delete(attributes).await?;
+            Ok(())
+        })
+    }
+}
+
+/// Keeps the secrets in memory, for the tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    items: Mutex<Vec<SecretItem>>,
+    searches: AtomicUsize,
+    remaining_stores: Mutex<Option<usize>>,
+}
+
+#[cfg(test)]
+impl MemoryStore {
+    /// How many times the items were searched.
+    pub fn searches(&self) -> usize {
+        self.searches.load(Ordering::SeqCst)
+    }
+
+    pub fn reset_searches(&self) {
+        self.searches.store(0, Ordering::SeqCst);
+    }
+
+    /// Makes storing items fail once `stores` more were stored, as if the
+    /// application was interrupted. `None` to stop failing.
+    pub fn fail_after(&self, stores: Option<usize>) {
+        *self.remaining_stores.lock().unwrap() = stores;
+    }
+}
+
+#[cfg(test)]
+impl SecretStore for MemoryStore {
+    fn store<'a>(
+        &'a self,
+        label: &'a str,
+        attributes: Attributes<'a>,
+        secret: &'a [u8],
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match self.remaining_stores.lock().unwrap().as_mut() {
+                Some(0) => anyhow::bail!("The store is unavailable"),
+                Some(remaining) => *remaining -= 1,
+                None => (),
+            }
+            let mut items = self.items.lock().unwrap();
+            items.retain(|item| item.attributes() != attributes);
+            items.push(SecretItem {
+                label: label.to_owned(),
+                attributes: attributes
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+                secret: Zeroizing::new(secret.to_vec()),
+            });
+            Ok(())
+        })
+    }
+
+    fn search<'a>(&'a self, attributes: Attributes<'a>) -> BoxFuture<'a, Result<Vec<SecretItem>>> {
+        Box::pin(async move {
+            self.searches.fetch_add(1, Ordering::SeqCst);
+            let items = self.items.lock().unwrap();
+            Ok(items
+                .iter()
+                .filter(|item| item.matches(&attributes))
+                .cloned()
+                .collect())
+        })
+    }
+
+    fn delete<'a>(&'a self, attributes: Attributes<'a>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.items
+                .lock()
+                .unwrap()
+                .retain(|item| !item.matches(&attributes));
+            Ok(())
+        })
+    }
+}
+
+/// The behaviour every [`SecretStore`] is expected to have, `store` has to be
+/// empty.
+#[cfg(test)]
+pub async fn conformance(store: &dyn SecretStore) {
+    let token = |id| HashMap::from([("application", "test"), ("type", "token"), ("token_id", id)]);
+    let tokens = || HashMap::from([("application", "test"), ("type", "token")]);
+
+    assert!(store.search(tokens()).await.unwrap().is_empty());
+    // Deleting missing items is not an error
+    store.delete(token("1")).await.unwrap();
+
+    store.store("First", token("1"), b"secret 1").await.unwrap();
+    store
+        .store("Second", token("2"), b"secret 2")
+        .await
+        .unwrap();
+    store
+        .store(
+            "Password",
+            HashMap::from([("application", "test"), ("type", "password")]),
+            b"password",
+        )
+        .await
+        .unwrap();
+
+    let items = store.search(token("1")).await.unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].label, "First");
+    assert_eq!(items[0].attributes(), token("1"));
+    assert_eq!(items[0].secret.as_slice(), b"secret 1");
+    let items = store.search_unlabeled(token("1")).await.unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].attributes(), token("1"));
+    assert_eq!(items[0].secret.as_slice(), b"secret 1");
+
+    // Searching matches a subset of the attributes
+    let mut secrets = store
+        .search(tokens())
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|item| item.secret.to_vec())
+        .collect::<Vec<_>>();
+    secrets.sort();
+    assert_eq!(secrets, [b"secret 1".to_vec(), b"secret 2".to_vec()]);
+    assert_eq!(
+        store
+            .search(HashMap::from([("application", "test")]))
+            .await
+            .unwrap()
+            .len(),
+        3
+    );
+    assert!(store
+        .search(HashMap::from([("application", "other")]))
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Storing with the same attributes replaces the item
+    store
+        .store("First", token("1"), b"new secret 1")
+        .await
+        .unwrap();
+    let items = store.search(token("1")).await.unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].secret.as_slice(), b"new secret 1");
+
+    store.delete(token("1")).await.unwrap();
+    assert!(store.search(token("1")).await.unwrap().is_empty());
+    assert_eq!(store.search(token("2")).await.unwrap().len(), 1);
+
+    store.delete(tokens()).await.unwrap();
+    assert!(store.search(tokens()).await.unwrap().is_empty());
+    assert_eq!(
+        store
+            .search(HashMap::from([("application", "test")]))
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::RngCore;
+
+    use super::*;
+    use crate::models::RUNTIME;
+
+    #[test]
+    fn memory_store() {
+        RUNTIME.block_on(conformance(&MemoryStore::default()));
+    }
+
+    #[test]
+    fn oo7_file_store() {
+        let path = std::env::temp_dir().join(format!("authenticator-oo7-{}", uuid::Uuid::new_v4()));
+        let mut secret = vec![0u8; 64];
+        rand::thread_rng().fill_bytes(&mut secret);
+        RUNTIME.block_on(async {
+            let keyring = oo7::portal::Keyring::load(&path, secret.into())
+                .await
+                .unwrap();
+            let store = Oo7Store {
+                keyring: oo7::Keyring::File(std::sync::Arc::new(keyring)),
+                secrets: None,
+            };
+            conformance(&store).await;
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+}
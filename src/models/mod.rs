@@ -3,6 +3,9 @@ use gtk::glib::once_cell::sync::Lazy;
 mod account;
 mod accounts;
 mod algorithm;
+pub mod clipboard;
+mod clock;
+mod codes_service;
 pub mod database;
 pub mod i18n;
 pub mod keyring;
@@ -11,7 +14,9 @@ mod otp_uri;
 mod provider;
 mod providers;
 mod search_provider;
+mod session_monitor;
 mod settings;
+mod ticker;
 
 pub static RUNTIME: Lazy<tokio::runtime::Runtime> =
     Lazy::new(|| tokio::runtime::Runtime::new().unwrap());
@@ -22,15 +27,20 @@ pub static FAVICONS_PATH: Lazy<std::path::PathBuf> = Lazy::new(|| {
         .join("favicons")
 });
 
+#[cfg(test)]
+pub use self::clock::FakeClock;
 pub use self::{
     account::Account,
     accounts::AccountsModel,
     algorithm::{Algorithm, Method},
-    keyring::SECRET_SERVICE,
+    clock::{Clock, SystemClock},
+    codes_service::{start as start_codes_service, CodesAccount, CodesAction, CodesCaller},
     otp::OTP,
     otp_uri::OTPUri,
     provider::{DieselProvider, Provider, ProviderPatch},
-    providers::ProvidersModel,
+    providers::{ProviderChange, ProvidersModel},
     search_provider::{start, SearchProviderAction},
+    session_monitor::{start as start_session_monitor, SessionEvent},
     settings::Settings,
+    ticker::Ticker,
 };
@@ -1,19 +1,23 @@
-use std::{
-    convert::TryInto,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::convert::TryInto;
 
 use anyhow::{anyhow, Result};
 use data_encoding::BASE32_NOPAD;
-use ring::hmac;
-use zeroize::{Zeroize, ZeroizeOnDrop};
+use hmac::{
+    digest::{core_api::BlockSizeUser, Digest},
+    Mac, SimpleHmac,
+};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Sha224, Sha256, Sha384, Sha512};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
-use super::Algorithm;
+use super::{Algorithm, Clock, Method};
 
 #[derive(Debug, Zeroize, ZeroizeOnDrop)]
 #[allow(clippy::upper_case_acronyms)]
 pub struct OTP {
     secret: Vec<u8>,
+    pin: Option<String>,
     #[zeroize(skip)]
     algorithm: Algorithm,
     #[zeroize(skip)]
@@ -24,6 +28,13 @@ impl OTP {
     const STEAM_CHARS: &str = "23456789BCDFGHJKMNPQRTVWXY";
     pub const STEAM_DEFAULT_PERIOD: u32 = 30;
     pub const STEAM_DEFAULT_DIGITS: u32 = 5;
+    const YANDEX_CHARS: &str = "abcdefghijklmnopqrstuvwxyz";
+    // Yandex secrets are 26 bytes long, only the first 16 are used as a key.
+    const YANDEX_SECRET_LENGTH: usize = 16;
+    pub const YANDEX_DEFAULT_PERIOD: u32 = 30;
+    pub const YANDEX_DEFAULT_DIGITS: u32 = 8;
+    pub const MOTP_DEFAULT_PERIOD: u32 = 10;
+    pub const MOTP_DEFAULT_DIGITS: u32 = 6;
     pub const DEFAULT_COUNTER: u32 = 1;
     pub const DEFAULT_DIGITS: u32 = 6;
     pub const DEFAULT_PERIOD: u32 = 30;
@@ -33,12 +44,19 @@ impl OTP {
         decode_secret(secret).is_ok()
     }
 
-    fn time_based_counter(period: u32) -> u64 {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        timestamp / period as u64
+    /// The duration of a time step of `method`. Steam, Yandex and mOTP codes
+    /// are always computed with their default period.
+    pub fn step_period(method: Method, period: u32) -> u32 {
+        match method {
+            Method::Steam => Self::STEAM_DEFAULT_PERIOD,
+            Method::Yandex => Self::YANDEX_DEFAULT_PERIOD,
+            Method::MOTP => Self::MOTP_DEFAULT_PERIOD,
+            Method::TOTP | Method::HOTP => period,
+        }
+    }
+
+    fn time_based_counter(clock: &dyn Clock, method: Method, period: u32) -> u64 {
+        clock.timestamp() / Self::step_period(method, period) as u64
     }
 
     pub fn from_bytes_steam(secret: impl AsRef<[u8]>) -> Self {
@@ -49,6 +67,29 @@ impl OTP {
         Self::from_str(secret, Algorithm::SHA1, Self::STEAM_DEFAULT_DIGITS)
     }
 
+    pub fn from_bytes_yandex(secret: impl AsRef<[u8]>, pin: &str) -> Self {
+        Self::from_bytes(secret, Algorithm::SHA256, Self::YANDEX_DEFAULT_DIGITS).with_pin(Some(pin))
+    }
+
+    pub fn from_str_yandex(secret: &str, pin: &str) -> Result<Self> {
+        Ok(
+            Self::from_str(secret, Algorithm::SHA256, Self::YANDEX_DEFAULT_DIGITS)?
+                .with_pin(Some(pin)),
+        )
+    }
+
+    pub fn from_bytes_motp(secret: impl AsRef<[u8]>, pin: &str) -> Self {
+        Self::from_bytes(secret, Algorithm::default(), Self::MOTP_DEFAULT_DIGITS)
+            .with_pin(Some(pin))
+    }
+
+    pub fn from_str_motp(secret: &str, pin: &str) -> Result<Self> {
+        Ok(
+            Self::from_str(secret, Algorithm::default(), Self::MOTP_DEFAULT_DIGITS)?
+                .with_pin(Some(pin)),
+        )
+    }
+
     pub fn from_str_with_defaults(secret: &str) -> Result<Self> {
         let decoded = decode_secret(secret)?;
         Ok(Self::from_bytes_with_defaults(decoded))
@@ -62,6 +103,7 @@ impl OTP {
     pub fn from_bytes_with_defaults(secret: impl AsRef<[u8]>) -> Self {
         Self {
             secret: secret.as_ref().to_owned(),
+            pin: None,
             algorithm: Algorithm::default(),
             digits: Self::DEFAULT_DIGITS,
         }
@@ -70,11 +112,18 @@ impl OTP {
     pub fn from_bytes(secret: impl AsRef<[u8]>, algorithm: Algorithm, digits: u32) -> Self {
         Self {
             secret: secret.as_ref().to_owned(),
+            pin: None,
             algorithm,
             digits,
         }
     }
 
+    /// Sets the PIN used by the Yandex & mOTP methods.
+    pub fn with_pin(mut self, pin: Option<&str>) -> Self {
+        self.pin = pin.map(ToOwned::to_owned);
+        self
+    }
+
     /// Performs the [HMAC-based One-time Password Algorithm](http://en.wikipedia.org/wiki/HMAC-based_One-time_Password_Algorithm)
     /// (HOTP) given an RFC4648 base32 encoded secret, and an integer counter.
     pub fn hotp(&self, counter: u64) -> Result<u32> {
@@ -86,18 +135,7 @@ impl OTP {
         self.hotp(counter).map(|d| format(d, self.digits as usize))
     }
 
-    pub fn totp(&self, period: Option<u32>) -> Result<u32> {
-        let counter = Self::time_based_counter(period.unwrap_or(Self::DEFAULT_PERIOD));
-        self.hotp(counter)
-    }
-
-    pub fn totp_formatted(&self, period: Option<u32>) -> Result<String> {
-        let counter = Self::time_based_counter(period.unwrap_or(Self::DEFAULT_PERIOD));
-        self.hotp_formatted(counter)
-    }
-
-    pub fn steam(&self, counter: Option<u64>) -> Result<String> {
-        let counter = counter.unwrap_or(Self::time_based_counter(Self::STEAM_DEFAULT_PERIOD));
+    pub fn steam(&self, counter: u64) -> Result<String> {
         let mut full_token = encode_digest(calc_digest(&self.secret, counter, Algorithm::SHA1))?;
 
         let mut code = String::new();
@@ -111,9 +149,130 @@ impl OTP {
         Ok(code)
     }
 
+    /// Performs the Yandex variant of TOTP, the key being derived from the PIN
+    /// and the secret. The code is made of 8 Latin lowercase letters.
+    pub fn yandex(&self, counter: u64) -> Result<String> {
+        let pin = self
+            .pin
+            .as_deref()
+            .ok_or_else(|| anyhow!("Yandex requires a PIN"))?;
+        let secret = &self.secret[..self.secret.len().min(Self::YANDEX_SECRET_LENGTH)];
+
+        let mut pin_with_secret = Zeroizing::new(pin.as_bytes().to_vec());
+        pin_with_secret.extend_from_slice(secret);
+        let key_hash = Sha256::digest(&*pin_with_secret);
+        let mut key = key_hash.as_slice();
+        if key[0] == 0 {
+            key = &key[1..];
+        }
+
+        let digest = calc_digest(key, counter, Algorithm::SHA256);
+        let offset = (digest[digest.len() - 1] & 0xf) as usize;
+        let code_bytes: [u8; 8] = digest[offset..offset + 8]
+            .try_into()
+            .map_err(|_| anyhow!("Invalid digest"))?;
+        let mut full_token = (u64::from_be_bytes(code_bytes) & 0x7fff_ffff_ffff_ffff)
+            % (Self::YANDEX_CHARS.len() as u64).pow(Self::YANDEX_DEFAULT_DIGITS);
+
+        let total_chars = Self::YANDEX_CHARS.len() as u64;
+        let mut code = Vec::with_capacity(Self::YANDEX_DEFAULT_DIGITS as usize);
+        for _ in 0..Self::YANDEX_DEFAULT_DIGITS {
+            let pos = full_token % total_chars;
+            code.push(Self::YANDEX_CHARS.as_bytes()[pos as usize]);
+            full_token /= total_chars;
+        }
+        code.reverse();
+        Ok(String::from_utf8(code)?)
+    }
+
+    /// Performs the [Mobile-OTP](https://motp.sourceforge.net/) algorithm,
+    /// the code being the first digits of MD5(epoch / 10 + secret + PIN).
+    pub fn motp(&self, counter: u64) -> Result<String> {
+        let pin = self
+            .pin
+            .as_deref()
+            .ok_or_else(|| anyhow!("mOTP requires a PIN"))?;
+
+        let source = Zeroizing::new(format!("{counter}{}{pin}", hex::encode(&self.secret)));
+        let digest = hex::encode(Md5::digest(source.as_bytes()));
+        Ok(digest[..Self::MOTP_DEFAULT_DIGITS as usize].to_owned())
+    }
+
+    /// Generates the code of `method` for the given counter, which is the
+    /// time step for the time based methods.
+    pub fn generate(&self, method: Method, counter: u64) -> Result<String> {
+        match method {
+            Method::TOTP | Method::HOTP => self.hotp_formatted(counter),
+            Method::Steam => self.steam(counter),
+            Method::Yandex => self.yandex(counter),
+            Method::MOTP => self.motp(counter),
+        }
+    }
+
+    /// Generates the current code of the time based `method` according to
+    /// `clock`.
+    pub fn generate_at(&self, method: Method, period: u32, clock: &dyn Clock) -> Result<String> {
+        self.generate(method, Self::time_based_counter(clock, method, period))
+    }
+
+    /// Generates the code of the time based `method` that follows the current
+    /// one according to `clock`.
+    pub fn generate_next_at(
+        &self,
+        method: Method,
+        period: u32,
+        clock: &dyn Clock,
+    ) -> Result<String> {
+        self.generate(method, Self::time_based_counter(clock, method, period) + 1)
+    }
+
+    /// Looks for `code` among the current time step of `method` according to
+    /// `clock` and the `window` steps before and after it.
+    ///
+    /// Returns the offset in steps of the matching code, if any.
+    pub fn verify(
+        &self,
+        code: &str,
+        method: Method,
+        period: u32,
+        window: u32,
+        clock: &dyn Clock,
+    ) -> Result<Option<i64>> {
+        let counter = Self::time_based_counter(clock, method, period);
+        self.verify_counter(code, method, counter, window)
+    }
+
+    /// Same as [`OTP::verify`] but around a given counter, useful for the
+    /// event based methods.
+    pub fn verify_counter(
+        &self,
+        code: &str,
+        method: Method,
+        counter: u64,
+        window: u32,
+    ) -> Result<Option<i64>> {
+        let code = code.replace(char::is_whitespace, "");
+        // Look for the closest steps first.
+        let steps = std::iter::once(0).chain((1..=window as i64).flat_map(|step| [-step, step]));
+        for step in steps {
+            let Some(counter) = counter.checked_add_signed(step) else {
+                continue;
+            };
+            let generated = self.generate(method, counter)?.replace(' ', "");
+            if generated.eq_ignore_ascii_case(&code) {
+                return Ok(Some(step));
+            }
+        }
+        Ok(None)
+    }
+
     pub fn secret(&self) -> String {
         data_encoding::BASE32_NOPAD.encode(&self.secret)
     }
+
+    pub fn pin(&self) -> Option<String> {
+        self.pin.clone()
+    }
 }
 
 /// Code graciously taken from the rust-otp crate.
@@ -142,18 +301,39 @@ fn format(code: u32, digits: usize) -> String {
 }
 
 /// Calculates the HMAC digest for the given secret and counter.
-fn calc_digest(decoded_secret: impl AsRef<[u8]>, counter: u64, algorithm: Algorithm) -> hmac::Tag {
-    let key = hmac::Key::new(algorithm.into(), decoded_secret.as_ref());
-    hmac::sign(&key, &counter.to_be_bytes())
+fn calc_digest(decoded_secret: impl AsRef<[u8]>, counter: u64, algorithm: Algorithm) -> Vec<u8> {
+    let key = decoded_secret.as_ref();
+    let counter = counter.to_be_bytes();
+    match algorithm {
+        Algorithm::SHA1 => hmac_sign::<Sha1>(key, &counter),
+        Algorithm::SHA224 => hmac_sign::<Sha224>(key, &counter),
+        Algorithm::SHA256 => hmac_sign::<Sha256>(key, &counter),
+        Algorithm::SHA384 => hmac_sign::<Sha384>(key, &counter),
+        Algorithm::SHA512 => hmac_sign::<Sha512>(key, &counter),
+        Algorithm::MD5 => hmac_sign::<Md5>(key, &counter),
+    }
+}
+
+/// Signs `data` using HMAC with the hash function `D`.
+fn hmac_sign<D: Digest + BlockSizeUser>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC accepts keys of any size, this can't fail.
+    let mut mac = <SimpleHmac<D> as Mac>::new_from_slice(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
 }
 
 /// Encodes the HMAC digest into a n-digit integer.
 fn encode_digest(digest: impl AsRef<[u8]>) -> Result<u32> {
     let digest = digest.as_ref();
+    if digest.len() < 4 {
+        anyhow::bail!("Invalid digest");
+    }
+    // MD5 digests are only 16 bytes long, keep the 4 bytes window inside of it.
     let offset = match digest.last() {
-        Some(x) => *x & 0xf,
+        Some(x) => (*x & 0xf) as usize,
         None => anyhow::bail!("Invalid digest"),
-    } as usize;
+    }
+    .min(digest.len() - 4);
     let code_bytes: [u8; 4] = match digest[offset..offset + 4].try_into() {
         Ok(x) => x,
         Err(_) => anyhow::bail!("Invalid digest"),
@@ -165,7 +345,8 @@ fn encode_digest(digest: impl AsRef<[u8]>) -> Result<u32> {
 // Some of the tests are heavily inspired(copy-paste) of the andOTP application
 #[cfg(test)]
 mod tests {
-    use super::{format, Algorithm, OTP};
+    use super::{format, Algorithm, Method, OTP};
+    use crate::models::FakeClock;
 
     #[test]
     fn totp() {
@@ -208,6 +389,48 @@ mod tests {
         assert_eq!(Some(47863826), otp3.hotp(counter6).ok());
     }
 
+    #[test]
+    fn totp_other_algorithms() {
+        let secret_md5 = b"1234567890123456";
+        let secret_sha224 = b"1234567890123456789012345678";
+        let secret_sha384 = b"123456789012345678901234567890123456789012345678";
+
+        let otp1 = OTP::from_bytes(secret_md5, Algorithm::MD5, 8);
+        let otp2 = OTP::from_bytes(secret_sha224, Algorithm::SHA224, 8);
+        let otp3 = OTP::from_bytes(secret_sha384, Algorithm::SHA384, 8);
+
+        let counter1 = 59 / OTP::DEFAULT_PERIOD as u64;
+        assert_eq!(Some(85931907), otp1.hotp(counter1).ok());
+        assert_eq!(Some(32201820), otp2.hotp(counter1).ok());
+        assert_eq!(Some(12260385), otp3.hotp(counter1).ok());
+
+        let counter2 = 1111111109 / OTP::DEFAULT_PERIOD as u64;
+        assert_eq!(Some(48407180), otp1.hotp(counter2).ok());
+        assert_eq!(Some(82019503), otp2.hotp(counter2).ok());
+        assert_eq!(Some(93607533), otp3.hotp(counter2).ok());
+
+        let counter3 = 1111111111 / OTP::DEFAULT_PERIOD as u64;
+        assert_eq!(Some(24643869), otp1.hotp(counter3).ok());
+        assert_eq!(Some(98501000), otp2.hotp(counter3).ok());
+        assert_eq!(Some(30635264), otp3.hotp(counter3).ok());
+
+        // The truncation offset of the MD5 digest overflows here
+        let counter4 = 1234567890 / OTP::DEFAULT_PERIOD as u64;
+        assert_eq!(Some(83262559), otp1.hotp(counter4).ok());
+        assert_eq!(Some(25631841), otp2.hotp(counter4).ok());
+        assert_eq!(Some(29066410), otp3.hotp(counter4).ok());
+
+        let counter5 = 2000000000 / OTP::DEFAULT_PERIOD as u64;
+        assert_eq!(Some(24615425), otp1.hotp(counter5).ok());
+        assert_eq!(Some(97691324), otp2.hotp(counter5).ok());
+        assert_eq!(Some(45679189), otp3.hotp(counter5).ok());
+
+        let counter6 = 20000000000 / OTP::DEFAULT_PERIOD as u64;
+        assert_eq!(Some(38292148), otp1.hotp(counter6).ok());
+        assert_eq!(Some(81596645), otp2.hotp(counter6).ok());
+        assert_eq!(Some(33578931), otp3.hotp(counter6).ok());
+    }
+
     #[test]
     fn hotp() {
         let otp = OTP::from_str_with_defaults("BASE32SECRET3232").unwrap();
@@ -231,8 +454,134 @@ mod tests {
     #[test]
     fn steam() {
         let token = OTP::from_str_steam("BASE32SECRET3232").unwrap();
-        assert_eq!(token.steam(Some(0)).ok(), Some("2TC8B".into()));
-        assert_eq!(token.steam(Some(1)).ok(), Some("YKKK4".into()));
+        assert_eq!(token.steam(0).ok(), Some("2TC8B".into()));
+        assert_eq!(token.steam(1).ok(), Some("YKKK4".into()));
+    }
+
+    #[test]
+    fn yandex() {
+        let token =
+            OTP::from_str_yandex("6SB2IKNM6OBZPAVBVTOHDKS4FAAAAAAADFUTQMBTRY", "5239").unwrap();
+        let counter = 1641559648 / OTP::YANDEX_DEFAULT_PERIOD as u64;
+        assert_eq!(token.yandex(counter).ok(), Some("umozdicq".into()));
+
+        let token =
+            OTP::from_str_yandex("LA2V6KMCGYMWWVEW64RNP3JA3IAAAAAAHTSG4HRZPI", "7586").unwrap();
+        let counter = 1581064020 / OTP::YANDEX_DEFAULT_PERIOD as u64;
+        assert_eq!(token.yandex(counter).ok(), Some("oactmacq".into()));
+        let counter = 1581090810 / OTP::YANDEX_DEFAULT_PERIOD as u64;
+        assert_eq!(token.yandex(counter).ok(), Some("wemdwrix".into()));
+
+        let token = OTP::from_str_yandex(
+            "JBGSAU4G7IEZG6OY4UAXX62JU4AAAAAAHTSG4HXU3M",
+            "5210481216086702",
+        )
+        .unwrap();
+        let counter = 1581091469 / OTP::YANDEX_DEFAULT_PERIOD as u64;
+        assert_eq!(token.yandex(counter).ok(), Some("dfrpywob".into()));
+        let counter = 1581093059 / OTP::YANDEX_DEFAULT_PERIOD as u64;
+        assert_eq!(token.yandex(counter).ok(), Some("vunyprpd".into()));
+
+        let token =
+            OTP::from_str_with_defaults("6SB2IKNM6OBZPAVBVTOHDKS4FAAAAAAADFUTQMBTRY").unwrap();
+        assert!(token.yandex(0).is_err());
+    }
+
+    #[test]
+    fn motp() {
+        let secret = hex::decode("e3152afee62599c8").unwrap();
+        let token = OTP::from_bytes_motp(secret, "1234");
+        let counter = 165892298 / OTP::MOTP_DEFAULT_PERIOD as u64;
+        assert_eq!(token.motp(counter).ok(), Some("e7d8b6".into()));
+        let counter = 123456789 / OTP::MOTP_DEFAULT_PERIOD as u64;
+        assert_eq!(token.motp(counter).ok(), Some("4ebfb2".into()));
+
+        let token = OTP::from_str_motp("4MKSV7XGEWM4Q", "1234").unwrap();
+        assert_eq!(token.motp(counter).ok(), Some("4ebfb2".into()));
+    }
+
+    #[test]
+    fn verify() {
+        let otp = OTP::from_bytes(b"12345678901234567890", Algorithm::SHA1, 8);
+        let counter = 1111111109 / OTP::DEFAULT_PERIOD as u64;
+
+        assert_eq!(
+            otp.verify_counter("07081804", Method::TOTP, counter, 0)
+                .unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            otp.verify_counter("0708 1804", Method::TOTP, counter - 2, 2)
+                .unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            otp.verify_counter("07081804", Method::TOTP, counter + 1, 1)
+                .unwrap(),
+            Some(-1)
+        );
+        assert_eq!(
+            otp.verify_counter("07081804", Method::TOTP, counter + 3, 2)
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            otp.verify_counter("14050471", Method::TOTP, counter, 1)
+                .unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            otp.verify_counter("89005924", Method::TOTP, counter, 10)
+                .unwrap(),
+            None
+        );
+
+        let steam = OTP::from_str_steam("BASE32SECRET3232").unwrap();
+        assert_eq!(
+            steam.verify_counter("2TC8B", Method::Steam, 1, 1).unwrap(),
+            Some(-1)
+        );
+        assert_eq!(
+            steam.verify_counter("2tc8b", Method::Steam, 0, 0).unwrap(),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn generate_at() {
+        let clock = FakeClock::new(59);
+        let otp = OTP::from_bytes(b"12345678901234567890", Algorithm::SHA1, 8);
+        assert_eq!(
+            otp.generate_at(Method::TOTP, 30, &clock).ok(),
+            Some("94 287 082".into())
+        );
+        clock.set(1111111109);
+        assert_eq!(
+            otp.generate_at(Method::TOTP, 30, &clock).ok(),
+            Some("07 081 804".into())
+        );
+        assert_eq!(
+            otp.verify("14050471", Method::TOTP, 30, 1, &clock).unwrap(),
+            Some(1)
+        );
+
+        assert_eq!(
+            otp.generate_next_at(Method::TOTP, 30, &clock).ok(),
+            Some("14 050 471".into())
+        );
+
+        // Steam codes ignore the period
+        let token = OTP::from_str_steam("BASE32SECRET3232").unwrap();
+        clock.set(29);
+        assert_eq!(
+            token.generate_at(Method::Steam, 60, &clock).ok(),
+            Some("2TC8B".into())
+        );
+        clock.advance(1);
+        assert_eq!(
+            token.generate_at(Method::Steam, 60, &clock).ok(),
+            Some("YKKK4".into())
+        );
     }
 
     #[test]
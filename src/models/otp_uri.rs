@@ -17,6 +17,7 @@ pub struct OTPUri {
     #[zeroize(skip)]
     pub(crate) label: String,
     pub(crate) secret: String,
+    pub(crate) pin: Option<String>,
     #[zeroize(skip)]
     pub(crate) issuer: String,
     #[zeroize(skip)]
@@ -42,6 +43,10 @@ impl RestorableItem for OTPUri {
         self.secret.clone()
     }
 
+    fn pin(&self) -> Option<String> {
+        self.pin.clone()
+    }
+
     fn period(&self) -> Option<u32> {
         self.period
     }
@@ -79,6 +84,7 @@ impl TryFrom<Url> for OTPUri {
         let mut provider_name = None;
         let mut algorithm = None;
         let mut secret = None;
+        let mut pin = None;
 
         let pairs = url.query_pairs();
 
@@ -117,6 +123,9 @@ impl TryFrom<Url> for OTPUri {
             "secret" => {
                 secret = Some(value.to_string());
             }
+            "pin" => {
+                pin = Some(value.to_string());
+            }
             _ => (),
         });
 
@@ -124,6 +133,13 @@ impl TryFrom<Url> for OTPUri {
             anyhow::bail!("OTP uri must contain a secret");
         }
 
+        if method.requires_pin() && pin.is_none() {
+            anyhow::bail!(
+                "OTP uri must contain a pin for the {} method",
+                method.to_string()
+            );
+        }
+
         let label = percent_decode_str(account_name).decode_utf8()?.into_owned();
         let issuer = if let Some(n) = provider_name {
             percent_decode_str(&n).decode_utf8()?.into_owned()
@@ -135,6 +151,7 @@ impl TryFrom<Url> for OTPUri {
             method,
             label,
             secret: secret.unwrap(),
+            pin,
             issuer,
             algorithm: algorithm.unwrap_or_default(),
             digits,
@@ -165,6 +182,14 @@ impl From<OTPUri> for String {
         if let Some(digits) = val.digits {
             write!(otp_uri, "&digits={digits}").unwrap();
         }
+        if let Some(ref pin) = val.pin {
+            write!(
+                otp_uri,
+                "&pin={}",
+                utf8_percent_encode(pin, NON_ALPHANUMERIC)
+            )
+            .unwrap();
+        }
         if val.method.is_event_based() {
             write!(
                 otp_uri,
@@ -176,7 +201,7 @@ impl From<OTPUri> for String {
             write!(
                 otp_uri,
                 "&period={}",
-                val.period.unwrap_or(OTP::DEFAULT_PERIOD)
+                val.period.unwrap_or_else(|| val.method.default_period())
             )
             .unwrap();
         }
@@ -190,6 +215,7 @@ impl From<&Account> for OTPUri {
             method: a.provider().method(),
             label: a.name(),
             secret: a.otp().secret(),
+            pin: a.otp().pin(),
             issuer: a.provider().name(),
             algorithm: a.provider().algorithm(),
             digits: Some(a.provider().digits()),
@@ -228,6 +254,18 @@ mod tests {
         assert_eq!(uri.secret(), "HXDMVJECJJWSRB3HWIZR4IFUGFTMXBOZ");
         assert_eq!(uri.account(), "john.doe@email.com");
         assert_eq!(uri.method(), Method::TOTP);
+
+        let uri = OTPUri::from_str(
+            "otpauth://hotp/Example:alice@example.com?secret=JBSWY3DPEHPK3PXP&algorithm=SHA-384&counter=5",
+        )
+        .unwrap();
+        assert_eq!(uri.algorithm(), Algorithm::SHA384);
+
+        let uri = OTPUri::from_str(
+            "otpauth://totp/Example:alice@example.com?secret=JBSWY3DPEHPK3PXP&algorithm=MD5",
+        )
+        .unwrap();
+        assert_eq!(uri.algorithm(), Algorithm::MD5);
     }
 
     #[test]
@@ -236,6 +274,7 @@ mod tests {
             algorithm: Algorithm::SHA1,
             label: "account test".to_owned(),
             secret: "dznF36H0IIg17rK".to_owned(),
+            pin: None,
             issuer: "Test".to_owned(),
             method: Method::TOTP,
             digits: Some(6),
@@ -244,4 +283,25 @@ mod tests {
         };
         assert_eq!(String::from(uri), "otpauth://totp/account%20test?secret=dznF36H0IIg17rK&issuer=Test&algorithm=SHA1&digits=6&period=30");
     }
+
+    #[test]
+    fn pin() {
+        let uri = OTPUri::from_str(
+            "otpauth://yandex/alice@yandex.ru?secret=6SB2IKNM6OBZPAVBVTOHDKS4FAAAAAAADFUTQMBTRY&pin=5239&issuer=Yandex",
+        )
+        .unwrap();
+        assert_eq!(uri.method(), Method::Yandex);
+        assert_eq!(uri.pin(), Some("5239".to_owned()));
+        assert_eq!(uri.account(), "alice@yandex.ru");
+
+        let uri = OTPUri::from_str("otpauth://motp/bob?secret=4MKSV7XGEWM4Q&pin=1234").unwrap();
+        assert_eq!(uri.method(), Method::MOTP);
+        assert_eq!(uri.pin(), Some("1234".to_owned()));
+        assert_eq!(
+            String::from(uri),
+            "otpauth://motp/bob?secret=4MKSV7XGEWM4Q&issuer=Default&algorithm=SHA1&pin=1234&period=10"
+        );
+
+        assert!(OTPUri::from_str("otpauth://motp/bob?secret=4MKSV7XGEWM4Q").is_err());
+    }
 }
@@ -1,21 +1,17 @@
-use std::{
-    string::ToString,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::{rc::Rc, string::ToString};
 
 use anyhow::Result;
 use diesel::prelude::*;
-use gtk::{
-    gdk_pixbuf, gio,
-    glib::{self, clone},
-    prelude::*,
-    subclass::prelude::*,
-};
+use gtk::{gdk_pixbuf, gio, glib, prelude::*, subclass::prelude::*};
 use url::Url;
 
 use crate::{
-    models::{database, Account, AccountsModel, Algorithm, Method, FAVICONS_PATH, OTP},
+    models::{
+        database, keyring, Account, AccountsModel, Algorithm, Clock, Method, SystemClock,
+        FAVICONS_PATH, OTP,
+    },
     schema::providers,
+    utils::spawn_tokio_blocking,
 };
 
 pub struct ProviderPatch {
@@ -61,7 +57,7 @@ pub struct DieselProvider {
 }
 
 mod imp {
-    use std::cell::{Cell, RefCell};
+    use std::cell::{Cell, OnceCell, RefCell};
 
     use super::*;
 
@@ -92,8 +88,13 @@ mod imp {
         pub remaining_time: Cell<u64>,
         #[property(get)]
         pub accounts_model: AccountsModel,
-        pub filter_model: gtk::FilterListModel,
-        pub tick_callback: RefCell<Option<glib::SourceId>>,
+        /// Created on demand, so the models can be used without initializing
+        /// GTK, e.g. from the command line.
+        pub filter_model: OnceCell<gtk::FilterListModel>,
+        /// The period, counted from the UNIX epoch, the codes were last
+        /// generated for.
+        pub time_step: Cell<u64>,
+        pub clock: RefCell<Rc<dyn Clock>>,
     }
 
     #[glib::object_subclass]
@@ -102,7 +103,6 @@ mod imp {
         type Type = super::Provider;
 
         fn new() -> Self {
-            let model = AccountsModel::default();
             Self {
                 id: Cell::default(),
                 default_counter: Cell::new(OTP::DEFAULT_COUNTER),
@@ -114,23 +114,17 @@ mod imp {
                 image_uri: RefCell::default(),
                 method: Cell::new(Method::default()),
                 period: Cell::new(OTP::DEFAULT_PERIOD),
-                filter_model: gtk::FilterListModel::new(Some(model.clone()), None::<gtk::Filter>),
-                accounts_model: model,
-                tick_callback: RefCell::default(),
+                filter_model: OnceCell::new(),
+                accounts_model: AccountsModel::default(),
+                time_step: Cell::default(),
                 remaining_time: Cell::default(),
+                clock: RefCell::new(Rc::new(SystemClock)),
             }
         }
     }
 
     #[glib::derived_properties]
-    impl ObjectImpl for Provider {
-        fn dispose(&self) {
-            // Stop ticking
-            if let Some(source_id) = self.tick_callback.borrow_mut().take() {
-                source_id.remove();
-            }
-        }
-    }
+    impl ObjectImpl for Provider {}
 
     impl Provider {
         fn set_image_uri_inner(&self, id: i32, uri: Option<&str>) -> anyhow::Result<()> {
@@ -175,7 +169,35 @@ impl Provider {
     ) -> Result<Self> {
         let db = database::connection();
         let mut conn = db.get()?;
+        Self::insert(
+            &mut conn,
+            name,
+            period,
+            algorithm,
+            website,
+            method,
+            digits,
+            default_counter,
+            help_url,
+            image_uri,
+        )
+    }
 
+    /// Same as [`Provider::create`] but using `conn`, for example to create
+    /// the provider as part of a transaction.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn insert(
+        conn: &mut SqliteConnection,
+        name: &str,
+        period: u32,
+        algorithm: Algorithm,
+        website: Option<String>,
+        method: Method,
+        digits: u32,
+        default_counter: u32,
+        help_url: Option<String>,
+        image_uri: Option<String>,
+    ) -> Result<Self> {
         diesel::insert_into(providers::table)
             .values(NewProvider {
                 name: name.to_string(),
@@ -188,11 +210,11 @@ impl Provider {
                 help_url,
                 image_uri,
             })
-            .execute(&mut conn)?;
+            .execute(conn)?;
 
         providers::table
             .order(providers::columns::id.desc())
-            .first::<DieselProvider>(&mut conn)
+            .first::<DieselProvider>(conn)
             .map_err(From::from)
             .map(From::from)
     }
@@ -202,16 +224,34 @@ impl Provider {
         let db = database::connection();
         let mut conn = db.get()?;
 
+        // Fetched with a search per kind of secret, not per account
+        let secrets = spawn_tokio_blocking(keyring::secrets())?;
+        let mut outdated = Vec::new();
         let results = providers
             .load::<DieselProvider>(&mut conn)?
             .into_iter()
             .map(From::from)
             .map(|p: Provider| {
-                let accounts = Account::load(&p).unwrap().collect::<Vec<_>>();
+                let accounts = Account::load(&p, &secrets)
+                    .unwrap()
+                    .map(|(account, is_outdated)| {
+                        if is_outdated {
+                            outdated.push(account.clone());
+                        }
+                        account
+                    })
+                    .collect::<Vec<_>>();
                 p.add_accounts(&accounts);
                 p
-            });
-        Ok(results)
+            })
+            .collect::<Vec<_>>();
+        if !outdated.is_empty() {
+            tracing::info!("Storing the fingerprints of {} accounts", outdated.len());
+            if let Err(err) = Account::store_fingerprints(&mut conn, &outdated) {
+                tracing::warn!("Failed to store the accounts fingerprints {err}");
+            }
+        }
+        Ok(results.into_iter())
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -309,8 +349,20 @@ impl Provider {
     }
 
     pub fn update(&self, patch: &ProviderPatch) -> Result<()> {
+        if self.matches(patch) {
+            return Ok(());
+        }
+
+        let db = database::connection();
+        let mut conn = db.get()?;
+        self.update_database(&mut conn, patch)?;
+        self.apply_patch(patch)
+    }
+
+    /// Whether applying `patch` would leave the provider unchanged.
+    pub(crate) fn matches(&self, patch: &ProviderPatch) -> bool {
         // Can't implement PartialEq because of how GObject works
-        if patch.name == self.name()
+        patch.name == self.name()
             && patch.website == self.website()
             && patch.help_url == self.help_url()
             && patch.image_uri == self.image_uri()
@@ -319,13 +371,14 @@ impl Provider {
             && patch.default_counter == self.default_counter() as i32
             && patch.algorithm == self.algorithm().to_string()
             && patch.method == self.method().to_string()
-        {
-            return Ok(());
-        }
-
-        let db = database::connection();
-        let mut conn = db.get()?;
+    }
 
+    /// Write `patch` into the database, without updating the provider itself.
+    pub(crate) fn update_database(
+        &self,
+        conn: &mut SqliteConnection,
+        patch: &ProviderPatch,
+    ) -> Result<()> {
         let target = providers::table.filter(providers::columns::id.eq(self.id() as i32));
         diesel::update(target)
             .set((
@@ -336,7 +389,7 @@ impl Provider {
                 providers::columns::default_counter.eq(&patch.default_counter),
                 providers::columns::name.eq(&patch.name),
             ))
-            .execute(&mut conn)?;
+            .execute(conn)?;
         if !patch.is_backup_restore {
             diesel::update(target)
                 .set((
@@ -344,9 +397,14 @@ impl Provider {
                     providers::columns::website.eq(&patch.website),
                     providers::columns::help_url.eq(&patch.help_url),
                 ))
-                .execute(&mut conn)?;
+                .execute(conn)?;
         };
+        Ok(())
+    }
 
+    /// Update the provider properties once `patch` was written into the
+    /// database.
+    pub(crate) fn apply_patch(&self, patch: &ProviderPatch) -> Result<()> {
         self.set_properties(&[
             ("name", &patch.name),
             ("period", &(patch.period as u32)),
@@ -372,43 +430,49 @@ impl Provider {
         }
     }
 
-    fn tick(&self) {
-        let period = self.period() as u64;
-        let remaining_time: u64 = period
-            - SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-                % period;
-        if period == remaining_time {
-            self.regenerate_otp();
-        }
-        self.set_remaining_time(remaining_time);
+    /// The clock the codes of the provider's accounts are computed with.
+    pub fn clock(&self) -> Rc<dyn Clock> {
+        self.imp().clock.borrow().clone()
     }
 
-    fn setup_tick_callback(&self) {
-        if self.imp().tick_callback.borrow().is_some() || self.method().is_event_based() {
-            return;
+    pub fn set_clock(&self, clock: impl Clock + 'static) {
+        self.imp().clock.replace(Rc::new(clock));
+        self.regenerate_otp();
+    }
+
+    /// The duration in seconds of the time steps the codes are generated for,
+    /// the Steam, Yandex & mOTP methods ignore the period of the provider.
+    pub fn step_period(&self) -> u32 {
+        OTP::step_period(self.method(), self.period())
+    }
+
+    /// The seconds left before the current code of a time based provider
+    /// expires.
+    pub fn seconds_left(&self) -> u64 {
+        let period = self.step_period() as u64;
+        period - self.clock().timestamp() % period
+    }
+
+    /// Called by the [`Ticker`](crate::models::Ticker) on every second
+    /// boundary.
+    pub(crate) fn tick(&self) {
+        let period = self.step_period() as u64;
+        let timestamp = self.clock().timestamp();
+        // Also catches up with the periods that ended while not ticking
+        if self.imp().time_step.get() != timestamp / period {
+            self.regenerate_otp();
         }
-        self.set_remaining_time(self.period() as u64);
-
-        match self.method() {
-            Method::TOTP | Method::Steam => {
-                let source_id = glib::timeout_add_seconds_local(
-                    1,
-                    clone!(@weak self as provider => @default-return glib::ControlFlow::Break, move || {
-                        provider.tick();
-                        glib::ControlFlow::Continue
-                    }),
-                );
-                self.imp().tick_callback.replace(Some(source_id));
-            }
-            _ => (),
-        };
+        self.set_remaining_time(period - timestamp % period);
     }
 
-    fn regenerate_otp(&self) {
-        let accounts = self.accounts();
+    pub fn regenerate_otp(&self) {
+        if self.method().is_time_based() {
+            let period = self.step_period() as u64;
+            self.imp().time_step.set(self.clock().timestamp() / period);
+        }
+        // Including the accounts hidden by the search, which would otherwise
+        // show stale codes once it is cleared
+        let accounts = self.accounts_model();
         for i in 0..accounts.n_items() {
             let item = accounts.item(i).unwrap();
             let account = item.downcast_ref::<Account>().unwrap();
@@ -422,12 +486,19 @@ impl Provider {
 
     fn add_accounts(&self, accounts: &[Account]) {
         self.accounts_model().splice(accounts);
-        self.setup_tick_callback();
+        self.update_remaining_time();
     }
 
     pub fn add_account(&self, account: &Account) {
         self.accounts_model().append(account);
-        self.setup_tick_callback();
+        self.update_remaining_time();
+    }
+
+    /// Shows the right remaining time until the next tick.
+    fn update_remaining_time(&self) {
+        if self.method().is_time_based() {
+            self.set_remaining_time(self.seconds_left());
+        }
     }
 
     fn tokenize_search(account_name: &str, provider_name: &str, term: &str) -> bool {
@@ -460,7 +531,9 @@ impl Provider {
     }
 
     pub fn accounts(&self) -> &gtk::FilterListModel {
-        &self.imp().filter_model
+        self.imp().filter_model.get_or_init(|| {
+            gtk::FilterListModel::new(Some(self.accounts_model()), None::<gtk::Filter>)
+        })
     }
 
     pub fn filter(&self, text: String) {
@@ -473,20 +546,13 @@ impl Provider {
                 Self::tokenize_search(&account_name, &provider_name, &text)
             }),
         );
-        self.imp().filter_model.set_filter(Some(&filter));
+        self.accounts().set_filter(Some(&filter));
     }
 
     pub fn remove_account(&self, account: &Account) {
-        let imp = self.imp();
         let model = self.accounts_model();
         if let Some(pos) = model.find_position_by_id(account.id()) {
             model.remove(pos);
-            if !self.has_accounts() && self.method().is_time_based() {
-                // Stop ticking
-                if let Some(source_id) = imp.tick_callback.borrow_mut().take() {
-                    source_id.remove();
-                }
-            }
         }
     }
 }
@@ -524,3 +590,100 @@ impl From<&Provider> for DieselProvider {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::FakeClock;
+
+    fn new_provider(method: Method, period: u32, clock: &FakeClock) -> Provider {
+        let provider = Provider::new(
+            1,
+            "Provider",
+            period,
+            method,
+            Algorithm::SHA1,
+            8,
+            OTP::DEFAULT_COUNTER,
+            None,
+            None,
+            None,
+        );
+        provider.set_clock(clock.clone());
+        provider
+    }
+
+    #[test]
+    fn tick() {
+        let clock = FakeClock::new(1111111108);
+        let provider = new_provider(Method::TOTP, OTP::DEFAULT_PERIOD, &clock);
+        let account = Account::new(
+            1,
+            "Account",
+            "token-id",
+            0,
+            &provider,
+            Some("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"),
+            None,
+        )
+        .unwrap();
+        provider.add_account(&account);
+        assert_eq!(account.code(), "07 081 804");
+        assert_eq!(account.next_code(), "14 050 471");
+
+        provider.tick();
+        assert_eq!(provider.remaining_time(), 2);
+        assert_eq!(account.code(), "07 081 804");
+
+        clock.advance(1);
+        provider.tick();
+        assert_eq!(provider.remaining_time(), 1);
+        assert_eq!(account.code(), "07 081 804");
+
+        // Crossing the period boundary regenerates the codes
+        clock.advance(1);
+        provider.tick();
+        assert_eq!(provider.remaining_time(), 30);
+        assert_eq!(account.code(), "14 050 471");
+
+        clock.advance(1);
+        provider.tick();
+        assert_eq!(provider.remaining_time(), 29);
+        assert_eq!(account.code(), "14 050 471");
+
+        // The periods that ended while not ticking are caught up with
+        clock.set(1234567890);
+        provider.tick();
+        assert_eq!(provider.remaining_time(), 30);
+        assert_eq!(account.code(), "89 005 924");
+
+        clock.set(2000000000);
+        provider.tick();
+        assert_eq!(provider.remaining_time(), 10);
+        assert_eq!(account.code(), "69 279 037");
+
+        // The Steam codes always change every 30 seconds
+        let clock = FakeClock::new(28);
+        let provider = new_provider(Method::Steam, 60, &clock);
+        let account = Account::new(
+            2,
+            "Account",
+            "token-id",
+            0,
+            &provider,
+            Some("BASE32SECRET3232"),
+            None,
+        )
+        .unwrap();
+        provider.add_account(&account);
+        assert_eq!(account.code(), "2TC8B");
+        assert_eq!(account.next_code(), "YKKK4");
+
+        for (remaining_time, code) in [(1, "2TC8B"), (30, "YKKK4"), (29, "YKKK4")] {
+            clock.advance(1);
+            provider.tick();
+            assert_eq!(provider.remaining_time(), remaining_time);
+            assert_eq!(account.code(), code);
+        }
+    }
+}
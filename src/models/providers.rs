@@ -1,7 +1,15 @@
 use anyhow::Result;
+use diesel::SqliteConnection;
 use gtk::{gio, glib, prelude::*, subclass::prelude::*};
 
-use super::{Account, Algorithm, Method, Provider, ProviderPatch, OTP};
+use super::{database, Account, Algorithm, Method, Provider, ProviderPatch, OTP};
+
+/// What [`ProvidersModel::find_or_insert`] wrote into the database.
+pub enum ProviderChange {
+    Unchanged,
+    Created,
+    Updated(ProviderPatch),
+}
 
 mod imp {
     use std::cell::{Cell, RefCell};
@@ -53,12 +61,59 @@ impl ProvidersModel {
         help_url: Option<String>,
         image_uri: Option<String>,
     ) -> Result<Provider> {
-        let provider = match self.find_by_name(name) {
+        let db = database::connection();
+        let mut conn = db.get()?;
+        let (provider, change) = self.find_or_insert(
+            &mut conn,
+            &[],
+            name,
+            period,
+            method,
+            website,
+            algorithm,
+            digits,
+            default_counter,
+            help_url,
+            image_uri,
+        )?;
+        match change {
+            ProviderChange::Unchanged => (),
+            ProviderChange::Created => self.append(&provider),
+            ProviderChange::Updated(patch) => provider.apply_patch(&patch)?,
+        }
+        Ok(provider)
+    }
+
+    /// Same as [`ProvidersModel::find_or_create`] but using `conn` and leaving
+    /// the model & the providers untouched, the returned change has to be
+    /// applied by the caller. `pending` are the providers created with `conn`
+    /// that are not part of the model yet.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn find_or_insert(
+        &self,
+        conn: &mut SqliteConnection,
+        pending: &[Provider],
+        name: &str,
+        period: Option<u32>,
+        method: Method,
+        website: Option<String>,
+        algorithm: Algorithm,
+        digits: Option<u32>,
+        default_counter: Option<u32>,
+        help_url: Option<String>,
+        image_uri: Option<String>,
+    ) -> Result<(Provider, ProviderChange)> {
+        let existing = pending
+            .iter()
+            .find(|p| p.name() == name)
+            .cloned()
+            .or_else(|| self.find_by_name(name));
+        match existing {
             Some(p) => {
                 // Update potenitally different properties than what we have in the pre-shipped
                 // database Note this does a comparaison first to avoid a
                 // uselesss rewrite
-                p.update(&ProviderPatch {
+                let patch = ProviderPatch {
                     name: name.to_owned(),
                     website,
                     help_url,
@@ -69,26 +124,29 @@ impl ProvidersModel {
                     algorithm: algorithm.to_string(),
                     method: method.to_string(),
                     is_backup_restore: true,
-                })?;
-                p
+                };
+                if p.matches(&patch) {
+                    return Ok((p, ProviderChange::Unchanged));
+                }
+                p.update_database(conn, &patch)?;
+                Ok((p, ProviderChange::Updated(patch)))
             }
             None => {
-                let p = Provider::create(
+                let p = Provider::insert(
+                    conn,
                     name,
-                    period.unwrap_or(OTP::DEFAULT_PERIOD),
+                    period.unwrap_or_else(|| method.default_period()),
                     algorithm,
                     website,
                     method,
-                    digits.unwrap_or(OTP::DEFAULT_DIGITS),
+                    digits.unwrap_or_else(|| method.default_digits()),
                     default_counter.unwrap_or(OTP::DEFAULT_COUNTER),
                     help_url,
                     image_uri,
                 )?;
-                self.append(&p);
-                p
+                Ok((p, ProviderChange::Created))
             }
-        };
-        Ok(provider)
+        }
     }
 
     fn find_by_name(&self, name: &str) -> Option<Provider> {
@@ -204,6 +262,30 @@ impl ProvidersModel {
         results
     }
 
+    /// Regenerates the codes of the time based providers, for example once
+    /// the time offset was changed.
+    pub fn regenerate_time_based_otps(&self) {
+        for pos in 0..self.n_items() {
+            let obj = self.item(pos).unwrap();
+            let provider = obj.downcast_ref::<Provider>().unwrap();
+            if provider.method().is_time_based() {
+                provider.regenerate_otp();
+            }
+        }
+    }
+
+    /// Updates the remaining time of the time based providers, regenerating
+    /// their codes once a period is over.
+    pub fn tick(&self) {
+        // Cloned, the notify handlers might change the model
+        let providers = self.imp().0.borrow().clone();
+        for provider in providers {
+            if provider.method().is_time_based() && provider.has_accounts() {
+                provider.tick();
+            }
+        }
+    }
+
     /// Check whether the model was loaded from the database
     pub fn is_loaded(&self) -> bool {
         self.imp().1.get()
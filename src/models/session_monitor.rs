@@ -0,0 +1,315 @@
+//! Watches the screen saver & logind, so the application can be locked along
+//! with the session.
+
+use futures_channel::mpsc::{UnboundedReceiver as Receiver, UnboundedSender as Sender};
+use futures_util::{Stream, StreamExt};
+use zbus::{dbus_proxy, zvariant::OwnedObjectPath, Connection};
+
+use super::RUNTIME;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// The screen was locked.
+    ScreenLocked,
+    /// The system is about to suspend.
+    Suspending,
+    /// Another session became the active one, e.g. when switching users.
+    Deactivated,
+}
+
+#[dbus_proxy(
+    interface = "org.gnome.ScreenSaver",
+    default_service = "org.gnome.ScreenSaver",
+    default_path = "/org/gnome/ScreenSaver"
+)]
+trait ScreenSaver {
+    #[dbus_proxy(signal)]
+    fn active_changed(&self, active: bool) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+trait LoginManager {
+    /// The session of the process `pid`, 0 for the caller.
+    fn get_session_by_pid(&self, pid: u32) -> zbus::Result<OwnedObjectPath>;
+
+    #[dbus_proxy(signal)]
+    fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1"
+)]
+trait LoginSession {
+    #[dbus_proxy(signal)]
+    fn lock(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(property)]
+    fn active(&self) -> zbus::Result<bool>;
+}
+
+/// Start watching the session, the services that are not available are
+/// skipped.
+pub async fn start() -> anyhow::Result<Receiver<SessionEvent>> {
+    let (sender, receiver) = futures_channel::mpsc::unbounded();
+
+    match Connection::session().await {
+        Ok(connection) => match screen_saver_events(&connection).await {
+            Ok(events) => forward(events, sender.clone()),
+            Err(err) => tracing::warn!("Failed to watch the screen saver {err}"),
+        },
+        Err(err) => tracing::warn!("Failed to connect to the session bus {err}"),
+    }
+
+    match Connection::system().await {
+        Ok(connection) => {
+            match sleep_events(&connection).await {
+                Ok(events) => forward(events, sender.clone()),
+                Err(err) => tracing::warn!("Failed to watch the system suspend {err}"),
+            }
+            match session_events(&connection).await {
+                Ok(events) => forward(events, sender),
+                Err(err) => tracing::warn!("Failed to watch the login session {err}"),
+            }
+        }
+        Err(err) => tracing::warn!("Failed to connect to the system bus {err}"),
+    }
+
+    Ok(receiver)
+}
+
+fn forward(
+    events: impl Stream<Item = SessionEvent> + Send + 'static,
+    sender: Sender<SessionEvent>,
+) {
+    RUNTIME.spawn(async move {
+        let mut events = Box::pin(events);
+        while let Some(event) = events.next().await {
+            if sender.unbounded_send(event).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+async fn screen_saver_events(
+    connection: &Connection,
+) -> zbus::Result<impl Stream<Item = SessionEvent>> {
+    let proxy = ScreenSaverProxy::new(connection).await?;
+    let signals = proxy.receive_active_changed().await?;
+    Ok(signals.filter_map(|signal| async move {
+        let active = signal.args().map(|args| *args.active()).unwrap_or_default();
+        active.then_some(SessionEvent::ScreenLocked)
+    }))
+}
+
+async fn sleep_events(connection: &Connection) -> zbus::Result<impl Stream<Item = SessionEvent>> {
+    let proxy = LoginManagerProxy::new(connection).await?;
+    let signals = proxy.receive_prepare_for_sleep().await?;
+    Ok(signals.filter_map(|signal| async move {
+        let start = signal.args().map(|args| *args.start()).unwrap_or_default();
+        start.then_some(SessionEvent::Suspending)
+    }))
+}
+
+/// The events of the session the application runs in, it is locked with
+/// `loginctl lock-session` and deactivated when switching users.
+async fn session_events(connection: &Connection) -> zbus::Result<impl Stream<Item = SessionEvent>> {
+    let manager = LoginManagerProxy::new(connection).await?;
+    let path = manager.get_session_by_pid(0).await?;
+    let proxy = LoginSessionProxy::builder(connection)
+        .path(path.into_inner())?
+        .build()
+        .await?;
+    let locks = proxy
+        .receive_lock()
+        .await?
+        .map(|_| SessionEvent::ScreenLocked);
+    let deactivations = proxy
+        .receive_active_changed()
+        .await
+        .filter_map(move |change| {
+            // Keep the proxy around, it caches the properties
+            let _ = &proxy;
+            async move {
+                let active = change.get().await.unwrap_or(true);
+                (!active).then_some(SessionEvent::Deactivated)
+            }
+        });
+    Ok(futures_util::stream::select(locks, deactivations))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        time::Duration,
+    };
+
+    use zbus::{dbus_interface, ConnectionBuilder, SignalContext};
+
+    use super::*;
+
+    /// A private bus the mock services are exported on.
+    struct MockBus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl MockBus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("Failed to start dbus-daemon");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .expect("Failed to read the address of the bus");
+            Self {
+                daemon,
+                address: address.trim().to_owned(),
+            }
+        }
+
+        async fn connect(&self, name: Option<&str>) -> zbus::Result<Connection> {
+            let builder = ConnectionBuilder::address(self.address.as_str())?;
+            match name {
+                Some(name) => builder.name(name)?.build().await,
+                None => builder.build().await,
+            }
+        }
+    }
+
+    impl Drop for MockBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    struct MockScreenSaver;
+
+    #[dbus_interface(name = "org.gnome.ScreenSaver")]
+    impl MockScreenSaver {
+        #[dbus_interface(signal)]
+        async fn active_changed(ctxt: &SignalContext<'_>, active: bool) -> zbus::Result<()>;
+    }
+
+    struct MockManager;
+
+    #[dbus_interface(name = "org.freedesktop.login1.Manager")]
+    impl MockManager {
+        fn get_session_by_pid(&self, _pid: u32) -> OwnedObjectPath {
+            OwnedObjectPath::try_from(SESSION_PATH).unwrap()
+        }
+
+        #[dbus_interface(signal)]
+        async fn prepare_for_sleep(ctxt: &SignalContext<'_>, start: bool) -> zbus::Result<()>;
+    }
+
+    struct MockSession {
+        active: bool,
+    }
+
+    #[dbus_interface(name = "org.freedesktop.login1.Session")]
+    impl MockSession {
+        #[dbus_interface(signal)]
+        async fn lock(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+        #[dbus_interface(property)]
+        fn active(&self) -> bool {
+            self.active
+        }
+    }
+
+    const SESSION_PATH: &str = "/org/freedesktop/login1/session/c1";
+
+    /// The next event of `events`, if any is received in time.
+    async fn next_event(
+        events: &mut (impl Stream<Item = SessionEvent> + Unpin),
+    ) -> Option<SessionEvent> {
+        tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon, run with --ignored"]
+    fn mock_services() {
+        let bus = MockBus::start();
+        RUNTIME.block_on(async {
+            let screen_saver = bus.connect(Some("org.gnome.ScreenSaver")).await.unwrap();
+            screen_saver
+                .object_server()
+                .at("/org/gnome/ScreenSaver", MockScreenSaver)
+                .await
+                .unwrap();
+            let login = bus.connect(Some("org.freedesktop.login1")).await.unwrap();
+            login
+                .object_server()
+                .at("/org/freedesktop/login1", MockManager)
+                .await
+                .unwrap();
+            login
+                .object_server()
+                .at(SESSION_PATH, MockSession { active: true })
+                .await
+                .unwrap();
+
+            let client = bus.connect(None).await.unwrap();
+            let mut screen_saver_events = Box::pin(screen_saver_events(&client).await.unwrap());
+            let mut sleep_events = Box::pin(sleep_events(&client).await.unwrap());
+            let mut session_events = Box::pin(session_events(&client).await.unwrap());
+
+            let ctxt = SignalContext::new(&screen_saver, "/org/gnome/ScreenSaver").unwrap();
+            // Unlocking the screen is not an event
+            MockScreenSaver::active_changed(&ctxt, false).await.unwrap();
+            MockScreenSaver::active_changed(&ctxt, true).await.unwrap();
+            assert_eq!(
+                next_event(&mut screen_saver_events).await,
+                Some(SessionEvent::ScreenLocked)
+            );
+
+            let ctxt = SignalContext::new(&login, "/org/freedesktop/login1").unwrap();
+            // Resuming is not an event either
+            MockManager::prepare_for_sleep(&ctxt, false).await.unwrap();
+            MockManager::prepare_for_sleep(&ctxt, true).await.unwrap();
+            assert_eq!(
+                next_event(&mut sleep_events).await,
+                Some(SessionEvent::Suspending)
+            );
+
+            let ctxt = SignalContext::new(&login, SESSION_PATH).unwrap();
+            MockSession::lock(&ctxt).await.unwrap();
+            assert_eq!(
+                next_event(&mut session_events).await,
+                Some(SessionEvent::ScreenLocked)
+            );
+
+            let session = login
+                .object_server()
+                .interface::<_, MockSession>(SESSION_PATH)
+                .await
+                .unwrap();
+            session.get_mut().await.active = false;
+            session
+                .get()
+                .await
+                .active_changed(session.signal_context())
+                .await
+                .unwrap();
+            assert_eq!(
+                next_event(&mut session_events).await,
+                Some(SessionEvent::Deactivated)
+            );
+        });
+    }
+}
@@ -1,4 +1,4 @@
-use std::ops::Deref;
+use std::{collections::HashMap, ops::Deref};
 
 use gtk::{gio, glib, prelude::*};
 
@@ -7,6 +7,10 @@ use crate::config;
 pub struct Settings(gio::Settings);
 
 impl Settings {
+    /// The largest time offset in seconds, either way. Matches the range of
+    /// the `time-offset` key.
+    pub const MAX_TIME_OFFSET: i32 = 3600;
+
     pub fn download_favicons(&self) -> bool {
         self.boolean("download-favicons")
     }
@@ -32,6 +36,121 @@ impl Settings {
             callback(settings.boolean("download-favicons-metered"))
         })
     }
+
+    pub fn time_offset(&self) -> i32 {
+        self.int("time-offset")
+    }
+
+    pub fn set_time_offset(&self, offset: i32) -> Result<(), glib::BoolError> {
+        self.set_int("time-offset", offset)
+    }
+
+    pub fn connect_time_offset_changed<F>(&self, callback: F) -> glib::SignalHandlerId
+    where
+        F: Fn(i32) + 'static,
+    {
+        self.connect_changed(Some("time-offset"), move |settings, _key| {
+            callback(settings.int("time-offset"))
+        })
+    }
+
+    pub fn next_code_threshold(&self) -> u32 {
+        self.uint("next-code-threshold")
+    }
+
+    pub fn connect_next_code_threshold_changed<F>(&self, callback: F) -> glib::SignalHandlerId
+    where
+        F: Fn(u32) + 'static,
+    {
+        self.connect_changed(Some("next-code-threshold"), move |settings, _key| {
+            callback(settings.uint("next-code-threshold"))
+        })
+    }
+
+    pub fn auto_backup(&self) -> bool {
+        self.boolean("auto-backup")
+    }
+
+    pub fn auto_backup_directory(&self) -> String {
+        self.string("auto-backup-directory").into()
+    }
+
+    pub fn set_auto_backup_directory(&self, directory: &str) -> Result<(), glib::BoolError> {
+        self.set_string("auto-backup-directory", directory)
+    }
+
+    pub fn auto_backup_format(&self) -> String {
+        self.string("auto-backup-format").into()
+    }
+
+    pub fn set_auto_backup_format(&self, identifier: &str) -> Result<(), glib::BoolError> {
+        self.set_string("auto-backup-format", identifier)
+    }
+
+    pub fn auto_backup_on_change(&self) -> bool {
+        self.boolean("auto-backup-on-change")
+    }
+
+    /// The hours between two scheduled backups, 0 if disabled.
+    pub fn auto_backup_interval(&self) -> u32 {
+        self.uint("auto-backup-interval")
+    }
+
+    pub fn auto_backup_keep(&self) -> u32 {
+        self.uint("auto-backup-keep")
+    }
+
+    pub fn auto_backup_last(&self) -> i64 {
+        self.int64("auto-backup-last")
+    }
+
+    pub fn set_auto_backup_last(&self, timestamp: i64) -> Result<(), glib::BoolError> {
+        self.set_int64("auto-backup-last", timestamp)
+    }
+
+    pub fn lock_on_screen_lock(&self) -> bool {
+        self.boolean("lock-on-screen-lock")
+    }
+
+    pub fn lock_on_suspend(&self) -> bool {
+        self.boolean("lock-on-suspend")
+    }
+
+    pub fn lock_on_user_switch(&self) -> bool {
+        self.boolean("lock-on-user-switch")
+    }
+
+    /// The seconds before clearing a copied code from the clipboard, 0 to
+    /// never clear it.
+    pub fn clipboard_clear_timeout(&self) -> u32 {
+        self.uint("clipboard-clear-timeout")
+    }
+
+    /// Where the secrets are stored, `auto` until it is chosen on the first
+    /// start.
+    pub fn secret_store(&self) -> String {
+        self.string("secret-store").into()
+    }
+
+    pub fn set_secret_store(&self, store: &str) -> Result<(), glib::BoolError> {
+        self.set_string("secret-store", store)
+    }
+
+    /// The applications that were allowed or denied access to the codes over
+    /// D-Bus.
+    pub fn codes_access(&self) -> HashMap<String, bool> {
+        self.value("codes-access").get().unwrap_or_default()
+    }
+
+    pub fn set_codes_access(&self, app_id: &str, allowed: bool) -> Result<(), glib::BoolError> {
+        let mut access = self.codes_access();
+        access.insert(app_id.to_owned(), allowed);
+        self.set_value("codes-access", &access.to_variant())
+    }
+
+    pub fn reset_codes_access(&self) {
+        self.reset("codes-access");
+    }
 }
 
 impl Default for Settings {
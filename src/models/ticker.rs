@@ -0,0 +1,97 @@
+//! The clock source of the time based codes.
+//!
+//! A single timeout is shared by all the providers, it is rescheduled on every
+//! tick so that it stays aligned to the wall-clock second boundaries instead
+//! of drifting.
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use gtk::{
+    glib::{self, clone},
+    prelude::*,
+};
+
+use super::ProvidersModel;
+
+/// Delay added after each second boundary, so a timeout firing a bit early
+/// doesn't tick for the previous second.
+const BOUNDARY_MARGIN: Duration = Duration::from_millis(5);
+
+#[derive(Debug, Default)]
+pub struct Ticker {
+    source_id: Rc<RefCell<Option<glib::SourceId>>>,
+}
+
+impl Ticker {
+    pub fn is_running(&self) -> bool {
+        self.source_id.borrow().is_some()
+    }
+
+    /// Ticks `model` right away, then on every second boundary until
+    /// stopped.
+    pub fn start(&self, model: &ProvidersModel) {
+        if self.is_running() {
+            return;
+        }
+        model.tick();
+        schedule(&self.source_id, model.downgrade());
+    }
+
+    pub fn stop(&self) {
+        if let Some(source_id) = self.source_id.borrow_mut().take() {
+            source_id.remove();
+        }
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn schedule(source_id: &Rc<RefCell<Option<glib::SourceId>>>, model: glib::WeakRef<ProvidersModel>) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let id = glib::timeout_add_local_once(
+        until_next_second(now),
+        clone!(@weak source_id => move || {
+            // The source is removed once it fired
+            source_id.borrow_mut().take();
+            if let Some(model) = model.upgrade() {
+                model.tick();
+                schedule(&source_id, model.downgrade());
+            }
+        }),
+    );
+    source_id.replace(Some(id));
+}
+
+/// The time left from `now` until right after the next second boundary.
+fn until_next_second(now: Duration) -> Duration {
+    Duration::from_secs(1) - Duration::from_nanos(now.subsec_nanos() as u64) + BOUNDARY_MARGIN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_second() {
+        assert_eq!(
+            until_next_second(Duration::from_millis(1_111_111_109_000)),
+            Duration::from_millis(1005)
+        );
+        assert_eq!(
+            until_next_second(Duration::from_millis(1_111_111_109_250)),
+            Duration::from_millis(755)
+        );
+        assert_eq!(
+            until_next_second(Duration::from_nanos(1_111_111_109_999_999_999)),
+            Duration::from_nanos(5_000_001)
+        );
+    }
+}
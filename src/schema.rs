@@ -5,6 +5,7 @@ diesel::table! {
         counter -> Integer,
         token_id -> Text,
         provider_id -> Integer,
+        fingerprint -> Nullable<Text>,
     }
 }
 
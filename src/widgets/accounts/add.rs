@@ -8,7 +8,7 @@ use gtk::{
 
 use crate::{
     backup::RestorableItem,
-    models::{Account, OTPUri, Provider, ProvidersModel, OTP},
+    models::{keyring, Account, Method, OTPUri, Provider, ProvidersModel, OTP},
     widgets::{providers::ProviderPage, screenshot, Camera, ErrorRevealer, ProviderImage, UrlRow},
 };
 
@@ -44,6 +44,8 @@ mod imp {
         #[template_child]
         pub token_entry: TemplateChild<adw::PasswordEntryRow>,
         #[template_child]
+        pub pin_entry: TemplateChild<adw::PasswordEntryRow>,
+        #[template_child]
         pub more_list: TemplateChild<gtk::ListBox>,
         #[template_child]
         pub period_label: TemplateChild<gtk::Label>,
@@ -167,9 +169,18 @@ impl AccountAddDialog {
         let imp = self.imp();
         let username = imp.username_entry.text();
         let token = imp.token_entry.text();
+        let pin = imp.pin_entry.text();
         let has_provider = imp.selected_provider.borrow().is_some();
-
-        let is_valid = !username.is_empty() && !token.is_empty() && has_provider;
+        let requires_pin = imp
+            .selected_provider
+            .borrow()
+            .as_ref()
+            .is_some_and(|p| p.method().requires_pin());
+
+        let is_valid = !username.is_empty()
+            && !token.is_empty()
+            && has_provider
+            && (!requires_pin || !pin.is_empty());
         self.action_set_enabled("add.save", is_valid);
     }
 
@@ -250,6 +261,9 @@ impl AccountAddDialog {
 
         imp.token_entry.set_text(&otp_uri.secret());
         imp.username_entry.set_text(&otp_uri.account());
+        if let Some(pin) = otp_uri.pin() {
+            imp.pin_entry.set_text(&pin);
+        }
 
         let provider = self
             .model()
@@ -295,13 +309,35 @@ impl AccountAddDialog {
         if let Some(ref provider) = *imp.selected_provider.borrow() {
             let username = imp.username_entry.text();
             let token = imp.token_entry.text();
-            let token = token.trim_end_matches('=');
-            if !OTP::is_valid(token) {
+            let mut token = token.trim_end_matches('=').to_owned();
+            // mOTP secrets are usually handed out as hex strings, which are only
+            // decoded as such if they are not valid Base32 secrets already
+            if provider.method() == Method::MOTP && !OTP::is_valid(&token) {
+                if let Ok(secret) = hex::decode(&token) {
+                    token = data_encoding::BASE32_NOPAD.encode(&secret);
+                }
+            }
+            if !OTP::is_valid(&token) {
                 imp.error_revealer.popup(&gettext("Invalid Token"));
                 anyhow::bail!("Token {} is not a valid Base32 secret", &token);
             }
+            if let Some(id) = Account::find_by_fingerprint(&keyring::fingerprint(&token))? {
+                imp.error_revealer
+                    .popup(&gettext("An account with the same token exists already"));
+                anyhow::bail!("The token was already added as the account {id}");
+            }
+            if keyring::requires_vault() && !keyring::is_vault_enabled() {
+                imp.error_revealer.popup(&gettext(
+                    "Set a passphrase in the preferences first, there is no keyring to store the token",
+                ));
+                anyhow::bail!("The token cannot be stored unencrypted without a keyring");
+            }
+            let pin = provider
+                .method()
+                .requires_pin()
+                .then(|| imp.pin_entry.text());
 
-            let account = Account::create(&username, token, None, provider)?;
+            let account = Account::create(&username, &token, pin.as_deref(), None, provider)?;
 
             self.model().add_account(&account, provider);
             self.emit_by_name::<()>("added", &[]);
@@ -317,7 +353,8 @@ impl AccountAddDialog {
         if let Some(provider) = provider {
             imp.more_list.set_visible(true);
             imp.provider_entry.set_text(&provider.name());
-            imp.period_label.set_text(&provider.period().to_string());
+            imp.period_label
+                .set_text(&provider.step_period().to_string());
 
             imp.image.set_provider(Some(&provider));
 
@@ -329,6 +366,8 @@ impl AccountAddDialog {
 
             imp.digits_label.set_text(&provider.digits().to_string());
 
+            imp.pin_entry.set_visible(provider.method().requires_pin());
+
             if provider.method().is_time_based() {
                 imp.counter_spinbutton.set_visible(false);
                 imp.period_row.set_visible(true);
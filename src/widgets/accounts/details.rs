@@ -8,9 +8,14 @@ use gtk::{
 
 use super::{QRCodeData, QRCodePaintable};
 use crate::{
-    models::{Account, Provider, ProvidersModel},
-    widgets::UrlRow,
+    models::{i18n, Account, Provider, ProvidersModel, Settings, SETTINGS},
+    widgets::{UrlRow, Window},
 };
+
+// Number of steps before and after the current one to look for a code
+// accepted by the service.
+const RESYNC_WINDOW: u32 = 10;
+
 mod imp {
     use std::cell::{OnceCell, RefCell};
 
@@ -42,6 +47,8 @@ mod imp {
         #[template_child]
         pub period_row: TemplateChild<adw::ActionRow>,
         #[template_child]
+        pub resync_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
         pub help_row: TemplateChild<UrlRow>,
         pub qrcode_paintable: QRCodePaintable,
         pub account: RefCell<Option<Account>>,
@@ -72,6 +79,10 @@ mod imp {
                 }
             });
 
+            klass.install_action("account.resync", None, move |page, _, _| {
+                page.resync();
+            });
+
             klass.install_action("account.back", None, move |page, _, _| {
                 page.activate_action("win.back", None).unwrap();
             });
@@ -145,6 +156,84 @@ impl AccountDetailsPage {
         dialog.present();
     }
 
+    fn resync(&self) {
+        let parent = self.root().and_downcast::<gtk::Window>().unwrap();
+        let entry = gtk::Entry::builder()
+            .placeholder_text(gettext("Accepted code"))
+            .activates_default(true)
+            .build();
+
+        let dialog = adw::MessageDialog::builder()
+            .heading(gettext("Synchronize Time"))
+            .body(gettext(
+                "Enter a code that was recently accepted by the service to compensate the difference between both clocks",
+            ))
+            .extra_child(&entry)
+            .default_response("resync")
+            .close_response("cancel")
+            .modal(true)
+            .transient_for(&parent)
+            .build();
+        dialog.add_responses(&[
+            ("cancel", &gettext("_Cancel")),
+            ("resync", &gettext("_Synchronize")),
+        ]);
+        dialog.set_response_appearance("resync", adw::ResponseAppearance::Suggested);
+        dialog.connect_response(
+            None,
+            clone!(@weak self as page, @weak entry => move |dialog, response| {
+                if response == "resync" {
+                    page.resync_with(&entry.text());
+                }
+                dialog.close();
+            }),
+        );
+
+        dialog.present();
+    }
+
+    fn resync_with(&self, code: &str) {
+        let Some(account) = self.imp().account.borrow().clone() else {
+            return;
+        };
+        let message = match account.verify(code, RESYNC_WINDOW) {
+            Ok(Some(0)) => gettext("The time is already synchronized"),
+            Ok(Some(step)) => {
+                let drift = step * account.provider().step_period() as i64;
+                let max_offset = Settings::MAX_TIME_OFFSET as i64;
+                let current_offset = SETTINGS.time_offset() as i64;
+                let offset = (current_offset + drift).clamp(-max_offset, max_offset);
+                if let Err(err) = SETTINGS.set_time_offset(offset as i32) {
+                    tracing::error!("Failed to store the time offset {err}");
+                    return;
+                }
+                let adjustment = (offset - current_offset).to_string();
+                if offset - current_offset == drift {
+                    // Translators: {} is replaced by a number of seconds, which can be negative
+                    i18n::i18n_f("Time adjusted by {} seconds", &[&adjustment])
+                } else {
+                    i18n::i18n_f(
+                        // Translators: the first {} is replaced by a number of seconds, which can
+                        // be negative, the second one by the largest offset in seconds
+                        "Time adjusted by {} seconds only, the offset is limited to {} seconds",
+                        &[&adjustment, &max_offset.to_string()],
+                    )
+                }
+            }
+            Ok(None) => gettext("The code doesn't match any recent code of this account"),
+            Err(err) => {
+                tracing::error!("Failed to verify the code {err}");
+                return;
+            }
+        };
+
+        if let Some(window) = self.root().and_downcast::<Window>() {
+            let toast = adw::Toast::new(&message);
+            toast.set_timeout(3);
+            window.add_toast(toast);
+        }
+    }
+
     pub fn set_account(&self, account: &Account) {
         let imp = self.imp();
         let qr_code = QRCodeData::from(String::from(account.otp_uri()));
@@ -175,10 +264,13 @@ impl AccountDetailsPage {
         if provider.method().is_event_based() {
             imp.counter_spinbutton.set_visible(true);
             imp.period_row.set_visible(false);
+            imp.resync_row.set_visible(false);
         } else {
             imp.counter_spinbutton.set_visible(false);
             imp.period_row.set_visible(true);
-            imp.period_label.set_text(&provider.period().to_string());
+            imp.resync_row.set_visible(true);
+            imp.period_label
+                .set_text(&provider.step_period().to_string());
         }
         imp.digits_label.set_text(&provider.digits().to_string());
         if let Some(help) = provider.help_url() {
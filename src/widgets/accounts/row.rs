@@ -1,9 +1,14 @@
-use gtk::{gdk, glib, prelude::*};
+use gtk::{
+    gdk,
+    glib::{self, clone},
+    prelude::*,
+    subclass::prelude::*,
+};
 
-use crate::models::Account;
+use crate::models::{Account, Provider, SETTINGS};
 
 mod imp {
-    use std::cell::OnceCell;
+    use std::cell::{OnceCell, RefCell};
 
     use adw::subclass::prelude::*;
     use gettextrs::gettext;
@@ -22,6 +27,12 @@ mod imp {
         pub increment_btn: TemplateChild<gtk::Button>,
         #[template_child]
         pub otp_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub copy_next_btn: TemplateChild<gtk::Button>,
+        /// Disconnected once the row is disposed, the provider & settings
+        /// outlive the rows.
+        pub remaining_time_handler: RefCell<Option<(Provider, glib::SignalHandlerId)>>,
+        pub next_code_threshold_handler: RefCell<Option<glib::SignalHandlerId>>,
     }
 
     #[glib::object_subclass]
@@ -47,6 +58,20 @@ mod imp {
                 toast.set_timeout(3);
                 window.add_toast(toast);
             });
+            klass.add_binding_action(
+                gdk::Key::c,
+                gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::SHIFT_MASK,
+                "account.copy-next-otp",
+                None,
+            );
+
+            klass.install_action("account.copy-next-otp", None, move |row, _, _| {
+                row.account().copy_next_otp();
+                let window = row.root().and_downcast::<Window>().unwrap();
+                let toast = adw::Toast::new(&gettext("Next One-Time password copied"));
+                toast.set_timeout(3);
+                window.add_toast(toast);
+            });
             klass.install_action("account.increment-counter", None, move |row, _, _| {
                 match row.account().increment_counter() {
                     Ok(_) => row.account().generate_otp(),
@@ -81,9 +106,35 @@ mod imp {
                 .sync_create()
                 .build();
 
+            account
+                .bind_property("next-code", &*self.copy_next_btn, "label")
+                .sync_create()
+                .build();
+
             // Only display the increment button if it is a HOTP account
             self.increment_btn
                 .set_visible(account.provider().method().is_event_based());
+
+            let provider = account.provider();
+            let handler_id = provider.connect_remaining_time_notify(
+                clone!(@weak obj => move |_| obj.update_next_code()),
+            );
+            self.remaining_time_handler
+                .replace(Some((provider, handler_id)));
+            let handler_id = SETTINGS.connect_next_code_threshold_changed(
+                clone!(@weak obj => move |_| obj.update_next_code()),
+            );
+            self.next_code_threshold_handler.replace(Some(handler_id));
+            obj.update_next_code();
+        }
+
+        fn dispose(&self) {
+            if let Some((provider, handler_id)) = self.remaining_time_handler.take() {
+                provider.disconnect(handler_id);
+            }
+            if let Some(handler_id) = self.next_code_threshold_handler.take() {
+                SETTINGS.disconnect(handler_id);
+            }
         }
     }
     impl WidgetImpl for AccountRow {}
@@ -101,4 +152,13 @@ impl AccountRow {
     pub fn new(account: &Account) -> Self {
         glib::Object::builder().property("account", account).build()
     }
+
+    /// Shows the next code once the current one is about to expire.
+    fn update_next_code(&self) {
+        let provider = self.account().provider();
+        let threshold = SETTINGS.next_code_threshold() as u64;
+        let visible = provider.method().is_time_based() && provider.remaining_time() <= threshold;
+        self.imp().copy_next_btn.set_visible(visible);
+        self.action_set_enabled("account.copy-next-otp", visible);
+    }
 }
@@ -10,7 +10,7 @@ mod url_row;
 mod window;
 
 pub use self::{
-    accounts::{AccountAddDialog, QRCodeData},
+    accounts::{AccountAddDialog, QRCodeData, QRCodePaintable},
     camera::{screenshot, Camera, CameraEvent},
     camera_paintable::CameraPaintable,
     camera_row::{CameraItem, CameraRow},
@@ -0,0 +1,325 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+};
+
+use adw::{prelude::*, subclass::navigation_page::*};
+use gettextrs::gettext;
+use gtk::{
+    glib::{self, clone},
+    subclass::prelude::*,
+};
+
+use crate::{backup::Selection, models::ProvidersModel};
+
+/// The rows of a provider and of its accounts.
+pub struct ProviderGroup {
+    name: String,
+    row: adw::ExpanderRow,
+    check_button: gtk::CheckButton,
+    accounts: Vec<AccountRow>,
+}
+
+pub struct AccountRow {
+    id: u32,
+    name: String,
+    row: adw::ActionRow,
+    check_button: gtk::CheckButton,
+}
+
+mod imp {
+    use glib::{once_cell::sync::Lazy, subclass::Signal};
+
+    use super::*;
+
+    #[derive(Default, gtk::CompositeTemplate)]
+    #[template(resource = "/com/belmoussaoui/Authenticator/preferences_backup_accounts_page.ui")]
+    pub struct BackupAccountsPage {
+        pub model: RefCell<Option<ProvidersModel>>,
+        pub selection: RefCell<Selection>,
+        pub groups: RefCell<Vec<ProviderGroup>>,
+        /// Set while the check buttons are toggled programmatically.
+        pub updating: Cell<bool>,
+        #[template_child]
+        pub stack: TemplateChild<gtk::Stack>,
+        #[template_child]
+        pub accounts_group: TemplateChild<adw::PreferencesGroup>,
+        #[template_child]
+        pub search_entry: TemplateChild<gtk::SearchEntry>,
+        #[template_child]
+        pub select_button: TemplateChild<gtk::Button>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for BackupAccountsPage {
+        const NAME: &'static str = "BackupAccountsPage";
+        type Type = super::BackupAccountsPage;
+        type ParentType = adw::NavigationPage;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+            klass.bind_template_instance_callbacks();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for BackupAccountsPage {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> =
+                Lazy::new(|| vec![Signal::builder("selection-changed").build()]);
+            SIGNALS.as_ref()
+        }
+    }
+
+    impl WidgetImpl for BackupAccountsPage {
+        fn map(&self) {
+            self.parent_map();
+            // The accounts might have changed since the page was last shown
+            self.obj().load();
+        }
+
+        fn unmap(&self) {
+            self.parent_unmap();
+            self.obj().clear();
+        }
+    }
+
+    impl NavigationPageImpl for BackupAccountsPage {}
+}
+
+glib::wrapper! {
+    pub struct BackupAccountsPage(ObjectSubclass<imp::BackupAccountsPage>)
+        @extends gtk::Widget, adw::NavigationPage;
+}
+
+#[gtk::template_callbacks]
+impl BackupAccountsPage {
+    pub fn set_model(&self, model: &ProvidersModel) {
+        self.imp().model.replace(Some(model.clone()));
+    }
+
+    /// The accounts the user picked.
+    pub fn selection(&self) -> Selection {
+        self.imp().selection.borrow().clone()
+    }
+
+    pub fn connect_selection_changed<F>(&self, callback: F) -> glib::SignalHandlerId
+    where
+        F: Fn(&Self) + 'static,
+    {
+        self.connect_local(
+            "selection-changed",
+            false,
+            clone!(@weak self as page => @default-return None, move |_| {
+                callback(&page);
+                None
+            }),
+        )
+    }
+
+    fn load(&self) {
+        let imp = self.imp();
+        let Some(model) = imp.model.borrow().clone() else {
+            return;
+        };
+        self.clear();
+
+        let selection = imp.selection.borrow().clone();
+        let mut groups = Vec::new();
+        for (provider, accounts) in Selection::All.accounts(&model) {
+            let check_button = gtk::CheckButton::builder()
+                .valign(gtk::Align::Center)
+                .tooltip_text(gettext("Select all the accounts of this provider"))
+                .build();
+            check_button.connect_toggled(
+                clone!(@weak self as page, @strong provider => move |button| {
+                    page.on_provider_toggled(&provider.name(), button.is_active());
+                }),
+            );
+            let provider_row = adw::ExpanderRow::builder()
+                .title(provider.name())
+                .use_markup(false)
+                .build();
+            provider_row.add_prefix(&check_button);
+
+            let accounts = accounts
+                .iter()
+                .map(|account| {
+                    let check_button = gtk::CheckButton::builder()
+                        .active(selection.contains(account))
+                        .valign(gtk::Align::Center)
+                        .build();
+                    check_button.connect_toggled(clone!(@weak self as page => move |_| {
+                        if !page.imp().updating.get() {
+                            page.update_selection();
+                        }
+                    }));
+                    let row = adw::ActionRow::builder()
+                        .title(account.name())
+                        .activatable_widget(&check_button)
+                        .use_markup(false)
+                        .build();
+                    row.add_prefix(&check_button);
+                    provider_row.add_row(&row);
+                    AccountRow {
+                        id: account.id(),
+                        name: account.name(),
+                        row,
+                        check_button,
+                    }
+                })
+                .collect();
+
+            imp.accounts_group.add(&provider_row);
+            groups.push(ProviderGroup {
+                name: provider.name(),
+                row: provider_row,
+                check_button,
+                accounts,
+            });
+        }
+        imp.groups.replace(groups);
+        self.update_selection();
+        self.filter();
+    }
+
+    fn clear(&self) {
+        let imp = self.imp();
+        for group in imp.groups.take() {
+            imp.accounts_group.remove(&group.row);
+        }
+        imp.search_entry.set_text("");
+    }
+
+    fn on_provider_toggled(&self, name: &str, is_active: bool) {
+        let imp = self.imp();
+        if imp.updating.get() {
+            return;
+        }
+        imp.updating.set(true);
+        if let Some(group) = imp.groups.borrow().iter().find(|group| group.name == name) {
+            for account in group.accounts.iter() {
+                account.check_button.set_active(is_active);
+            }
+        }
+        imp.updating.set(false);
+        self.update_selection();
+    }
+
+    /// Sync the selection & the provider check buttons with the accounts
+    /// check buttons.
+    fn update_selection(&self) {
+        let imp = self.imp();
+        let groups = imp.groups.borrow();
+        let mut ids = HashSet::new();
+        let mut n_accounts = 0;
+
+        imp.updating.set(true);
+        for group in groups.iter() {
+            let n_selected = group
+                .accounts
+                .iter()
+                .filter(|account| account.check_button.is_active())
+                .inspect(|account| {
+                    ids.insert(account.id);
+                })
+                .count();
+            group
+                .check_button
+                .set_active(n_selected == group.accounts.len());
+            group
+                .check_button
+                .set_inconsistent(n_selected > 0 && n_selected < group.accounts.len());
+            n_accounts += group.accounts.len();
+        }
+        imp.updating.set(false);
+
+        let selection = if ids.len() == n_accounts {
+            Selection::All
+        } else {
+            Selection::Accounts(ids)
+        };
+        drop(groups);
+        self.update_select_button();
+        if imp.selection.replace(selection.clone()) != selection {
+            self.emit_by_name::<()>("selection-changed", &[]);
+        }
+    }
+
+    fn filter(&self) {
+        let imp = self.imp();
+        let query = imp.search_entry.text().to_lowercase();
+        let mut has_results = false;
+
+        for group in imp.groups.borrow().iter() {
+            let provider_matches = group.name.to_lowercase().contains(&query);
+            let mut group_visible = false;
+            for account in group.accounts.iter() {
+                let visible = provider_matches || account.name.to_lowercase().contains(&query);
+                account.row.set_visible(visible);
+                group_visible |= visible;
+            }
+            group.row.set_visible(group_visible);
+            // Reveal the matching accounts while searching
+            group.row.set_expanded(!query.is_empty() && group_visible);
+            has_results |= group_visible;
+        }
+
+        let name = if has_results { "accounts" } else { "empty" };
+        imp.stack.set_visible_child_name(name);
+        self.update_select_button();
+    }
+
+    /// The accounts rows matching the search.
+    fn visible_accounts(&self) -> Vec<gtk::CheckButton> {
+        self.imp()
+            .groups
+            .borrow()
+            .iter()
+            .flat_map(|group| group.accounts.iter())
+            .filter(|account| account.row.is_visible())
+            .map(|account| account.check_button.clone())
+            .collect()
+    }
+
+    fn update_select_button(&self) {
+        let accounts = self.visible_accounts();
+        let all_selected = accounts.iter().all(|button| button.is_active());
+        let label = if all_selected && !accounts.is_empty() {
+            gettext("Select _None")
+        } else {
+            gettext("Select _All")
+        };
+        let select_button = &self.imp().select_button;
+        select_button.set_label(&label);
+        select_button.set_sensitive(!accounts.is_empty());
+    }
+
+    #[template_callback]
+    fn on_search_changed(&self, _entry: gtk::SearchEntry) {
+        self.filter();
+    }
+
+    #[template_callback]
+    fn on_select_clicked(&self, _btn: gtk::Button) {
+        let imp = self.imp();
+        let accounts = self.visible_accounts();
+        let is_active = !accounts.iter().all(|button| button.is_active());
+
+        imp.updating.set(true);
+        for button in accounts {
+            button.set_active(is_active);
+        }
+        imp.updating.set(false);
+        self.update_selection();
+    }
+}
+
+impl Default for BackupAccountsPage {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
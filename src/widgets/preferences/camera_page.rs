@@ -9,7 +9,7 @@ use gtk::{
 };
 use tokio::{
     select,
-    sync::oneshot,
+    sync::{mpsc, oneshot},
     time::{sleep, Duration},
 };
 
@@ -28,6 +28,8 @@ mod imp {
         pub actions: OnceCell<gio::SimpleActionGroup>,
         #[template_child]
         pub camera: TemplateChild<Camera>,
+        #[template_child]
+        pub progress_label: TemplateChild<gtk::Label>,
     }
 
     #[glib::object_subclass]
@@ -49,7 +51,13 @@ mod imp {
     #[glib::derived_properties]
     impl ObjectImpl for CameraPage {}
 
-    impl WidgetImpl for CameraPage {}
+    impl WidgetImpl for CameraPage {
+        fn unmap(&self) {
+            self.parent_unmap();
+            self.obj().set_progress(None);
+        }
+    }
+
     impl NavigationPageImpl for CameraPage {}
 }
 
@@ -64,52 +72,43 @@ impl CameraPage {
         glib::Object::builder().property("actions", actions).build()
     }
 
-    pub async fn scan_from_camera(&self) -> Result<String> {
+    /// Keep scanning QR codes from the camera until `callback` returns `true`
+    /// or the page is closed.
+    pub async fn scan_many_from_camera<F>(&self, mut callback: F)
+    where
+        F: FnMut(String) -> bool,
+    {
         let imp = self.imp();
 
-        let (tx, rx) = oneshot::channel();
-
-        // This is required because for whatever reason `glib::clone!` wouldn't let it
-        // be moved into the closure.
-        let tx = Rc::new(Cell::new(Some(tx)));
+        // `None` is sent once the page is closed, to stop waiting for more codes.
+        let (tx, mut rx) = mpsc::unbounded_channel();
 
-        // This is to make it safe to access `src` inside of the connected closure to
-        // disconnect it after being called.
-        let src = Rc::new(Cell::new(None));
-
-        src.set(Some(imp.camera.connect_code_detected(clone!(
-            @weak self as camera_page, @strong src, @strong tx
-            => move |_, code| {
-                match tx.take().unwrap().send(code) {
-                    Ok(()) => (),
-                    Err(_) => {
-                        tracing::error!(concat!(
-                            "CameraPage::scan_from_camera failed to send the resulting QR ",
-                            "code to the recipient because the recipient already received a ",
-                            "QR code or was dropped. This should never occur.",
-                        ));
-                    }
-                }
-                camera_page.imp().camera.disconnect(src.take().unwrap());
-            }
-        ))));
-
-        drop(tx);
-        drop(src);
+        let code_src = imp
+            .camera
+            .connect_code_detected(clone!(@strong tx => move |_, code| {
+                let _ = tx.send(Some(code));
+            }));
+        let unmap_src = self.connect_unmap(move |_| {
+            let _ = tx.send(None);
+        });
 
         imp.camera.scan_from_camera().await;
 
-        match rx.await {
-            Ok(code) => Ok(code),
-            Err(error) => {
-                tracing::error!(concat!(
-                    "CameraPage::scan_from_camera failed to receive the resulting QR code from ",
-                    "the sender because the sender was dropped without sending a QR code. This ",
-                    "should never occur."
-                ));
-                Err(error.into())
+        while let Some(Some(code)) = rx.recv().await {
+            if callback(code) {
+                break;
             }
         }
+
+        imp.camera.disconnect(code_src);
+        self.disconnect(unmap_src);
+    }
+
+    /// Show the scanning progress of a backup spanning several QR codes.
+    pub fn set_progress(&self, progress: Option<&str>) {
+        let progress_label = &self.imp().progress_label;
+        progress_label.set_label(progress.unwrap_or_default());
+        progress_label.set_visible(progress.is_some());
     }
 
     pub async fn scan_from_screenshot(&self) -> Result<String> {
@@ -1,5 +1,8 @@
+mod backup_accounts_page;
 mod camera_page;
 mod password_page;
+mod qr_codes_page;
+mod restore_page;
 mod window;
 
 pub use window::PreferencesWindow;
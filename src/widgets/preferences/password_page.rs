@@ -155,7 +155,11 @@ impl PasswordPage {
         save_password_action.set_enabled(false);
 
         let reset_password_action = actions.lookup_action("reset_password").unwrap();
+        // The passphrase is all that protects the secrets without a keyring
         self.bind_property("has-set-password", &reset_password_action, "enabled")
+            .transform_to(|_, has_set_password: bool| {
+                Some(has_set_password && !keyring::requires_vault())
+            })
             .sync_create()
             .build();
     }
@@ -210,7 +214,7 @@ impl PasswordPage {
         })
         .await;
 
-        if self.has_set_password() && is_current_password {
+        if self.has_set_password() && !is_current_password {
             imp.error_revealer.popup(&gettext("Wrong Passphrase"));
             return;
         }
@@ -0,0 +1,132 @@
+use adw::subclass::navigation_page::*;
+use gtk::{glib, prelude::*, subclass::prelude::*};
+
+use crate::{
+    models::i18n,
+    widgets::{QRCodeData, QRCodePaintable},
+};
+
+mod imp {
+    use glib::subclass::InitializingObject;
+
+    use super::*;
+
+    #[derive(Default, gtk::CompositeTemplate)]
+    #[template(resource = "/com/belmoussaoui/Authenticator/preferences_qr_codes_page.ui")]
+    pub struct QRCodesPage {
+        #[template_child]
+        pub carousel: TemplateChild<adw::Carousel>,
+        #[template_child]
+        pub page_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub previous_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub next_button: TemplateChild<gtk::Button>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for QRCodesPage {
+        const NAME: &'static str = "QRCodesPage";
+        type Type = super::QRCodesPage;
+        type ParentType = adw::NavigationPage;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+            klass.bind_template_instance_callbacks();
+        }
+
+        fn instance_init(obj: &InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for QRCodesPage {}
+
+    impl WidgetImpl for QRCodesPage {
+        fn unmap(&self) {
+            self.parent_unmap();
+            // Don't keep the secrets around once the codes were scanned
+            self.obj().set_codes(&[]);
+        }
+    }
+
+    impl NavigationPageImpl for QRCodesPage {}
+}
+
+glib::wrapper! {
+    pub struct QRCodesPage(ObjectSubclass<imp::QRCodesPage>)
+        @extends gtk::Widget, adw::NavigationPage;
+}
+
+#[gtk::template_callbacks]
+impl QRCodesPage {
+    /// Replace the displayed QR codes, one carousel page per code.
+    pub fn set_codes(&self, codes: &[&str]) {
+        let carousel = &*self.imp().carousel;
+        while carousel.n_pages() > 0 {
+            carousel.remove(&carousel.nth_page(0));
+        }
+
+        for code in codes {
+            let paintable = QRCodePaintable::default();
+            paintable.set_qrcode(QRCodeData::from(code));
+            let picture = gtk::Picture::builder()
+                .paintable(&paintable)
+                .halign(gtk::Align::Center)
+                .valign(gtk::Align::Center)
+                .width_request(300)
+                .height_request(300)
+                .overflow(gtk::Overflow::Hidden)
+                .css_classes(vec!["card".to_string()])
+                .build();
+            carousel.append(&picture);
+        }
+        self.update_navigation();
+    }
+
+    fn scroll_by(&self, offset: i32) {
+        let carousel = &*self.imp().carousel;
+        let position = carousel.position().round() as i32 + offset;
+        if position >= 0 && (position as u32) < carousel.n_pages() {
+            carousel.scroll_to(&carousel.nth_page(position as u32), true);
+        }
+    }
+
+    fn update_navigation(&self) {
+        let imp = self.imp();
+        let n_pages = imp.carousel.n_pages();
+        let position = imp.carousel.position().round() as u32;
+
+        imp.page_label.set_label(&i18n::i18n_f(
+            // Translators: This is the position of the displayed QR code, e.g. 1 of 3
+            "{} of {}",
+            &[
+                &(position + 1).min(n_pages).to_string(),
+                &n_pages.to_string(),
+            ],
+        ));
+        imp.previous_button.set_sensitive(position > 0);
+        imp.next_button.set_sensitive(position + 1 < n_pages);
+    }
+
+    #[template_callback]
+    fn on_page_changed(&self, #[rest] _values: &[glib::Value]) {
+        self.update_navigation();
+    }
+
+    #[template_callback]
+    fn on_previous_clicked(&self, _btn: gtk::Button) {
+        self.scroll_by(-1);
+    }
+
+    #[template_callback]
+    fn on_next_clicked(&self, _btn: gtk::Button) {
+        self.scroll_by(1);
+    }
+}
+
+impl Default for QRCodesPage {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
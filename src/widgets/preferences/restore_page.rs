@@ -0,0 +1,294 @@
+use std::cell::RefCell;
+
+use adw::{prelude::*, subclass::navigation_page::*};
+use gettextrs::gettext;
+use gtk::{
+    glib::{self, clone},
+    subclass::prelude::*,
+};
+
+use crate::{
+    backup::{
+        preview_items, restore_preview, AccountIdentity, Conflict, ExistingAccounts, PreviewItem,
+        Resolution, RestorableItem, RestoreSummary,
+    },
+    models::{i18n, Account, Provider, ProvidersModel},
+};
+
+mod imp {
+    use glib::{once_cell::sync::Lazy, subclass::Signal};
+
+    use super::*;
+
+    #[derive(Default, gtk::CompositeTemplate)]
+    #[template(resource = "/com/belmoussaoui/Authenticator/preferences_restore_page.ui")]
+    pub struct RestorePage {
+        pub items: RefCell<Vec<PreviewItem>>,
+        /// The existing accounts the conflicts refer to.
+        pub accounts: RefCell<Vec<Account>>,
+        pub model: RefCell<Option<ProvidersModel>>,
+        pub rows: RefCell<Vec<adw::ActionRow>>,
+        #[template_child]
+        pub stack: TemplateChild<gtk::Stack>,
+        #[template_child]
+        pub items_group: TemplateChild<adw::PreferencesGroup>,
+        #[template_child]
+        pub summary_page: TemplateChild<adw::StatusPage>,
+        #[template_child]
+        pub import_button: TemplateChild<gtk::Button>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for RestorePage {
+        const NAME: &'static str = "RestorePage";
+        type Type = super::RestorePage;
+        type ParentType = adw::NavigationPage;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.bind_template();
+            klass.bind_template_instance_callbacks();
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for RestorePage {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> =
+                Lazy::new(|| vec![Signal::builder("restored").build()]);
+            SIGNALS.as_ref()
+        }
+    }
+
+    impl WidgetImpl for RestorePage {
+        fn unmap(&self) {
+            self.parent_unmap();
+            // Don't keep the secrets around once the page is closed
+            self.obj().clear();
+        }
+    }
+
+    impl NavigationPageImpl for RestorePage {}
+}
+
+glib::wrapper! {
+    pub struct RestorePage(ObjectSubclass<imp::RestorePage>)
+        @extends gtk::Widget, adw::NavigationPage;
+}
+
+#[gtk::template_callbacks]
+impl RestorePage {
+    /// Preview `items` before restoring them into `model`.
+    pub fn set_items(&self, model: &ProvidersModel, items: Vec<Box<dyn RestorableItem>>) {
+        let imp = self.imp();
+        self.clear();
+
+        let accounts = existing_accounts(model);
+        let existing = accounts
+            .iter()
+            .map(AccountIdentity::from)
+            .collect::<ExistingAccounts>();
+        let items = preview_items(items, &existing);
+
+        for (index, preview) in items.iter().enumerate() {
+            // The providers without accounts are always restored
+            if !preview.item.has_account() {
+                continue;
+            }
+            let row = self.create_row(index, preview, &items, &accounts);
+            imp.items_group.add(&row);
+            imp.rows.borrow_mut().push(row);
+        }
+
+        imp.items.replace(items);
+        imp.accounts.replace(accounts);
+        imp.model.replace(Some(model.clone()));
+        imp.stack.set_visible_child_name("preview");
+        imp.import_button.set_visible(true);
+        self.update_import_button();
+    }
+
+    pub fn connect_restored<F>(&self, callback: F) -> glib::SignalHandlerId
+    where
+        F: Fn(&Self) + 'static,
+    {
+        self.connect_local(
+            "restored",
+            false,
+            clone!(@weak self as page => @default-return None, move |_| {
+                callback(&page);
+                None
+            }),
+        )
+    }
+
+    fn clear(&self) {
+        let imp = self.imp();
+        for row in imp.rows.take() {
+            imp.items_group.remove(&row);
+        }
+        imp.items.take();
+        imp.accounts.take();
+        imp.model.take();
+    }
+
+    fn create_row(
+        &self,
+        index: usize,
+        preview: &PreviewItem,
+        items: &[PreviewItem],
+        accounts: &[Account],
+    ) -> adw::ActionRow {
+        let item = &preview.item;
+        let check_button = gtk::CheckButton::builder()
+            .active(preview.selected)
+            .valign(gtk::Align::Center)
+            .build();
+        check_button.connect_toggled(clone!(@weak self as page => move |button| {
+            if let Some(preview) = page.imp().items.borrow_mut().get_mut(index) {
+                preview.selected = button.is_active();
+            }
+            page.update_import_button();
+        }));
+
+        let row = adw::ActionRow::builder()
+            .title(item.issuer())
+            .subtitle(item.account())
+            .activatable_widget(&check_button)
+            .use_markup(false)
+            .build();
+        row.add_prefix(&check_button);
+
+        if let Some((conflict, other)) = preview.conflict {
+            let subtitle = match conflict {
+                Conflict::Duplicate => i18n::i18n_f(
+                    "{} — Already added as {} / {}",
+                    &[
+                        &item.account(),
+                        &accounts[other].provider().name(),
+                        &accounts[other].name(),
+                    ],
+                ),
+                Conflict::SameName => {
+                    i18n::i18n_f("{} — Another account has the same name", &[&item.account()])
+                }
+                Conflict::Repeated => i18n::i18n_f(
+                    "{} — Also in the backup as {} / {}",
+                    &[
+                        &item.account(),
+                        &items[other].item.issuer(),
+                        &items[other].item.account(),
+                    ],
+                ),
+            };
+            row.set_subtitle(&subtitle);
+
+            // There is no existing account to replace for the repeated items
+            let resolutions = if conflict == Conflict::Repeated {
+                vec![Resolution::Skip, Resolution::KeepBoth]
+            } else {
+                vec![Resolution::Skip, Resolution::Replace, Resolution::KeepBoth]
+            };
+            let labels = resolutions
+                .iter()
+                .map(|resolution| match resolution {
+                    Resolution::Skip => gettext("Skip"),
+                    Resolution::Replace => gettext("Replace"),
+                    Resolution::KeepBoth => gettext("Keep Both"),
+                })
+                .collect::<Vec<_>>();
+            let dropdown =
+                gtk::DropDown::from_strings(&labels.iter().map(String::as_str).collect::<Vec<_>>());
+            dropdown.set_valign(gtk::Align::Center);
+            dropdown.set_tooltip_text(Some(&gettext("What to do with the existing account")));
+            let selected = resolutions
+                .iter()
+                .position(|resolution| *resolution == preview.resolution)
+                .unwrap_or_default();
+            dropdown.set_selected(selected as u32);
+            dropdown.connect_selected_notify(clone!(@weak self as page => move |dropdown| {
+                if let (Some(preview), Some(resolution)) = (
+                    page.imp().items.borrow_mut().get_mut(index),
+                    resolutions.get(dropdown.selected() as usize),
+                ) {
+                    preview.resolution = *resolution;
+                }
+            }));
+            row.add_suffix(&dropdown);
+        }
+
+        row
+    }
+
+    fn update_import_button(&self) {
+        let imp = self.imp();
+        let has_selection = imp.items.borrow().iter().any(|preview| preview.selected);
+        imp.import_button.set_sensitive(has_selection);
+    }
+
+    fn show_summary(&self, summary: Option<RestoreSummary>) {
+        let imp = self.imp();
+        let summary_page = &imp.summary_page;
+        if let Some(summary) = summary {
+            summary_page.set_icon_name(Some("emblem-ok-symbolic"));
+            summary_page.set_title(&gettext("Restore Completed"));
+            summary_page.set_description(Some(&i18n::i18n_f(
+                // Translators: The summary of a restore, e.g. 3 imported, 1 replaced, 2 skipped
+                "{} imported, {} replaced, {} skipped",
+                &[
+                    &summary.imported.to_string(),
+                    &summary.replaced.to_string(),
+                    &summary.skipped.to_string(),
+                ],
+            )));
+        } else {
+            summary_page.set_icon_name(Some("dialog-error-symbolic"));
+            summary_page.set_title(&gettext("Restore Failed"));
+            summary_page.set_description(Some(&gettext(
+                "The backup could not be fully restored, none of its accounts were imported",
+            )));
+        }
+        imp.import_button.set_visible(false);
+        imp.stack.set_visible_child_name("summary");
+    }
+
+    #[template_callback]
+    fn on_import_clicked(&self, _btn: gtk::Button) {
+        let imp = self.imp();
+        let Some(model) = imp.model.borrow().clone() else {
+            return;
+        };
+        match restore_preview(&imp.items.borrow(), &imp.accounts.borrow(), &model) {
+            Ok(summary) => {
+                tracing::info!("Restore completed {summary:?}");
+                self.emit_by_name::<()>("restored", &[]);
+                self.show_summary(Some(summary));
+            }
+            Err(err) => {
+                tracing::error!("Restore failed and was rolled back: {err:?}");
+                self.show_summary(None);
+            }
+        }
+    }
+}
+
+/// The accounts of every provider of `model`.
+fn existing_accounts(model: &ProvidersModel) -> Vec<Account> {
+    let mut accounts = Vec::new();
+    for i in 0..model.n_items() {
+        let provider = model.item(i).and_downcast::<Provider>().unwrap();
+        let accounts_model = provider.accounts_model();
+        for j in 0..accounts_model.n_items() {
+            accounts.push(accounts_model.item(j).and_downcast::<Account>().unwrap());
+        }
+    }
+    accounts
+}
+
+impl Default for RestorePage {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
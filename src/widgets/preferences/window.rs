@@ -6,15 +6,20 @@ use gtk::{
     glib::{self, clone},
     subclass::prelude::*,
 };
+use zeroize::Zeroizing;
 
-use super::{camera_page::CameraPage, password_page::PasswordPage};
+use super::{
+    backup_accounts_page::BackupAccountsPage, camera_page::CameraPage, password_page::PasswordPage,
+    qr_codes_page::QRCodesPage, restore_page::RestorePage,
+};
 use crate::{
     backup::{
-        Aegis, AndOTP, Backupable, Bitwarden, FreeOTP, FreeOTPJSON, Google, LegacyAuthenticator,
-        Operation, Restorable, RestorableItem,
+        auto, Aegis, AndOTP, Authenticator, Backupable, Bitwarden, Ente, FreeOTP, FreeOTPEncrypted,
+        FreeOTPJSON, Google, LegacyAuthenticator, Operation, Raivo, Restorable, RestorableItem,
+        ScannedBatches, Selection, TwoFAS,
     },
-    models::{ProvidersModel, SETTINGS},
-    utils::spawn,
+    models::{i18n, keyring, Provider, ProvidersModel, SETTINGS},
+    utils::{spawn, spawn_tokio},
     widgets::screenshot,
 };
 
@@ -40,21 +45,57 @@ mod imp {
         pub actions: gio::SimpleActionGroup,
         pub backup_actions: gio::SimpleActionGroup,
         pub restore_actions: gio::SimpleActionGroup,
+        pub backup_accounts_page: BackupAccountsPage,
         pub camera_page: CameraPage,
         pub password_page: PasswordPage,
+        pub qr_codes_page: QRCodesPage,
+        pub restore_page: RestorePage,
         #[template_child]
         pub backup_group: TemplateChild<adw::PreferencesGroup>,
         #[template_child]
+        pub backup_accounts_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
         pub restore_group: TemplateChild<adw::PreferencesGroup>,
+        #[template_child(id = "auto_backup_switch")]
+        pub auto_backup: TemplateChild<adw::SwitchRow>,
+        #[template_child]
+        pub auto_backup_directory_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub auto_backup_format_row: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub auto_backup_password_row: TemplateChild<adw::PasswordEntryRow>,
+        #[template_child(id = "auto_backup_on_change_switch")]
+        pub auto_backup_on_change: TemplateChild<adw::SwitchRow>,
+        #[template_child(id = "auto_backup_interval_spin_btn")]
+        pub auto_backup_interval: TemplateChild<adw::SpinRow>,
+        #[template_child(id = "auto_backup_keep_spin_btn")]
+        pub auto_backup_keep: TemplateChild<adw::SpinRow>,
         #[template_child(id = "auto_lock_switch")]
         pub auto_lock: TemplateChild<adw::SwitchRow>,
+        #[template_child(id = "clipboard_clear_timeout_spin_btn")]
+        pub clipboard_clear_timeout: TemplateChild<adw::SpinRow>,
+        #[template_child(id = "encrypt_secrets_switch")]
+        pub encrypt_secrets: TemplateChild<adw::SwitchRow>,
         #[template_child(id = "download_favicons_switch")]
         pub download_favicons: TemplateChild<adw::SwitchRow>,
         #[template_child(id = "download_favicons_metered_switch")]
         pub download_favicons_metered: TemplateChild<adw::SwitchRow>,
+        #[template_child(id = "lock_on_screen_lock_switch")]
+        pub lock_on_screen_lock: TemplateChild<adw::SwitchRow>,
+        #[template_child(id = "lock_on_suspend_switch")]
+        pub lock_on_suspend: TemplateChild<adw::SwitchRow>,
+        #[template_child(id = "lock_on_user_switch_switch")]
+        pub lock_on_user_switch: TemplateChild<adw::SwitchRow>,
         #[template_child(id = "lock_timeout_spin_btn")]
         pub lock_timeout: TemplateChild<adw::SpinRow>,
+        #[template_child(id = "time_offset_spin_btn")]
+        pub time_offset: TemplateChild<adw::SpinRow>,
+        #[template_child(id = "next_code_threshold_spin_btn")]
+        pub next_code_threshold: TemplateChild<adw::SpinRow>,
         pub key_entries: RefCell<HashMap<String, adw::PasswordEntryRow>>,
+        /// Every account of the model, to follow the accounts added or
+        /// removed while the window is open.
+        pub accounts: OnceCell<gtk::FlattenListModel>,
     }
 
     #[glib::object_subclass]
@@ -68,18 +109,33 @@ mod imp {
 
             Self {
                 has_set_password: Cell::default(), // Synced from the application
+                backup_accounts_page: BackupAccountsPage::default(),
                 camera_page: CameraPage::new(&actions),
                 password_page: PasswordPage::new(&actions),
+                qr_codes_page: QRCodesPage::default(),
+                restore_page: RestorePage::default(),
                 actions,
                 model: OnceCell::default(),
+                accounts: OnceCell::default(),
                 backup_actions: gio::SimpleActionGroup::new(),
                 restore_actions: gio::SimpleActionGroup::new(),
                 auto_lock: TemplateChild::default(),
                 download_favicons: TemplateChild::default(),
+                encrypt_secrets: TemplateChild::default(),
                 download_favicons_metered: TemplateChild::default(),
                 lock_timeout: TemplateChild::default(),
+                time_offset: TemplateChild::default(),
+                next_code_threshold: TemplateChild::default(),
                 backup_group: TemplateChild::default(),
+                backup_accounts_row: TemplateChild::default(),
                 restore_group: TemplateChild::default(),
+                auto_backup: TemplateChild::default(),
+                auto_backup_directory_row: TemplateChild::default(),
+                auto_backup_format_row: TemplateChild::default(),
+                auto_backup_password_row: TemplateChild::default(),
+                auto_backup_on_change: TemplateChild::default(),
+                auto_backup_interval: TemplateChild::default(),
+                auto_backup_keep: TemplateChild::default(),
                 key_entries: RefCell::default(),
             }
         }
@@ -158,6 +214,30 @@ impl PreferencesWindow {
         SETTINGS
             .bind("auto-lock-timeout", &*imp.lock_timeout, "value")
             .build();
+        SETTINGS
+            .bind("lock-on-screen-lock", &*imp.lock_on_screen_lock, "active")
+            .build();
+        SETTINGS
+            .bind("lock-on-suspend", &*imp.lock_on_suspend, "active")
+            .build();
+        SETTINGS
+            .bind("lock-on-user-switch", &*imp.lock_on_user_switch, "active")
+            .build();
+        SETTINGS
+            .bind(
+                "clipboard-clear-timeout",
+                &*imp.clipboard_clear_timeout,
+                "value",
+            )
+            .build();
+        SETTINGS
+            .bind("time-offset", &*imp.time_offset, "value")
+            .build();
+        SETTINGS
+            .bind("next-code-threshold", &*imp.next_code_threshold, "value")
+            .build();
+
+        self.setup_auto_backup();
 
         imp.password_page
             .bind_property("has-set-password", self, "has-set-password")
@@ -165,20 +245,57 @@ impl PreferencesWindow {
             .bidirectional()
             .build();
 
-        // FreeOTP is first in all of these lists, since its the way to backup
-        // Authenticator for use with Authenticator. Others are sorted
-        // alphabetically.
+        self.update_encrypt_secrets();
+        imp.encrypt_secrets
+            .connect_active_notify(clone!(@weak self as win => move |row| {
+                if row.is_active() != keyring::is_vault_enabled() {
+                    spawn(clone!(@weak win => async move {
+                        win.set_secrets_encrypted(win.imp().encrypt_secrets.is_active()).await;
+                    }));
+                }
+            }));
+        // Setting or removing the passphrase enables or disables the encryption
+        self.connect_has_set_password_notify(|win| {
+            win.update_encrypt_secrets();
+        });
+
+        // The Authenticator formats are first in all of these lists, since they
+        // are the way to backup Authenticator for use with Authenticator. Others
+        // are sorted alphabetically by title, the legacy format comes last.
 
+        self.register_backup::<Authenticator>(&["application/json"]);
         self.register_backup::<FreeOTP>(&["text/plain"]);
         self.register_backup::<Aegis>(&["application/json"]);
-        self.register_backup::<AndOTP>(&["application/json"]);
-
+        self.register_backup::<AndOTP>(&["application/json", "application/octet-stream"]);
+        self.register_backup::<Google>(&[]);
+        imp.backup_accounts_page.set_model(&self.model());
+        let accounts = gtk::FlattenListModel::new(Some(gtk::MapListModel::new(
+            Some(self.model()),
+            |provider| {
+                provider
+                    .downcast_ref::<Provider>()
+                    .unwrap()
+                    .accounts_model()
+                    .upcast()
+            },
+        )));
+        accounts.connect_items_changed(clone!(@weak self as win => move |_, _, _, _| {
+            win.update_backup_accounts();
+        }));
+        imp.accounts.set(accounts).unwrap();
+        self.update_backup_accounts();
+
+        self.register_restore::<Authenticator>(&["application/json"]);
         self.register_restore::<FreeOTP>(&["text/plain"]);
-        self.register_restore::<FreeOTPJSON>(&["application/json"]);
+        self.register_restore::<TwoFAS>(&["application/json", "application/octet-stream"]);
         self.register_restore::<Aegis>(&["application/json"]);
-        self.register_restore::<AndOTP>(&["application/json"]);
+        self.register_restore::<AndOTP>(&["application/json", "application/octet-stream"]);
         self.register_restore::<Bitwarden>(&["application/json"]);
+        self.register_restore::<Ente>(&["text/plain"]);
+        self.register_restore::<FreeOTPEncrypted>(&["application/xml", "application/octet-stream"]);
+        self.register_restore::<FreeOTPJSON>(&["application/json"]);
         self.register_restore::<Google>(&[]);
+        self.register_restore::<Raivo>(&["application/zip", "application/json"]);
         self.register_restore::<LegacyAuthenticator>(&["application/json"]);
     }
 
@@ -225,6 +342,13 @@ impl PreferencesWindow {
 
         let action = gio::ActionEntry::builder(T::IDENTIFIER)
             .activate(clone!(@weak self as win => move |_, _,_| {
+                if T::SCANNABLE {
+                    if let Err(err) = win.backup_into_qr_codes::<T>() {
+                        tracing::error!("Failed to backup into QR codes {err}");
+                        win.add_toast(adw::Toast::new(&gettext("Failed to create a backup")));
+                    }
+                    return;
+                }
                 spawn(clone!(@weak win => async move {
                     if let Err(err) = win.backup_into_file::<T>(filters).await {
                         tracing::error!("Failed to backup into a file {err}");
@@ -236,13 +360,239 @@ impl PreferencesWindow {
         imp.backup_actions.add_action_entries([action]);
     }
 
+    fn setup_auto_backup(&self) {
+        let imp = self.imp();
+
+        SETTINGS
+            .bind("auto-backup", &*imp.auto_backup, "active")
+            .build();
+        SETTINGS
+            .bind(
+                "auto-backup-on-change",
+                &*imp.auto_backup_on_change,
+                "active",
+            )
+            .build();
+        SETTINGS
+            .bind("auto-backup-interval", &*imp.auto_backup_interval, "value")
+            .build();
+        SETTINGS
+            .bind("auto-backup-keep", &*imp.auto_backup_keep, "value")
+            .build();
+
+        let formats = auto::Format::all();
+        let titles = formats.iter().map(auto::Format::title).collect::<Vec<_>>();
+        let titles = titles.iter().map(String::as_str).collect::<Vec<_>>();
+        imp.auto_backup_format_row
+            .set_model(Some(&gtk::StringList::new(&titles)));
+        let identifier = SETTINGS.auto_backup_format();
+        if let Some(position) = formats
+            .iter()
+            .position(|format| format.identifier == identifier)
+        {
+            imp.auto_backup_format_row.set_selected(position as u32);
+        }
+        imp.auto_backup_format_row
+            .connect_selected_notify(move |row| {
+                if let Some(format) = formats.get(row.selected() as usize) {
+                    if let Err(err) = SETTINGS.set_auto_backup_format(format.identifier) {
+                        tracing::error!("Failed to save the automatic backups format {err}");
+                    }
+                }
+            });
+
+        imp.auto_backup_password_row
+            .connect_apply(clone!(@weak self as win => move |row| {
+                let password = row.text();
+                row.set_text("");
+                if password.is_empty() {
+                    win.add_toast(adw::Toast::new(&gettext("The passphrase cannot be empty")));
+                    return;
+                }
+                spawn(clone!(@weak win => async move {
+                    let result = spawn_tokio(async move {
+                        keyring::store_backup_password(&password).await
+                    })
+                    .await;
+                    let message = match result {
+                        Ok(_) => gettext("Automatic backups passphrase saved"),
+                        Err(err) => {
+                            tracing::error!("Failed to save the automatic backups passphrase {err}");
+                            gettext("Failed to save the passphrase")
+                        }
+                    };
+                    win.add_toast(adw::Toast::new(&message));
+                }));
+            }));
+
+        self.update_auto_backup_directory();
+        SETTINGS.connect_changed(
+            Some("auto-backup-directory"),
+            clone!(@weak self as win => move |_, _| {
+                win.update_auto_backup_directory();
+            }),
+        );
+    }
+
+    async fn set_secrets_encrypted(&self, encrypted: bool) {
+        let result = if encrypted {
+            match self.ask_password().await {
+                Some(password) => {
+                    spawn_tokio(async move {
+                        anyhow::ensure!(
+                            keyring::is_current_password(&password).await?,
+                            "Wrong passphrase"
+                        );
+                        keyring::enable_vault(&password).await
+                    })
+                    .await
+                }
+                None => Ok(()),
+            }
+        } else {
+            spawn_tokio(keyring::disable_vault()).await
+        };
+        if let Err(err) = result {
+            tracing::error!("Failed to change the secrets encryption {err}");
+            self.add_toast(adw::Toast::new(&gettext("Failed to update the secrets")));
+        }
+        self.imp()
+            .encrypt_secrets
+            .set_active(keyring::is_vault_enabled());
+    }
+
+    fn update_encrypt_secrets(&self) {
+        let row = &self.imp().encrypt_secrets;
+        row.set_active(keyring::is_vault_enabled());
+        // Without a keyring, the file storing the secrets is as good as
+        // plaintext unless they are encrypted with the passphrase
+        if keyring::requires_vault() {
+            row.set_sensitive(false);
+            row.set_subtitle(&gettext(
+                "Required as there is no keyring, the secrets are stored in a file only protected by the passphrase",
+            ));
+        }
+    }
+
+    /// Ask for the application passphrase, `None` if cancelled.
+    async fn ask_password(&self) -> Option<Zeroizing<String>> {
+        let entry = adw::PasswordEntryRow::builder()
+            .title(gettext("Passphrase"))
+            .activates_default(true)
+            .build();
+        let list = gtk::ListBox::builder()
+            .selection_mode(gtk::SelectionMode::None)
+            .css_classes(["boxed-list"])
+            .build();
+        list.append(&entry);
+        let dialog = adw::MessageDialog::builder()
+            .heading(gettext("Encrypt the Secrets?"))
+            .body(gettext(
+                "The secrets of the accounts will only be readable once the application is unlocked.",
+            ))
+            .extra_child(&list)
+            .default_response("encrypt")
+            .close_response("cancel")
+            .modal(true)
+            .transient_for(self)
+            .build();
+        dialog.add_responses(&[
+            ("cancel", &gettext("_Cancel")),
+            ("encrypt", &gettext("_Encrypt")),
+        ]);
+        dialog.set_response_appearance("encrypt", adw::ResponseAppearance::Suggested);
+        match dialog.choose_future().await.as_str() {
+            "encrypt" => Some(Zeroizing::new(entry.text().to_string())),
+            _ => None,
+        }
+    }
+
+    fn update_auto_backup_directory(&self) {
+        let directory = SETTINGS.auto_backup_directory();
+        let subtitle = if directory.is_empty() {
+            gettext("No folder selected")
+        } else {
+            directory
+        };
+        self.imp()
+            .auto_backup_directory_row
+            .set_subtitle(&glib::markup_escape_text(&subtitle));
+    }
+
+    async fn select_auto_backup_directory(&self) -> Result<()> {
+        let dialog = gtk::FileDialog::builder()
+            .modal(true)
+            .title(gettext("Automatic Backups Folder"))
+            .build();
+        let folder = dialog.select_folder_future(Some(self)).await?;
+        let path = folder
+            .path()
+            .ok_or_else(|| anyhow::anyhow!("The selected folder is not a local one"))?;
+        SETTINGS.set_auto_backup_directory(&path.to_string_lossy())?;
+        Ok(())
+    }
+
+    /// Describe the accounts to back up, the backups are disabled if there
+    /// are none.
+    fn update_backup_accounts(&self) {
+        let imp = self.imp();
+        let model = self.model();
+        let selection = imp.backup_accounts_page.selection();
+        let count = |selection: &Selection| {
+            selection
+                .accounts(&model)
+                .iter()
+                .map(|(_, accounts)| accounts.len())
+                .sum::<usize>()
+        };
+        let (n_selected, n_accounts) = (count(&selection), count(&Selection::All));
+
+        let subtitle = if selection == Selection::All {
+            gettext("All accounts")
+        } else {
+            i18n::i18n_nf(
+                // Translators: The number of accounts to back up, e.g. 3 of 10 accounts
+                "{} of {} account",
+                "{} of {} accounts",
+                n_accounts as u32,
+                &[&n_selected.to_string(), &n_accounts.to_string()],
+            )
+        };
+        imp.backup_accounts_row.set_subtitle(&subtitle);
+
+        for name in imp.backup_actions.list_actions() {
+            if let Some(action) = imp
+                .backup_actions
+                .lookup_action(&name)
+                .and_downcast::<gio::SimpleAction>()
+            {
+                action.set_enabled(n_selected > 0);
+            }
+        }
+    }
+
+    fn backup_into_qr_codes<T: Backupable>(&self) -> Result<()> {
+        let imp = self.imp();
+        let selection = imp.backup_accounts_page.selection();
+        let content = Zeroizing::new(String::from_utf8(T::backup(
+            &self.model(),
+            &selection,
+            None,
+        )?)?);
+        let codes = content.lines().collect::<Vec<_>>();
+        imp.qr_codes_page.set_codes(&codes);
+        self.push_subpage(&imp.qr_codes_page);
+        Ok(())
+    }
+
     async fn backup_into_file<T: Backupable>(&self, filters: &'static [&str]) -> Result<()> {
         let model = self.model();
         let file = self.select_file(filters, Operation::Backup).await?;
         let key = T::ENCRYPTABLE
             .then(|| self.encryption_key(Operation::Backup, T::IDENTIFIER))
             .flatten();
-        let content = T::backup(&model, key.as_deref())?;
+        let selection = self.imp().backup_accounts_page.selection();
+        let content = T::backup(&model, &selection, key.as_deref())?;
         file.replace_contents_future(
             content,
             None,
@@ -383,7 +733,7 @@ impl PreferencesWindow {
             imp.restore_actions.add_action_entries([action]);
         };
     }
-    async fn restore_from_file<T: Restorable<Item = Q>, Q: RestorableItem>(
+    async fn restore_from_file<T: Restorable<Item = Q>, Q: RestorableItem + 'static>(
         &self,
         filters: &'static [&str],
     ) -> Result<()> {
@@ -397,15 +747,49 @@ impl PreferencesWindow {
         Ok(())
     }
 
-    async fn restore_from_camera<T: Restorable<Item = Q>, Q: RestorableItem>(&self) -> Result<()> {
-        let code = self.imp().camera_page.scan_from_camera().await?;
-        let items = T::restore_from_data(code.as_bytes(), None)?;
-        self.restore_items::<T, T::Item>(items);
+    async fn restore_from_camera<T: Restorable<Item = Q>, Q: RestorableItem + 'static>(
+        &self,
+    ) -> Result<()> {
+        let camera_page = &self.imp().camera_page;
+        let mut batches = ScannedBatches::default();
+        camera_page
+            .scan_many_from_camera(|code| {
+                let batch = match T::batch(code.as_bytes()) {
+                    Ok(batch) => batch,
+                    Err(err) => {
+                        tracing::warn!("Ignoring scanned QR code {err}");
+                        return false;
+                    }
+                };
+                if batches.add(batch, code) && batches.size() > 1 {
+                    camera_page.set_progress(Some(&i18n::i18n_f(
+                        // Translators: The progress of scanning a backup made of several QR codes
+                        "Scanned {} of {} QR codes",
+                        &[
+                            &batches.n_scanned().to_string(),
+                            &batches.size().to_string(),
+                        ],
+                    )));
+                }
+                batches.is_complete()
+            })
+            .await;
+        // The page was closed before scanning every QR code
+        if !batches.is_complete() {
+            return Ok(());
+        }
+
+        let mut items = Vec::new();
+        for code in batches.into_codes() {
+            items.extend(T::restore_from_data(code.as_bytes(), None)?);
+        }
+        // Replace the camera page with the restore preview
         self.imp().actions.activate_action("close_page", None);
+        self.restore_items::<T, T::Item>(items);
         Ok(())
     }
 
-    async fn restore_from_screenshot<T: Restorable<Item = Q>, Q: RestorableItem>(
+    async fn restore_from_screenshot<T: Restorable<Item = Q>, Q: RestorableItem + 'static>(
         &self,
     ) -> Result<()> {
         let code = self.imp().camera_page.scan_from_screenshot().await?;
@@ -414,7 +798,9 @@ impl PreferencesWindow {
         Ok(())
     }
 
-    async fn restore_from_image<T: Restorable<Item = Q>, Q: RestorableItem>(&self) -> Result<()> {
+    async fn restore_from_image<T: Restorable<Item = Q>, Q: RestorableItem + 'static>(
+        &self,
+    ) -> Result<()> {
         let images_filter = gtk::FileFilter::new();
         images_filter.set_name(Some(&gettext("Image")));
         images_filter.add_pixbuf_formats();
@@ -444,20 +830,17 @@ impl PreferencesWindow {
             .borrow()
             .get(&identifier)
             .map(|entry| entry.text())
+            .filter(|key| !key.is_empty())
     }
 
-    fn restore_items<T: Restorable<Item = Q>, Q: RestorableItem>(&self, items: Vec<Q>) {
-        let model = self.model();
-        items
-            .iter()
-            .map(move |item| item.restore(&model))
-            .for_each(|item| {
-                if let Err(err) = item {
-                    tracing::warn!("Failed to restore item {}", err);
-                }
-            });
-        self.emit_by_name::<()>("restore-completed", &[]);
-        self.close();
+    fn restore_items<T: Restorable<Item = Q>, Q: RestorableItem + 'static>(&self, items: Vec<Q>) {
+        let imp = self.imp();
+        let items = items
+            .into_iter()
+            .map(|item| Box::new(item) as Box<dyn RestorableItem>)
+            .collect();
+        imp.restore_page.set_items(&self.model(), items);
+        self.push_subpage(&imp.restore_page);
     }
 
     async fn select_file(
@@ -496,6 +879,21 @@ impl PreferencesWindow {
     fn setup_actions(&self) {
         let imp = self.imp();
 
+        imp.backup_accounts_page
+            .connect_map(clone!(@weak self as win => move |_| {
+                win.set_search_enabled(false);
+            }));
+
+        imp.backup_accounts_page
+            .connect_unmap(clone!(@weak self as win => move |_| {
+                win.set_search_enabled(true);
+            }));
+
+        imp.backup_accounts_page
+            .connect_selection_changed(clone!(@weak self as win => move |_| {
+                win.update_backup_accounts();
+            }));
+
         imp.camera_page
             .connect_map(clone!(@weak self as win => move |_| {
                 win.set_search_enabled(false);
@@ -516,18 +914,69 @@ impl PreferencesWindow {
                 win.set_search_enabled(true);
             }));
 
+        imp.qr_codes_page
+            .connect_map(clone!(@weak self as win => move |_| {
+                win.set_search_enabled(false);
+            }));
+
+        imp.qr_codes_page
+            .connect_unmap(clone!(@weak self as win => move |_| {
+                win.set_search_enabled(true);
+            }));
+
+        imp.restore_page
+            .connect_map(clone!(@weak self as win => move |_| {
+                win.set_search_enabled(false);
+            }));
+
+        imp.restore_page
+            .connect_unmap(clone!(@weak self as win => move |_| {
+                win.set_search_enabled(true);
+            }));
+
+        imp.restore_page
+            .connect_restored(clone!(@weak self as win => move |_| {
+                win.emit_by_name::<()>("restore-completed", &[]);
+            }));
+
         let show_camera_page = gio::ActionEntry::builder("show_camera_page")
             .activate(clone!(@weak self as win => move |_, _, _| {
                 win.push_subpage(&win.imp().camera_page);
             }))
             .build();
 
+        let show_backup_accounts_page = gio::ActionEntry::builder("show_backup_accounts_page")
+            .activate(clone!(@weak self as win => move |_, _, _| {
+                win.push_subpage(&win.imp().backup_accounts_page);
+            }))
+            .build();
+
         let show_password_page = gio::ActionEntry::builder("show_password_page")
             .activate(clone!(@weak self as win => move |_, _, _| {
                 win.push_subpage(&win.imp().password_page);
             }))
             .build();
 
+        let select_auto_backup_directory =
+            gio::ActionEntry::builder("select_auto_backup_directory")
+                .activate(clone!(@weak self as win => move |_, _, _| {
+                    spawn(clone!(@weak win => async move {
+                        if let Err(err) = win.select_auto_backup_directory().await {
+                            tracing::error!("Failed to select the automatic backups folder {err}");
+                        }
+                    }));
+                }))
+                .build();
+
+        let reset_codes_access = gio::ActionEntry::builder("reset_codes_access")
+            .activate(clone!(@weak self as win => move |_, _, _| {
+                SETTINGS.reset_codes_access();
+                win.add_toast(adw::Toast::new(&gettext(
+                    "Applications will have to ask again to access the codes",
+                )));
+            }))
+            .build();
+
         let close_page = gio::ActionEntry::builder("close_page")
             .activate(clone!(@weak self as win => move |_, _, _| {
                 win.pop_subpage();
@@ -535,8 +984,27 @@ impl PreferencesWindow {
             }))
             .build();
 
-        imp.actions
-            .add_action_entries([show_camera_page, show_password_page, close_page]);
+        imp.actions.add_action_entries([
+            show_backup_accounts_page,
+            show_camera_page,
+            show_password_page,
+            select_auto_backup_directory,
+            reset_codes_access,
+            close_page,
+        ]);
+
+        let reset_codes_access = imp
+            .actions
+            .lookup_action("reset_codes_access")
+            .and_downcast::<gio::SimpleAction>()
+            .unwrap();
+        reset_codes_access.set_enabled(!SETTINGS.codes_access().is_empty());
+        SETTINGS.connect_changed(
+            Some("codes-access"),
+            clone!(@weak reset_codes_access => move |_, _| {
+                reset_codes_access.set_enabled(!SETTINGS.codes_access().is_empty());
+            }),
+        );
 
         self.insert_action_group("preferences", Some(&imp.actions));
         self.insert_action_group("backup", Some(&imp.backup_actions));
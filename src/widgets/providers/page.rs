@@ -380,14 +380,33 @@ impl ProviderPage {
                 imp.algorithm_comborow
                     .set_selected(Algorithm::default().into_glib() as u32);
             }
+            Method::Yandex => {
+                imp.default_counter_spinbutton.set_visible(false);
+                imp.period_spinbutton.set_visible(true);
+                imp.digits_spinbutton
+                    .set_value(OTP::YANDEX_DEFAULT_DIGITS as f64);
+                imp.period_spinbutton
+                    .set_value(OTP::YANDEX_DEFAULT_PERIOD as f64);
+                imp.algorithm_comborow
+                    .set_selected(Algorithm::SHA256.into_glib() as u32);
+            }
+            Method::MOTP => {
+                imp.default_counter_spinbutton.set_visible(false);
+                imp.period_spinbutton.set_visible(true);
+                imp.digits_spinbutton
+                    .set_value(OTP::MOTP_DEFAULT_DIGITS as f64);
+                imp.period_spinbutton
+                    .set_value(OTP::MOTP_DEFAULT_PERIOD as f64);
+                imp.algorithm_comborow
+                    .set_selected(Algorithm::default().into_glib() as u32);
+            }
         }
 
-        imp.algorithm_comborow
-            .set_sensitive(selected != Method::Steam);
-        imp.period_spinbutton
-            .set_sensitive(selected != Method::Steam);
-        imp.digits_spinbutton
-            .set_sensitive(selected != Method::Steam);
+        // These methods have a fixed algorithm, period and number of digits
+        let is_customizable = matches!(selected, Method::TOTP | Method::HOTP);
+        imp.algorithm_comborow.set_sensitive(is_customizable);
+        imp.period_spinbutton.set_sensitive(is_customizable);
+        imp.digits_spinbutton.set_sensitive(is_customizable);
     }
 }
 
@@ -116,7 +116,7 @@ impl ProviderRow {
 
     fn tick_progressbar(&self) {
         let imp = self.imp();
-        let period_millis = self.provider().period() as u128 * 1000;
+        let period_millis = self.provider().step_period() as u128 * 1000;
         let now: u128 = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -98,12 +98,20 @@ mod imp {
                 let app = win.app();
                 let password = imp.password_entry.text();
                 let is_current_password = spawn_tokio_blocking(async move {
-                    keyring::is_current_password(&password)
+                    let is_current_password = keyring::is_current_password(&password)
                         .await
                         .unwrap_or_else(|err| {
                             tracing::debug!("Could not verify password: {:?}", err);
                             false
-                        })
+                        });
+                    // The secrets can only be decrypted once the vault is unlocked
+                    if is_current_password {
+                        if let Err(err) = keyring::unlock_vault(&password).await {
+                            tracing::error!("Failed to unlock the vault {err}");
+                            return false;
+                        }
+                    }
+                    is_current_password
                 });
                 if is_current_password {
                     imp.password_entry.set_text("");